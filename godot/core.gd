@@ -6,9 +6,12 @@ enum ServerProcessState {NOT_RUNNING, RUNNING}
 enum PlaySoloMode {CREATION, JOIN}
 
 var socket = WebSocketPeer.new()
+const PROTOCOL_VERSION = 1
+
 var login_hash = {
 	"Login": {
-		"nickname": ""
+		"nickname": "",
+		"version": PROTOCOL_VERSION
 	}
 }
 var login = ""
@@ -29,6 +32,7 @@ var instantiate_timer = 0
 var instantiate_limit = 0.01
 
 var bodies_infos = {}
+var galaxy_map = []
 
 @onready var asteroid_scene = load("res://scenes/asteroid.tscn")
 @onready var planet_scene = load("res://scenes/planet.tscn")
@@ -156,6 +160,12 @@ func _process(delta: float) -> void:
 				elif galactic_to_instantiate.type == "Player":
 					galactic_tree = player_scene.instantiate()
 					color = Color(0, 1, 0)
+				elif galactic_to_instantiate.type == "Npc":
+					galactic_tree = player_scene.instantiate()
+					color = Color(1, 0, 1)
+				elif galactic_to_instantiate.type == "Structure":
+					galactic_tree = moon_scene.instantiate()
+					color = Color(1, 0.5, 0)
 				else:
 					assert(false)
 				var model = galactic_tree.get_child(0)
@@ -294,12 +304,15 @@ func handle_network(delta):
 		elif network_state == NetworkState.AUTHENTICATING:
 			if socket.get_available_packet_count():
 				var variant = JSON.parse_string(socket.get_packet().get_string_from_utf8())
-				if variant["success"] == false:
-					print("Login failure: %s" % variant["message"])
-					ui.error_placeholder.set_text("Authentication failed: %s" % variant["message"])
+				if typeof(variant) != TYPE_DICTIONARY || !variant.has("Ok"):
+					var reason = variant if typeof(variant) == TYPE_STRING else JSON.stringify(variant)
+					print("Login failure: %s" % reason)
+					ui.error_placeholder.set_text("Authentication failed: %s" % reason)
 					leave()
 				else:
-					print("Login success, id is %s" % variant["message"])
+					print("Login success, id is %d" % int(variant["Ok"]["body_id"]))
+					if socket.send_text(JSON.stringify("RequestMap")) != OK:
+						print("Send error")
 					new_network_state = NetworkState.WAITING_GAMEINFO
 					if server_process_state == ServerProcessState.RUNNING:
 						new_state = State.PLAYING_SOLO
@@ -342,6 +355,20 @@ func handle_network(delta):
 								#"type": "Player",
 								#"coords": Vector3(element["coords"][0], element["coords"][1], element["coords"][2])})
 
+				elif variant.has("Map"):
+					galaxy_map = variant["Map"]["systems"] as Array
+					for system in galaxy_map:
+						var star_id = int(system["star"])
+						if container.get_node_or_null(str(star_id)) || to_instantiate.any(func(queued): return queued.id == star_id):
+							continue
+						to_instantiate.push_back({
+							"id": star_id,
+							"type": "Star",
+							"coords": Vector3(system["coords"][0], system["coords"][1], system["coords"][2]),
+							"gravity_center": -1,
+							"rotating_speed": 0,
+							})
+
 				elif variant.has("BodiesInSystem"):
 					var elements = variant["BodiesInSystem"] as Array
 					
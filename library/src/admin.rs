@@ -0,0 +1,90 @@
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::AUTHORIZATION;
+use hyper::{Method, Request, Response, StatusCode};
+
+use crate::error::Error;
+use crate::registry::Registry;
+
+pub const ADMIN_PREFIX: &str = "/admin/";
+
+fn respond(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::<Bytes>::new(body.into()));
+    *response.status_mut() = status;
+    response
+}
+
+fn authorized<B>(request: &Request<B>, token: &str) -> bool {
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|bearer| bearer == token)
+}
+
+/// Instances management, only served when the registry has an admin token:
+///
+/// - `GET /admin/instances` lists the hosted instances
+/// - `POST /admin/instances/<name>/create` hosts a new instance
+/// - `POST /admin/instances/<name>/load` hosts a saved instance
+/// - `POST /admin/instances/<name>/unload` saves and stops an instance
+/// - `GET /admin/instances/<name>/snapshots` lists the snapshots of an instance
+/// - `POST /admin/instances/<name>/snapshots` snapshots an instance now
+/// - `POST /admin/instances/<name>/rollback/<id>` brings an instance back to
+///   one of its snapshots
+///
+/// Listing and snapshotting answer with the snapshots concerned, the other
+/// routes with the hosted instances.
+pub async fn serve_admin<B>(request: &Request<B>, registry: &Registry) -> Response<Full<Bytes>> {
+    let Some(token) = registry.admin_token() else {
+        return respond(StatusCode::NOT_FOUND, String::new());
+    };
+    if !authorized(request, token) {
+        return respond(StatusCode::UNAUTHORIZED, String::new());
+    }
+
+    let route = request
+        .uri()
+        .path()
+        .strip_prefix(ADMIN_PREFIX)
+        .unwrap_or_default();
+    let segments: Vec<&str> = route.split('/').collect();
+
+    let result = match (request.method(), segments.as_slice()) {
+        (&Method::GET, ["instances"]) => Ok(None),
+        (&Method::POST, ["instances", name, "create"]) => registry.create(name).await.map(|_| None),
+        (&Method::POST, ["instances", name, "load"]) => registry.load(name).await.map(|_| None),
+        (&Method::POST, ["instances", name, "unload"]) => registry.unload(name).await.map(|_| None),
+        (&Method::GET, ["instances", name, "snapshots"]) => registry
+            .snapshots(name)
+            .map(|snapshots| Some(serde_json::to_string(&snapshots).unwrap())),
+        (&Method::POST, ["instances", name, "snapshots"]) => registry
+            .snapshot(name)
+            .await
+            .map(|snapshot| Some(serde_json::to_string(&[snapshot]).unwrap())),
+        (&Method::POST, ["instances", name, "rollback", id]) => match id.parse() {
+            Ok(id) => registry.rollback(name, id).await.map(|_| None),
+            Err(_) => Err(Error::SnapshotNotFound(id.to_string())),
+        },
+        _ => return respond(StatusCode::NOT_FOUND, String::new()),
+    };
+
+    match result {
+        Ok(Some(body)) => respond(StatusCode::OK, body),
+        Ok(None) => respond(
+            StatusCode::OK,
+            serde_json::to_string(&registry.list().await).unwrap(),
+        ),
+        Err(err) => {
+            log::info!("Admin request {} refused: {}", request.uri(), err);
+            let status = match err {
+                Error::InvalidInstanceName(_) => StatusCode::BAD_REQUEST,
+                Error::InstanceNotFound(_) | Error::SnapshotNotFound(_) => StatusCode::NOT_FOUND,
+                Error::InstanceAlreadyExists(_) => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            respond(status, err.to_string())
+        }
+    }
+}
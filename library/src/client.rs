@@ -1,5 +1,3 @@
-use std::str::FromStr;
-
 use futures::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
 
@@ -7,14 +5,20 @@ use tokio_rustls::client::TlsStream;
 use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
 
 use crate::error::Error;
+use crate::game::combat::Weapon;
+use crate::game::entity::structure::StructureKind;
 use crate::game::repr::Vector3;
 use crate::network::tcp::{connect, ClientStream};
 use crate::network::tls::ClientPki;
 
-use crate::protocol::{GameInfo, PlayerInfo, ShipState};
+use crate::protocol::{
+    Build, Chat, ChatChannel, ChatInfo, Claim, Fire, FireTarget, GameInfo, HitInfo, InventoryInfo,
+    KillInfo, MapInfo, Mine, PlayerInfo, ShipState, Spectate, SpectateTarget, Trade, TradeInfo,
+};
+use crate::registry::DEFAULT_INSTANCE;
 use crate::Id;
 use crate::{
-    protocol::{AuthInfo, Login, PlayerAction},
+    protocol::{Login, LoginResult, PlayerAction, PROTOCOL_VERSION},
     Result,
 };
 
@@ -30,13 +34,13 @@ impl WebSocketStream {
                 stream
                     .send(login_json)
                     .await
-                    .map_err(|err| Error::WsCantSend(err))?;
+                    .map_err(|err| Error::WsCantSend(Box::new(err)))?;
             }
             Self::Tls(stream) => {
                 stream
                     .send(login_json)
                     .await
-                    .map_err(|err| Error::WsCantSend(err))?;
+                    .map_err(|err| Error::WsCantSend(Box::new(err)))?;
             }
         }
         Ok(())
@@ -62,13 +66,13 @@ impl Client {
                 stream
                     .close(None)
                     .await
-                    .map_err(|err| Error::GracefulCloseError(err))?;
+                    .map_err(|err| Error::GracefulCloseError(Box::new(err)))?;
             }
             WebSocketStream::Tls(stream) => {
                 stream
                     .close(None)
                     .await
-                    .map_err(|err| Error::GracefulCloseError(err))?;
+                    .map_err(|err| Error::GracefulCloseError(Box::new(err)))?;
             }
         }
         Ok(())
@@ -110,9 +114,53 @@ impl Client {
     }
 
     pub async fn login(&mut self, nickname: &str) -> Result<Id> {
-        let login = PlayerAction::Login(Login {
+        self.send_login(PlayerAction::Login(Login {
+            nickname: nickname.to_string(),
+            version: PROTOCOL_VERSION,
+            instance: None,
+        }))
+        .await
+    }
+
+    /// Logs in one of the named instances of the server.
+    pub async fn login_to_instance(&mut self, instance: &str, nickname: &str) -> Result<Id> {
+        self.send_login(PlayerAction::Login(Login {
             nickname: nickname.to_string(),
-        });
+            version: PROTOCOL_VERSION,
+            instance: Some(instance.to_string()),
+        }))
+        .await
+    }
+
+    /// Logs in as a read-only spectator and returns its id.
+    pub async fn spectate(&mut self, follow: SpectateTarget, range: f64) -> Result<Id> {
+        self.send_login(PlayerAction::Spectate(Spectate {
+            version: PROTOCOL_VERSION,
+            follow,
+            range,
+            instance: None,
+        }))
+        .await
+    }
+
+    /// Changes what an already spectating client watches.
+    pub async fn follow(&mut self, follow: SpectateTarget, range: f64) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Spectate(Spectate {
+                    version: PROTOCOL_VERSION,
+                    follow,
+                    range,
+                    instance: None,
+                }))
+                .unwrap()
+                .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    async fn send_login(&mut self, login: PlayerAction) -> Result<Id> {
         let login_json =
             serde_json::to_string(&login).map_err(|err| Error::FailedToSerializeLogin(err))?;
 
@@ -122,16 +170,35 @@ impl Client {
 
         match response {
             Message::Text(response_str) => {
-                let login_info: AuthInfo = serde_json::from_str(&response_str).map_err(|err| {
-                    Error::DeserializeAuthenticationResponseError(err, response_str.to_string())
-                })?;
-
-                let uuid = Id::from_str(login_info.message.as_str())
-                    .map_err(|_err| Error::BadUuidError(login_info.message))?;
+                if let Ok(GameInfo::Error(info)) = serde_json::from_str(&response_str) {
+                    return Err(Error::LoginRefused(info.message));
+                }
+                let login_result: LoginResult =
+                    serde_json::from_str(&response_str).map_err(|err| {
+                        Error::DeserializeAuthenticationResponseError(err, response_str.to_string())
+                    })?;
 
-                return Ok(uuid);
+                match login_result {
+                    LoginResult::Ok { body_id, .. } => Ok(body_id),
+                    LoginResult::Spectating { spectator_id, .. } => Ok(spectator_id),
+                    LoginResult::InvalidNickname => Err(Error::InvalidNickname),
+                    LoginResult::AlreadyConnected => Err(Error::PlayerAlreadyAuthenticated),
+                    LoginResult::ServerFull => Err(Error::ServerFull),
+                    LoginResult::Redirect { address } => Err(Error::PlayerHandedOff(address)),
+                    LoginResult::UnknownInstance => Err(Error::InstanceNotFound(
+                        match login {
+                            PlayerAction::Login(Login { instance, .. })
+                            | PlayerAction::Spectate(Spectate { instance, .. }) => instance,
+                            _ => None,
+                        }
+                        .unwrap_or_else(|| DEFAULT_INSTANCE.to_string()),
+                    )),
+                    LoginResult::VersionMismatch { server_version } => {
+                        Err(Error::VersionMismatch(PROTOCOL_VERSION, server_version))
+                    }
+                }
             }
-            _ => return Err(Error::UnexpectedResponse(format!("{:?}", response))),
+            _ => Err(Error::UnexpectedResponse(format!("{:?}", response))),
         }
     }
 
@@ -149,6 +216,89 @@ impl Client {
         Ok(())
     }
 
+    pub async fn chat(&mut self, channel: ChatChannel, message: &str) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Chat(Chat {
+                    channel,
+                    message: message.to_string(),
+                }))
+                .unwrap()
+                .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn claim(&mut self, target: Id) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Claim(Claim { target }))
+                    .unwrap()
+                    .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn mine(&mut self, target: Id) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Mine(Mine { target }))
+                    .unwrap()
+                    .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn build(&mut self, kind: StructureKind, anchor: Id) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Build(Build { kind, anchor }))
+                    .unwrap()
+                    .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn trade(&mut self, trade: Trade) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Trade(trade))
+                    .unwrap()
+                    .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn fire(&mut self, weapon: Weapon, target_or_direction: FireTarget) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::Fire(Fire {
+                    weapon,
+                    target_or_direction,
+                }))
+                .unwrap()
+                .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn request_map(&mut self) -> Result<()> {
+        self.stream
+            .send(Message::Text(
+                serde_json::to_string(&PlayerAction::RequestMap)
+                    .unwrap()
+                    .into(),
+            ))
+            .await?;
+        Ok(())
+    }
+
     pub async fn next_game_info(&mut self) -> Result<GameInfo> {
         let next = self.stream.next().await?;
 
@@ -173,4 +323,64 @@ impl Client {
             }
         }
     }
+
+    pub async fn until_inventory_info(&mut self) -> Result<InventoryInfo> {
+        loop {
+            let game_info = self.next_game_info().await?;
+
+            if let GameInfo::Inventory(inventory) = game_info {
+                return Ok(inventory);
+            }
+        }
+    }
+
+    pub async fn until_trade_info(&mut self) -> Result<TradeInfo> {
+        loop {
+            let game_info = self.next_game_info().await?;
+
+            if let GameInfo::Trade(trade) = game_info {
+                return Ok(trade);
+            }
+        }
+    }
+
+    pub async fn until_hit_info(&mut self) -> Result<HitInfo> {
+        loop {
+            let game_info = self.next_game_info().await?;
+
+            if let GameInfo::Hit(hit) = game_info {
+                return Ok(hit);
+            }
+        }
+    }
+
+    pub async fn until_kill_info(&mut self) -> Result<KillInfo> {
+        loop {
+            let game_info = self.next_game_info().await?;
+
+            if let GameInfo::Kill(kill) = game_info {
+                return Ok(kill);
+            }
+        }
+    }
+
+    pub async fn until_map_info(&mut self) -> Result<MapInfo> {
+        loop {
+            let game_info = self.next_game_info().await?;
+
+            if let GameInfo::Map(map) = game_info {
+                return Ok(map);
+            }
+        }
+    }
+
+    pub async fn until_chat_info(&mut self) -> Result<ChatInfo> {
+        loop {
+            let game_info = self.next_game_info().await?;
+
+            if let GameInfo::Chat(chat) = game_info {
+                return Ok(chat);
+            }
+        }
+    }
 }
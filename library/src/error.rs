@@ -22,6 +22,10 @@ pub enum Error {
     SqlDbInsertError(String, sqlx::Error),
     #[error("Uuid not found in db: {0}")]
     DbUuidNotFound(Id),
+    #[error("Body {0} is a player, loaded by its nickname")]
+    BodyIsPlayer(Id),
+    #[error("Inconsistent rows in db for body {0}")]
+    InconsistentRow(Id),
     #[error("Gravity center not found")]
     GravityCenterNotFound,
     #[error("Invalid nickname")]
@@ -38,8 +42,18 @@ pub enum Error {
     DbSelectFromJoinedIdsError(String, String, String, sqlx::Error),
     #[error("Can't select in '{0}' with where clause '{1}': {2}")]
     DbSelectFromWhereError(String, String, sqlx::Error),
+    #[error("Galaxy file has schema version {0}, this server only knows up to {1}")]
+    DbSchemaTooNew(u32, u32),
+    #[error("Not a table or column of the schema: {0}")]
+    DbInvalidIdentifier(String),
+    #[error("Can't run database transaction: {0}")]
+    DbTransactionError(sqlx::Error),
     #[error("DB file creation error {0}")]
     DbFileCreationError(std::io::Error),
+    #[error("DB invalid structure kind: {0}")]
+    DbInvalidStructureKind(String),
+    #[error("DB invalid brain kind: {0}")]
+    DbInvalidBrainKind(String),
     #[error("DB invalid ID: {0}")]
     DbInvalidUuidError(Id),
     #[error("CRITICAL: found several ({0}) players with same nickname")]
@@ -52,6 +66,8 @@ pub enum Error {
     DbLoadError(sqlx::Error),
     #[error("Can't open DB {0}: {1}")]
     DbOpenError(String, sqlx::Error),
+    #[error("Can't copy DB into {0}: {1}")]
+    DbCopyError(String, sqlx::Error),
     #[error("Error while trying to deserialize authentication response from server {0} {1}")]
     DeserializeAuthenticationResponseError(serde_json::Error, String),
     #[error("Failed to serialize login")]
@@ -67,13 +83,89 @@ pub enum Error {
     #[error("Can't build tls config: {0}")]
     TlsConfigBuildError(rustls::Error),
     #[error("Websocket send: {0}")]
-    WsCantSend(tungstenite::Error),
+    WsCantSend(Box<tungstenite::Error>),
     #[error("Websocket read: {0}")]
-    WsCantRead(tungstenite::Error),
+    WsCantRead(Box<tungstenite::Error>),
     #[error("Unexpected response from server: {0}")]
     UnexpectedResponse(String),
-    #[error("Bad UUID in \"{0}\"")]
-    BadUuidError(String),
+    #[error("Server is full")]
+    ServerFull,
+    #[error("Player to spectate not found: {0}")]
+    SpectatedPlayerNotFound(String),
+    #[error("Login refused: {0}")]
+    LoginRefused(String),
+    #[error("Spectators can't act")]
+    SpectatorReadOnly,
+    #[error("Invalid instance name: {0}")]
+    InvalidInstanceName(String),
+    #[error("Instance not found: {0}")]
+    InstanceNotFound(String),
+    #[error("Instance already exists: {0}")]
+    InstanceAlreadyExists(String),
+    #[error("Instance loop failed: {0}")]
+    InstanceLoopError(String),
+    #[error("Can't load shard config {0}: {1}")]
+    ShardConfigError(String, String),
+    #[error("Handoff to {0} failed: {1}")]
+    HandoffError(String, String),
+    #[error("Handoff refused: {0}")]
+    HandoffRefused(String),
+    #[error("Player is now served by {0}")]
+    PlayerHandedOff(String),
+    #[error("Journal {0} failed: {1}")]
+    JournalError(String, String),
+    #[error("Galaxy export {0} failed: {1}")]
+    ExportError(String, String),
+    #[error("Galaxy export has format version {0}, this server only knows up to {1}")]
+    ExportTooNew(u32, u32),
+    #[error("Can't import into a galaxy which already has bodies")]
+    ImportIntoNonEmpty,
+    #[error("Snapshots in {0} failed: {1}")]
+    SnapshotError(String, String),
+    #[error("Snapshot not found: {0}")]
+    SnapshotNotFound(String),
+    #[error("Protocol version mismatch: client is {0}, server is {1}")]
+    VersionMismatch(u32, u32),
+    #[error("Invalid chat message")]
+    InvalidChatMessage,
+    #[error("Rate limit exceeded")]
+    RateLimited,
+    #[error("Chat rate limit exceeded")]
+    ChatRateLimited,
+    #[error("Chat recipient not found: {0}")]
+    ChatRecipientNotFound(String),
+    #[error("Body not found: {0}")]
+    BodyNotFound(Id),
+    #[error("Body {0} can't be claimed")]
+    NotClaimable(Id),
+    #[error("Body {0} is already owned")]
+    AlreadyOwned(Id),
+    #[error("Body {0} is too far")]
+    TooFar(Id),
+    #[error("Body {0} can't be mined")]
+    NotMineable(Id),
+    #[error("Cargo hold is full")]
+    CargoFull,
+    #[error("Not enough items")]
+    NotEnoughItems,
+    #[error("Trade offer not found: {0}")]
+    TradeNotFound(Id),
+    #[error("Too many pending trade offers")]
+    TooManyOffers,
+    #[error("Body {0} is not owned by the builder")]
+    NotOwned(Id),
+    #[error("Body {0} can't anchor more structures")]
+    TooManyStructures(Id),
+    #[error("Body {0} can't be fired at")]
+    InvalidTarget(Id),
+    #[error("Weapon is cooling down")]
+    WeaponCooldown,
+    #[error("Action refused by the game rules")]
+    ActionRefusedByScript,
+    #[error("Can't load scripts {0}: {1}")]
+    ScriptLoadError(String, String),
+    #[error("Invalid action: {0}")]
+    InvalidAction(String),
     #[error("Cannot close connection gracefully: {0}")]
-    GracefulCloseError(tungstenite::Error),
+    GracefulCloseError(Box<tungstenite::Error>),
 }
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::instance::Instance;
+use crate::Id;
+
+/// Events a subscriber may lag behind by before missing the next ones.
+pub const EVENTS_CHANNEL_SIZE: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    PlayerJoined {
+        body: Id,
+        player: Id,
+        nickname: String,
+        first_login: bool,
+    },
+    PlayerLeft {
+        body: Id,
+        player: Id,
+        nickname: String,
+    },
+    BodyCreated(Id),
+    BodyRemoved(Id),
+    BodyOwnerChanged {
+        body: Id,
+        previous: Id,
+        owner: Id,
+    },
+    Tick(f64),
+}
+
+/// Reacts to an event within the instance, before subscribers get it.
+pub type EventHandler = fn(&mut Instance, &Event);
+
+/// Gameplay events of an instance, dispatched in the order they were
+/// emitted even when handling one emits others.
+#[derive(Default)]
+pub struct EventBus {
+    queue: VecDeque<Event>,
+    dispatching: bool,
+    handlers: Vec<EventHandler>,
+    subscribers: Vec<Sender<Event>>,
+}
+
+impl EventBus {
+    /// Handlers are called in the order they were registered.
+    pub(crate) fn register(&mut self, handler: EventHandler) {
+        self.handlers.push(handler);
+    }
+
+    pub(crate) fn handler(&self, index: usize) -> Option<EventHandler> {
+        self.handlers.get(index).copied()
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (send, recv) = tokio::sync::mpsc::channel(EVENTS_CHANNEL_SIZE);
+        self.subscribers.push(send);
+        recv
+    }
+
+    pub fn subscribers_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Queues `event` and tells whether the caller has to dispatch the
+    /// queue, which is not the case while it is already being dispatched.
+    pub(crate) fn push(&mut self, event: Event) -> bool {
+        self.queue.push_back(event);
+        !std::mem::replace(&mut self.dispatching, true)
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Event> {
+        let event = self.queue.pop_front();
+        self.dispatching = event.is_some();
+        event
+    }
+
+    /// Hands `event` to the subscribers. Subscriptions whose receiver was
+    /// dropped are forgotten.
+    pub(crate) fn publish(&mut self, event: &Event) {
+        self.subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+        for subscriber in &self.subscribers {
+            let _ = subscriber.try_send(event.clone());
+        }
+    }
+}
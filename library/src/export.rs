@@ -0,0 +1,83 @@
+//! Galaxies as a single JSON document, whatever store they were kept in.
+//!
+//! The document is an object with:
+//! - `format_version`: [`EXPORT_FORMAT_VERSION`] when written by this server,
+//! - `generation_seed`: seed the systems were generated from,
+//! - `bodies`: every body by id, as journal [`BodyRecord`]s, players with
+//!   their inventory, ship and discoveries,
+//! - `chat`: the chat history, oldest first, with the star of the system it
+//!   was sent in.
+//!
+//! Ids are kept as they are, `4294967295` standing for none.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::game::celestial_body::CelestialBody;
+use crate::journal::BodyRecord;
+use crate::protocol::ChatInfo;
+use crate::{Id, Result};
+
+/// Version of the format this server writes.
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatRecord {
+    pub system: Id,
+    pub info: ChatInfo,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GalaxyExport {
+    pub format_version: u32,
+    pub generation_seed: u64,
+    pub bodies: Vec<BodyRecord>,
+    pub chat: Vec<ChatRecord>,
+}
+
+impl GalaxyExport {
+    pub fn new(
+        generation_seed: u64,
+        bodies: &[CelestialBody],
+        chat: Vec<(Id, ChatInfo)>,
+    ) -> GalaxyExport {
+        GalaxyExport {
+            format_version: EXPORT_FORMAT_VERSION,
+            generation_seed,
+            bodies: bodies.iter().map(BodyRecord::from_body).collect(),
+            chat: chat
+                .into_iter()
+                .map(|(system, info)| ChatRecord { system, info })
+                .collect(),
+        }
+    }
+
+    /// Writes the export to `path`, indented so that two exports diff line
+    /// by line.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let error = |err: String| Error::ExportError(path.to_string(), err);
+        let mut writer = BufWriter::new(File::create(path).map_err(|err| error(err.to_string()))?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(|err| error(err.to_string()))?;
+        writer
+            .write_all(b"\n")
+            .and_then(|_| writer.flush())
+            .map_err(|err| error(err.to_string()))
+    }
+
+    pub fn read(path: &str) -> Result<GalaxyExport> {
+        let error = |err: String| Error::ExportError(path.to_string(), err);
+        let reader = BufReader::new(File::open(path).map_err(|err| error(err.to_string()))?);
+        let export: GalaxyExport =
+            serde_json::from_reader(reader).map_err(|err| error(err.to_string()))?;
+        if export.format_version > EXPORT_FORMAT_VERSION {
+            return Err(Error::ExportTooNew(
+                export.format_version,
+                EXPORT_FORMAT_VERSION,
+            ));
+        }
+        Ok(export)
+    }
+}
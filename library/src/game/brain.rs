@@ -0,0 +1,190 @@
+use std::f64::consts::{PI, TAU};
+use std::fmt::Debug;
+
+use rand::Rng;
+use scilib::coordinate::spherical::Spherical;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::ShipState;
+use crate::Id;
+
+use super::celestial_body::CelestialBody;
+use super::entity::Entity;
+use super::mining::MINE_RANGE;
+use super::repr::Vector3;
+
+pub const NPC_SENSOR_RANGE: f64 = 2000f64;
+pub const NPC_SPEED: f64 = 50f64;
+pub const PATROL_RADIUS: f64 = 2000f64;
+pub const FLEE_RADIUS: f64 = 1000f64;
+
+/// Where an NPC joining the system around `center` is placed.
+pub fn spawn_coords(center: Vector3, rng: &mut impl Rng) -> Vector3 {
+    let phi = rng.gen_range(-TAU..TAU);
+    let theta = rng.gen_range(PI - 0.1..PI + 0.1);
+    let distance = rng.gen_range(1000f64..PATROL_RADIUS);
+    center + Vector3::from_coord(Spherical::from(distance, theta, phi))
+}
+
+/// What an NPC sees of the galaxy on a tick.
+pub struct Neighbourhood<'a> {
+    pub id: Id,
+    pub coords: Vector3,
+    pub gravity_center: Option<Vector3>,
+    pub bodies: Vec<&'a CelestialBody>,
+}
+
+impl Neighbourhood<'_> {
+    fn nearest(&self, filter: impl Fn(&Entity) -> bool) -> Option<&CelestialBody> {
+        self.bodies
+            .iter()
+            .filter(|body| body.id != self.id && filter(&body.entity))
+            .min_by(|b1, b2| {
+                let d1 = (b1.coords - self.coords).norm();
+                let d2 = (b2.coords - self.coords).norm();
+                d1.total_cmp(&d2).then(b1.id.cmp(&b2.id))
+            })
+            .copied()
+    }
+}
+
+/// Server side pilot of an NPC ship, asked every tick what to do.
+pub trait ShipBrain: Debug + Send + Sync {
+    fn kind(&self) -> BrainKind;
+
+    fn think(&mut self, view: &Neighbourhood) -> ShipState;
+
+    fn clone_box(&self) -> Box<dyn ShipBrain>;
+}
+
+impl Clone for Box<dyn ShipBrain> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Brains NPCs can be persisted with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BrainKind {
+    Patrol,
+    Mine,
+    Flee,
+}
+
+impl BrainKind {
+    pub const ALL: [BrainKind; 3] = [BrainKind::Patrol, BrainKind::Mine, BrainKind::Flee];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            BrainKind::Patrol => "Patrol",
+            BrainKind::Mine => "Mine",
+            BrainKind::Flee => "Flee",
+        }
+    }
+
+    pub(crate) fn from_str(kind: &str) -> Option<BrainKind> {
+        match kind {
+            "Patrol" => Some(BrainKind::Patrol),
+            "Mine" => Some(BrainKind::Mine),
+            "Flee" => Some(BrainKind::Flee),
+            _ => None,
+        }
+    }
+
+    pub fn brain(&self) -> Box<dyn ShipBrain> {
+        match self {
+            BrainKind::Patrol => Box::new(Patrol),
+            BrainKind::Mine => Box::new(Miner),
+            BrainKind::Flee => Box::new(Flee),
+        }
+    }
+}
+
+fn idle() -> ShipState {
+    ShipState {
+        throttle_up: false,
+        direction: [0f64; 3],
+    }
+}
+
+fn towards(direction: Vector3) -> ShipState {
+    ShipState {
+        throttle_up: true,
+        direction: [direction.x, direction.y, direction.z],
+    }
+}
+
+/// Circles its gravity center at `PATROL_RADIUS`.
+#[derive(Clone, Debug)]
+pub struct Patrol;
+
+impl ShipBrain for Patrol {
+    fn kind(&self) -> BrainKind {
+        BrainKind::Patrol
+    }
+
+    fn think(&mut self, view: &Neighbourhood) -> ShipState {
+        let Some(center) = view.gravity_center else {
+            return idle();
+        };
+        let local = view.coords - center;
+        let distance = local.norm();
+        if distance <= f64::EPSILON {
+            return towards(Vector3::from(1f64, 0f64, 0f64));
+        }
+        let radial = local / distance;
+        // Orbiting in the x/z plane, like the rest of the system.
+        let tangent = Vector3::from(-radial.z, 0f64, radial.x);
+        towards(tangent + radial * ((PATROL_RADIUS - distance) / PATROL_RADIUS))
+    }
+
+    fn clone_box(&self) -> Box<dyn ShipBrain> {
+        Box::new(self.clone())
+    }
+}
+
+/// Flies to the nearest asteroid and stays in mining range of it.
+#[derive(Clone, Debug)]
+pub struct Miner;
+
+impl ShipBrain for Miner {
+    fn kind(&self) -> BrainKind {
+        BrainKind::Mine
+    }
+
+    fn think(&mut self, view: &Neighbourhood) -> ShipState {
+        match view.nearest(|entity| matches!(entity, Entity::Asteroid(_))) {
+            Some(asteroid) if (asteroid.coords - view.coords).norm() > MINE_RANGE / 2f64 => {
+                towards(asteroid.coords - view.coords)
+            }
+            _ => idle(),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn ShipBrain> {
+        Box::new(self.clone())
+    }
+}
+
+/// Runs away from the nearest player coming closer than `FLEE_RADIUS`.
+#[derive(Clone, Debug)]
+pub struct Flee;
+
+impl ShipBrain for Flee {
+    fn kind(&self) -> BrainKind {
+        BrainKind::Flee
+    }
+
+    fn think(&mut self, view: &Neighbourhood) -> ShipState {
+        match view.nearest(|entity| matches!(entity, Entity::Player(_))) {
+            Some(player) if (player.coords - view.coords).norm() < FLEE_RADIUS => {
+                towards(view.coords - player.coords)
+            }
+            _ => idle(),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn ShipBrain> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,81 @@
+use std::f64::consts::{PI, TAU};
+
+use scilib::coordinate::spherical::Spherical;
+
+use crate::error::Error;
+use crate::{Id, Result};
+
+use super::celestial_body::CelestialBody;
+use super::entity::structure::StructureKind;
+use super::inventory::{Inventory, Item, ItemStack};
+use super::mining::Ore;
+use super::ownership;
+use super::repr::Vector3;
+
+pub const BUILD_RANGE: f64 = 1000f64;
+pub const MAX_STRUCTURES_PER_BODY: usize = 4;
+pub const STRUCTURE_ROTATING_SPEED: f64 = 0.01;
+
+pub fn cost(kind: StructureKind) -> Vec<ItemStack> {
+    let items = match kind {
+        StructureKind::Station => vec![
+            (Item::Credits, 500),
+            (Item::Ore(Ore::Iron), 200),
+            (Item::Ore(Ore::Nickel), 100),
+        ],
+        StructureKind::Outpost => vec![(Item::Credits, 100), (Item::Ore(Ore::Iron), 50)],
+        StructureKind::MiningRig => vec![
+            (Item::Credits, 200),
+            (Item::Ore(Ore::Iron), 80),
+            (Item::Ore(Ore::Nickel), 20),
+        ],
+    };
+    items
+        .into_iter()
+        .map(|(item, amount)| ItemStack { item, amount })
+        .collect()
+}
+
+fn orbit_radius(kind: StructureKind) -> f64 {
+    match kind {
+        StructureKind::Station => 150f64,
+        StructureKind::Outpost => 100f64,
+        StructureKind::MiningRig => 60f64,
+    }
+}
+
+/// Where the `index`-th structure around `anchor` is placed: structures are
+/// spread evenly on the anchor's orbital plane.
+pub fn placement(anchor: &CelestialBody, kind: StructureKind, index: usize) -> Vector3 {
+    let phi = index as f64 * TAU / MAX_STRUCTURES_PER_BODY as f64;
+    anchor.coords + Vector3::from_coord(Spherical::from(orbit_radius(kind), PI, phi))
+}
+
+/// Checks whether `builder_id`, flying `builder` with `inventory`, may build
+/// a `kind` structure around `anchor`, which already anchors `anchored`.
+pub fn check_build(
+    builder_id: Id,
+    builder: &CelestialBody,
+    inventory: &Inventory,
+    anchor: &CelestialBody,
+    kind: StructureKind,
+    anchored: usize,
+) -> Result<()> {
+    if !ownership::is_claimable(&anchor.entity) || anchor.owner != builder_id {
+        return Err(Error::NotOwned(anchor.id));
+    }
+
+    if (anchor.coords - builder.coords).norm() > BUILD_RANGE {
+        return Err(Error::TooFar(anchor.id));
+    }
+
+    if anchored >= MAX_STRUCTURES_PER_BODY {
+        return Err(Error::TooManyStructures(anchor.id));
+    }
+
+    if !inventory.contains(&cost(kind)) {
+        return Err(Error::NotEnoughItems);
+    }
+
+    Ok(())
+}
@@ -1,9 +1,6 @@
 use crate::Id;
 
-use super::{
-    entity::{asteroid::Asteroid, Entity},
-    repr::Vector3,
-};
+use super::{entity::Entity, repr::Vector3};
 use rstar::{RTreeObject, AABB};
 
 #[derive(Clone, Debug)]
@@ -53,6 +50,14 @@ impl CelestialBody {
         self.local_speed
     }
 
+    pub fn get_owner(&self) -> Option<Id> {
+        if self.owner == Id::MAX {
+            None
+        } else {
+            Some(self.owner)
+        }
+    }
+
     pub fn borrow_entity(&self) -> &Entity {
         &self.entity
     }
@@ -80,18 +85,4 @@ impl CelestialBody {
             entity,
         }
     }
-
-    pub(crate) fn dummy(id: Id) -> CelestialBody {
-        CelestialBody::new(
-            id,
-            Id::default(),
-            Vector3::default(),
-            Vector3::default(),
-            0f64,
-            0f64,
-            0f64,
-            Id::default(),
-            Entity::Asteroid(Asteroid { id: Id::default() }),
-        )
-    }
 }
@@ -0,0 +1,89 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use is_printable::IsPrintable;
+
+use crate::error::Error;
+use crate::protocol::{ChatChannel, ChatInfo};
+use crate::rate_limit::RateLimiter;
+use crate::{Id, Result};
+
+pub const MAX_CHAT_MESSAGE_LEN: usize = 256;
+pub const CHAT_RATE_CAPACITY: usize = 5;
+pub const CHAT_RATE_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub struct ChatConfig {
+    pub history_size: usize,
+    pub persist_history: bool,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        ChatConfig {
+            history_size: 50,
+            persist_history: false,
+        }
+    }
+}
+
+pub fn new_chat_limiter() -> RateLimiter {
+    RateLimiter::new(CHAT_RATE_CAPACITY, CHAT_RATE_WINDOW)
+}
+
+pub fn validate_message(message: &str) -> Result<()> {
+    if message.trim().is_empty()
+        || message.chars().count() > MAX_CHAT_MESSAGE_LEN
+        || !message.is_printable()
+    {
+        return Err(Error::InvalidChatMessage);
+    }
+    Ok(())
+}
+
+pub fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Recent global and per-system messages, replayed to players on login.
+/// Direct messages are never kept.
+#[derive(Default)]
+pub struct ChatHistory {
+    pub(crate) size: usize,
+    pub(crate) global: VecDeque<ChatInfo>,
+    pub(crate) systems: HashMap<Id, VecDeque<ChatInfo>>,
+}
+
+impl ChatHistory {
+    pub fn new(size: usize) -> ChatHistory {
+        ChatHistory {
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, system: Id, info: ChatInfo) {
+        let queue = match info.channel {
+            ChatChannel::Global => &mut self.global,
+            ChatChannel::System => self.systems.entry(system).or_default(),
+            ChatChannel::Direct(_) => return,
+        };
+
+        queue.push_back(info);
+        while queue.len() > self.size {
+            queue.pop_front();
+        }
+    }
+
+    pub fn for_system(&self, system: Id) -> Vec<ChatInfo> {
+        let mut infos: Vec<ChatInfo> = self.global.iter().cloned().collect();
+        if let Some(system_infos) = self.systems.get(&system) {
+            infos.extend(system_infos.iter().cloned());
+        }
+        infos.sort_by_key(|info| info.timestamp);
+        infos
+    }
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::{Id, Result};
+
+use super::celestial_body::CelestialBody;
+use super::repr::Vector3;
+
+pub const MAX_HULL: f64 = 100f64;
+pub const MAX_SHIELD: f64 = 50f64;
+/// Shield points regained per second.
+pub const SHIELD_REGEN_RATE: f64 = 5f64;
+/// How close to a shot's line of fire a ship must be to be hit.
+pub const HIT_RADIUS: f64 = 20f64;
+pub const RESPAWN_DISTANCE: f64 = 1500f64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Weapon {
+    Laser,
+    Railgun,
+}
+
+impl Weapon {
+    pub fn damage(&self) -> f64 {
+        match self {
+            Weapon::Laser => 10f64,
+            Weapon::Railgun => 40f64,
+        }
+    }
+
+    pub fn range(&self) -> f64 {
+        match self {
+            Weapon::Laser => 2000f64,
+            Weapon::Railgun => 4000f64,
+        }
+    }
+
+    /// Seconds before the ship may fire again.
+    pub fn cooldown(&self) -> f64 {
+        match self {
+            Weapon::Laser => 0.5f64,
+            Weapon::Railgun => 3f64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ship {
+    pub(crate) hull: f64,
+    pub(crate) shield: f64,
+    pub(crate) cooldown: f64,
+}
+
+impl Default for Ship {
+    fn default() -> Self {
+        Ship {
+            hull: MAX_HULL,
+            shield: MAX_SHIELD,
+            cooldown: 0f64,
+        }
+    }
+}
+
+impl Ship {
+    pub fn hull(&self) -> f64 {
+        self.hull
+    }
+
+    pub fn shield(&self) -> f64 {
+        self.shield
+    }
+
+    pub fn is_destroyed(&self) -> bool {
+        self.hull <= 0f64
+    }
+
+    /// Shields absorb damage first, the rest goes to the hull.
+    pub fn take_damage(&mut self, damage: f64) {
+        let absorbed = damage.min(self.shield);
+        self.shield -= absorbed;
+        self.hull = (self.hull - (damage - absorbed)).max(0f64);
+    }
+
+    pub fn update(&mut self, delta: f64) {
+        self.cooldown = (self.cooldown - delta).max(0f64);
+        if !self.is_destroyed() {
+            self.shield = (self.shield + SHIELD_REGEN_RATE * delta).min(MAX_SHIELD);
+        }
+    }
+
+    pub fn check_fire(&self) -> Result<()> {
+        if self.cooldown > 0f64 {
+            return Err(Error::WeaponCooldown);
+        }
+        Ok(())
+    }
+}
+
+/// A shot fired during a tick, resolved at the end of it.
+#[derive(Clone, Debug)]
+pub struct Shot {
+    pub(crate) shooter: Id,
+    pub(crate) weapon: Weapon,
+    pub(crate) target: ShotTarget,
+}
+
+#[derive(Clone, Debug)]
+pub enum ShotTarget {
+    Body(Id),
+    Direction(Vector3),
+}
+
+/// Distance along the shot at which `body` is hit, if it is close enough
+/// to the segment going from `origin` toward `direction` (a unit vector)
+/// for `range`.
+pub fn hit_distance(
+    origin: Vector3,
+    direction: Vector3,
+    range: f64,
+    body: &CelestialBody,
+) -> Option<f64> {
+    let local = body.coords - origin;
+    let along = local.x * direction.x + local.y * direction.y + local.z * direction.z;
+    if !(0f64..=range).contains(&along) {
+        return None;
+    }
+    if (local - direction * along).norm() > HIT_RADIUS {
+        return None;
+    }
+    Some(along)
+}
+
+/// Where a destroyed ship comes back, next to its home star.
+pub fn respawn_coords(home_star: &CelestialBody) -> Vector3 {
+    home_star.coords + Vector3::from(RESPAWN_DISTANCE, 0f64, 0f64)
+}
@@ -1,12 +1,21 @@
+use crate::game::mining::Ores;
 use crate::Id;
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Asteroid {
     pub(crate) id: Id,
+    pub(crate) ores: Ores,
 }
 
 impl Asteroid {
     pub fn new(id: Id) -> Asteroid {
-        Asteroid { id }
+        Asteroid {
+            id,
+            ores: Ores::new(),
+        }
+    }
+
+    pub fn borrow_ores(&self) -> &Ores {
+        &self.ores
     }
 }
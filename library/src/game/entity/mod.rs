@@ -1,14 +1,19 @@
+use super::combat::Ship;
 use asteroid::Asteroid;
 use moon::Moon;
+use npc::Npc;
 use planet::Planet;
 use player::Player;
 use star::Star;
+use structure::Structure;
 
 pub mod asteroid;
 pub mod moon;
+pub mod npc;
 pub mod planet;
 pub mod player;
 pub mod star;
+pub mod structure;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Entity {
@@ -17,4 +22,25 @@ pub enum Entity {
     Asteroid(Asteroid),
     Planet(Planet),
     Moon(Moon),
+    Structure(Structure),
+    Npc(Npc),
+}
+
+impl Entity {
+    /// Hull and shields of the bodies combat applies to.
+    pub fn ship(&self) -> Option<&Ship> {
+        match self {
+            Entity::Player(player) => Some(&player.ship),
+            Entity::Npc(npc) => Some(&npc.ship),
+            _ => None,
+        }
+    }
+
+    pub fn ship_mut(&mut self) -> Option<&mut Ship> {
+        match self {
+            Entity::Player(player) => Some(&mut player.ship),
+            Entity::Npc(npc) => Some(&mut npc.ship),
+            _ => None,
+        }
+    }
 }
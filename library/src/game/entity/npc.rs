@@ -0,0 +1,51 @@
+use crate::{
+    game::{
+        brain::{Neighbourhood, ShipBrain},
+        combat::Ship,
+        repr::Vector3,
+    },
+    Id,
+};
+
+#[derive(Clone, Debug)]
+pub struct Npc {
+    pub(crate) id: Id,
+    pub(crate) brain: Box<dyn ShipBrain>,
+    pub(crate) ship: Ship,
+}
+
+impl PartialEq for Npc {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Npc {
+    pub fn new(id: Id, brain: Box<dyn ShipBrain>) -> Npc {
+        Npc {
+            id,
+            brain,
+            ship: Ship::default(),
+        }
+    }
+
+    pub fn borrow_brain(&self) -> &dyn ShipBrain {
+        self.brain.as_ref()
+    }
+
+    pub fn borrow_ship(&self) -> &Ship {
+        &self.ship
+    }
+
+    pub fn update(
+        &mut self,
+        coordinates: Vector3,
+        speed: f64,
+        delta: f64,
+        view: &Neighbourhood,
+    ) -> (Vector3, Vector3) {
+        let direction = self.brain.think(view).heading().unwrap_or_default();
+
+        (coordinates + direction * speed * delta, direction)
+    }
+}
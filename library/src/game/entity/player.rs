@@ -1,6 +1,14 @@
 use crate::{
-    game::{celestial_body::CelestialBody, repr::Vector3},
-    protocol::{BodyInfo, GameInfo, PlayerAction, PlayerInfo},
+    game::{
+        celestial_body::CelestialBody,
+        chat,
+        combat::Ship,
+        inventory::Inventory,
+        repr::Vector3,
+        sensors::{Sensors, BODIES_PER_INFO},
+    },
+    protocol::{GameInfo, PlayerAction, PlayerInfo},
+    rate_limit::RateLimiter,
     Id,
 };
 
@@ -8,8 +16,15 @@ use crate::{
 pub struct Player {
     pub(crate) id: Id,
     pub(crate) nickname: String,
-    pub(crate) _ownings: Vec<Id>,
+    pub(crate) ownings: Vec<Id>,
+    pub(crate) inventory: Inventory,
+    pub(crate) mining: Option<Id>,
+    pub(crate) mining_progress: f64,
+    pub(crate) ship: Ship,
+    // Boxed to keep the `Entity` variants close in size.
+    pub(crate) sensors: Box<Sensors>,
     pub(crate) actions: Vec<PlayerAction>,
+    pub(crate) chat_limiter: RateLimiter,
     pub(crate) infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
 }
 
@@ -32,12 +47,30 @@ impl Player {
         Player {
             id,
             actions: Vec::default(),
+            chat_limiter: chat::new_chat_limiter(),
             infos_sender,
             nickname,
-            _ownings: Vec::default(),
+            ownings: Vec::default(),
+            inventory: Inventory::default(),
+            mining: None,
+            mining_progress: 0f64,
+            ship: Ship::default(),
+            sensors: Box::default(),
         }
     }
 
+    pub fn borrow_inventory(&self) -> &Inventory {
+        &self.inventory
+    }
+
+    pub fn borrow_ship(&self) -> &Ship {
+        &self.ship
+    }
+
+    pub fn borrow_sensors(&self) -> &Sensors {
+        &self.sensors
+    }
+
     pub async fn update(
         &mut self,
         coordinates: Vector3,
@@ -48,18 +81,10 @@ impl Player {
         let mut direction = Vector3::default();
 
         for action in &self.actions {
-            match action {
-                PlayerAction::ShipState(ship_state) => {
-                    if ship_state.throttle_up {
-                        direction = Vector3::from(
-                            ship_state.direction[0],
-                            ship_state.direction[1],
-                            ship_state.direction[2],
-                        );
-                        direction /= direction.norm();
-                    }
+            if let PlayerAction::ShipState(ship_state) = action {
+                if let Some(heading) = ship_state.heading() {
+                    direction = heading;
                 }
-                _ => todo!(),
             }
         }
 
@@ -68,47 +93,22 @@ impl Player {
         let mut coords = coordinates.clone();
 
         if direction.norm() > 0f64 {
-            coords += direction / direction.norm() * speed * delta;
+            coords += direction * speed * delta;
         }
 
         let _ = self
             .infos_sender
             .send(GameInfo::Player(PlayerInfo {
                 coords: [coords.x, coords.y, coords.z],
+                hull: self.ship.hull,
+                shield: self.ship.shield,
             }))
             .await;
 
-        let mut bodies = Vec::new();
-
-        for celestial in env {
-            let element_type = match celestial.entity {
-                super::Entity::Asteroid(_) => "Asteroid",
-                super::Entity::Star(_) => "Star",
-                super::Entity::Player(_) => "Player",
-                super::Entity::Planet(_) => "Planet",
-                super::Entity::Moon(_) => "Moon",
-            };
-            bodies.push(BodyInfo {
-                coords: [celestial.coords.x, celestial.coords.y, celestial.coords.z],
-                id: celestial.id,
-                element_type: element_type.to_string(),
-                gravity_center: celestial.gravity_center,
-                rotating_speed: celestial.rotating_speed,
-            });
-
-            if bodies.len() == 50 {
-                let _ = self
-                    .infos_sender
-                    .send(GameInfo::BodiesInSystem(bodies.clone()))
-                    .await;
-                bodies.clear();
-            }
-        }
-
-        if !bodies.is_empty() {
+        for bodies in self.sensors.scan(&env).chunks(BODIES_PER_INFO) {
             let _ = self
                 .infos_sender
-                .send(GameInfo::BodiesInSystem(bodies.clone()))
+                .send(GameInfo::BodiesInSystem(bodies.to_vec()))
                 .await;
         }
 
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Id;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StructureKind {
+    Station,
+    Outpost,
+    MiningRig,
+}
+
+impl StructureKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            StructureKind::Station => "Station",
+            StructureKind::Outpost => "Outpost",
+            StructureKind::MiningRig => "MiningRig",
+        }
+    }
+
+    pub(crate) fn from_str(kind: &str) -> Option<StructureKind> {
+        match kind {
+            "Station" => Some(StructureKind::Station),
+            "Outpost" => Some(StructureKind::Outpost),
+            "MiningRig" => Some(StructureKind::MiningRig),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Structure {
+    pub(crate) id: Id,
+    pub(crate) kind: StructureKind,
+}
+
+impl Structure {
+    pub fn new(id: Id, kind: StructureKind) -> Structure {
+        Structure { id, kind }
+    }
+
+    pub fn get_kind(&self) -> StructureKind {
+        self.kind
+    }
+}
@@ -1,12 +1,19 @@
 use core::f64;
-use std::f64::consts::PI;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::f64::consts::{PI, TAU};
 
+use super::brain::{Neighbourhood, NPC_SENSOR_RANGE};
+use super::combat::{self, HIT_RADIUS};
 use super::repr::Vector3;
 use super::{celestial_body::CelestialBody, entity::Entity};
 use crate::Id;
 use rstar::{RTree, AABB};
 use scilib::coordinate::spherical::Spherical;
 
+/// Seconds of orbit per second of update.
+const TIME_SCALE: f64 = 10f64;
+
 #[derive(Default)]
 pub struct Galaxy {
     pub(crate) celestials: RTree<CelestialBody>,
@@ -25,8 +32,63 @@ impl Galaxy {
         self.celestials.iter_mut().find(|g| g.id == id)
     }
 
-    pub fn _remove_by_id(&mut self, id: Id) -> Option<CelestialBody> {
-        self.celestials.remove(&CelestialBody::dummy(id))
+    pub fn remove_by_id(&mut self, id: Id) -> Option<CelestialBody> {
+        let body = self.borrow_body(id)?.clone();
+        self.celestials.remove(&body)
+    }
+
+    /// The system of each body: the root of its gravity centers, or the
+    /// first of them which isn't loaded.
+    pub(crate) fn systems(&self) -> HashMap<Id, Id> {
+        let centers: HashMap<Id, Id> = self
+            .celestials
+            .iter()
+            .map(|c| (c.id, c.gravity_center))
+            .collect();
+
+        centers
+            .keys()
+            .map(|&id| {
+                let mut system = id;
+                let mut steps = 0;
+                while let Some(&center) = centers.get(&system) {
+                    steps += 1;
+                    if center == Id::MAX || steps > centers.len() {
+                        break;
+                    }
+                    system = center;
+                }
+                (id, system)
+            })
+            .collect()
+    }
+
+    /// Ids of the bodies of the system of `star`, but the players.
+    pub(crate) fn system_bodies(&self, star: Id) -> HashSet<Id> {
+        let systems = self.systems();
+        self.celestials
+            .iter()
+            .filter(|body| {
+                systems.get(&body.id) == Some(&star) && !matches!(body.entity, Entity::Player(_))
+            })
+            .map(|body| body.id)
+            .collect()
+    }
+
+    /// Takes the bodies of the system of `star` out of the galaxy, but the
+    /// players.
+    pub(crate) fn remove_system(&mut self, star: Id) -> Vec<CelestialBody> {
+        let ids = self.system_bodies(star);
+        let (removed, kept): (Vec<_>, Vec<_>) = self
+            .celestials
+            .drain()
+            .partition(|body| ids.contains(&body.id));
+        self.celestials = RTree::bulk_load(kept);
+        removed
+    }
+
+    pub fn bodies_in_range(&self, center: Vector3, radius: f64) -> Vec<&CelestialBody> {
+        Self::galactics_in_spherical_view(&self.celestials, center, radius)
     }
 
     fn galactics_in_spherical_view(
@@ -47,34 +109,197 @@ impl Galaxy {
             .collect()
     }
 
-    pub async fn update(&mut self, mut delta: f64) {
-        delta *= 10f64;
-        if self.celestials.iter().count() < 2 {
-            return;
+    /// Bodies lying on the line of fire going from `origin` toward
+    /// `direction` (a unit vector) for `range`, nearest first. Bodies at the
+    /// same distance are ordered by id so that resolution never depends on
+    /// the tree layout.
+    pub fn bodies_in_line_of_fire(
+        &self,
+        origin: Vector3,
+        direction: Vector3,
+        range: f64,
+    ) -> Vec<(f64, &CelestialBody)> {
+        let end = origin + direction * range;
+        let min = [
+            origin.x.min(end.x) - HIT_RADIUS,
+            origin.y.min(end.y) - HIT_RADIUS,
+            origin.z.min(end.z) - HIT_RADIUS,
+        ];
+        let max = [
+            origin.x.max(end.x) + HIT_RADIUS,
+            origin.y.max(end.y) + HIT_RADIUS,
+            origin.z.max(end.z) + HIT_RADIUS,
+        ];
+        let mut bodies: Vec<(f64, &CelestialBody)> = self
+            .celestials
+            .locate_in_envelope_intersecting(&AABB::from_corners(min, max))
+            .filter_map(|body| {
+                combat::hit_distance(origin, direction, range, body).map(|along| (along, body))
+            })
+            .collect();
+        bodies.sort_by(|(d1, b1), (d2, b2)| d1.total_cmp(d2).then(b1.id.cmp(&b2.id)));
+        bodies
+    }
+
+    /// Same as `Spherical::from_coord` but clamped, so bodies crossing the
+    /// poles or sitting on their gravity center never produce NaN angles.
+    fn spherical_from_local(local: Vector3) -> Spherical {
+        let r = local.norm();
+        if r <= f64::EPSILON {
+            return Spherical {
+                r: 0f64,
+                theta: 0f64,
+                phi: 0f64,
+            };
+        }
+        Spherical {
+            r,
+            theta: local.y.atan2(local.x),
+            phi: (local.z / r).clamp(-1f64, 1f64).acos(),
+        }
+    }
+
+    /// Number of gravity centers between each body and the root of its system.
+    fn depths(celestials: &[CelestialBody]) -> HashMap<Id, usize> {
+        let centers: HashMap<Id, Id> = celestials
+            .iter()
+            .map(|c| (c.id, c.gravity_center))
+            .collect();
+
+        celestials
+            .iter()
+            .map(|c| {
+                let mut depth = 0;
+                let mut center = c.gravity_center;
+                while let Some(next) = centers.get(&center) {
+                    depth += 1;
+                    if depth > centers.len() {
+                        log::error!("Gravity center cycle around {}", c.id);
+                        break;
+                    }
+                    center = *next;
+                }
+                (c.id, depth)
+            })
+            .collect()
+    }
+
+    fn is_finite(coords: &Vector3) -> bool {
+        coords.x.is_finite() && coords.y.is_finite() && coords.z.is_finite()
+    }
+
+    /// `local` turned by `angle` along its meridian, the way `update` moves
+    /// bodies around their gravity center.
+    fn orbit(local: Vector3, angle: f64) -> Vector3 {
+        let spherical = Self::spherical_from_local(local);
+        Vector3::from_coord(Spherical {
+            phi: (spherical.phi + angle).rem_euclid(TAU),
+            ..spherical
+        })
+    }
+
+    /// Moves `bodies`, which make up whole systems, as far along their
+    /// orbits as `elapsed` seconds of updates would. Ships only follow their
+    /// gravity center.
+    pub(crate) fn advance_orbits(bodies: &mut [CelestialBody], elapsed: f64) {
+        let depths = Self::depths(bodies);
+        bodies.sort_by_key(|c| (depths[&c.id], c.id));
+
+        // Where each gravity center was, and where it is now.
+        let mut moved: HashMap<Id, (Vector3, Vector3)> = HashMap::new();
+        for body in bodies.iter_mut() {
+            let old = body.coords;
+            if let Some(&(old_center, new_center)) = moved.get(&body.gravity_center) {
+                let coords = match body.entity {
+                    Entity::Player(_) | Entity::Npc(_) => old + (new_center - old_center),
+                    _ => {
+                        new_center
+                            + Self::orbit(
+                                old - old_center,
+                                body.rotating_speed * elapsed * TIME_SCALE,
+                            )
+                    }
+                };
+                if Self::is_finite(&coords) {
+                    body.coords = coords;
+                } else {
+                    log::error!("Discarding non finite orbit catch up for {}", body.id);
+                }
+            }
+            moved.insert(body.id, (old, body.coords));
+        }
+    }
+
+    /// Moves the galaxy `delta` seconds forward and returns the bodies which
+    /// moved, along with every player since their actions and discoveries
+    /// change without them moving.
+    pub async fn update(&mut self, mut delta: f64) -> HashSet<Id> {
+        delta *= TIME_SCALE;
+        let mut updated = HashSet::new();
+        if self.celestials.size() == 0 {
+            return updated;
         }
 
         let mut old_rtree = self.celestials.clone();
         let mut new_rtree = RTree::<CelestialBody>::default();
         let mut celestials: Vec<_> = self.celestials.drain().collect();
 
-        while celestials.len() > 0 {
-            let mut celestial = celestials.pop().unwrap();
+        // Gravity centers are updated before their satellites, so that a
+        // satellite both follows its center and orbits around it.
+        let depths = Self::depths(&celestials);
+        celestials.sort_by_key(|c| Reverse((depths[&c.id], c.id)));
+        let mut updated_centers: HashMap<Id, Vector3> = HashMap::new();
+
+        while let Some(mut celestial) = celestials.pop() {
             old_rtree.remove(&celestial);
 
-            let gravity_center = celestials.iter().find(|g| g.id == celestial.gravity_center);
+            let gravity_center = updated_centers.get(&celestial.gravity_center).copied();
 
             if let Entity::Player(player) = &mut celestial.entity {
-                let env = Self::galactics_in_spherical_view(&old_rtree, celestial.coords, 10000f64);
+                let env = Self::galactics_in_spherical_view(
+                    &old_rtree,
+                    celestial.coords,
+                    player.sensors.range,
+                );
 
                 let (coords, direction, _speed) = player
                     .update(celestial.coords, celestial.local_speed, delta, env)
                     .await;
 
-                celestial.coords = coords;
-                celestial.local_direction = direction;
+                if Self::is_finite(&coords) {
+                    celestial.coords = coords;
+                    celestial.local_direction = direction;
+                } else {
+                    log::error!("Discarding non finite move for {}", celestial.id);
+                }
+                updated.insert(celestial.id);
+            } else if let Entity::Npc(npc) = &mut celestial.entity {
+                let view = Neighbourhood {
+                    id: celestial.id,
+                    coords: celestial.coords,
+                    gravity_center,
+                    bodies: Self::galactics_in_spherical_view(
+                        &old_rtree,
+                        celestial.coords,
+                        NPC_SENSOR_RANGE,
+                    ),
+                };
+
+                let (coords, direction) =
+                    npc.update(celestial.coords, celestial.local_speed, delta, &view);
+
+                if Self::is_finite(&coords) {
+                    if coords != celestial.coords || direction != celestial.local_direction {
+                        updated.insert(celestial.id);
+                    }
+                    celestial.coords = coords;
+                    celestial.local_direction = direction;
+                } else {
+                    log::error!("Discarding non finite move for {}", celestial.id);
+                }
             } else if let Some(gravity_center) = gravity_center {
-                let local_coordinates_car = celestial.coords - gravity_center.coords;
-                let local_coordinates_sph = Spherical::from_coord(local_coordinates_car);
+                let local_coordinates_car = celestial.coords - gravity_center;
+                let local_coordinates_sph = Self::spherical_from_local(local_coordinates_car);
                 let mut new_coordinates_sph = local_coordinates_sph.clone();
                 new_coordinates_sph.phi =
                     new_coordinates_sph.phi + celestial.rotating_speed * delta;
@@ -87,7 +312,15 @@ impl Galaxy {
                 let delta_car = Vector3::from_coord(new_coordinates_sph)
                     - Vector3::from_coord(local_coordinates_sph);
 
+                let delta_car = if Self::is_finite(&delta_car) {
+                    delta_car
+                } else {
+                    log::error!("Discarding non finite orbit step for {}", celestial.id);
+                    Vector3::default()
+                };
+
                 celestial.coords += delta_car;
+                updated.insert(celestial.id);
 
                 let mut ids = vec![celestial.id];
 
@@ -96,16 +329,19 @@ impl Galaxy {
                     celestials.iter_mut().for_each(|g| {
                         if g.gravity_center == id {
                             g.coords += delta_car;
+                            updated.insert(g.id);
                             ids.push(g.id);
                         }
                     });
                 }
             }
+            updated_centers.insert(celestial.id, celestial.coords);
             old_rtree.insert(celestial.clone());
             new_rtree.insert(celestial);
         }
 
         // assert_eq!(old_rtree.iter().count(), new_rtree.iter().count());
         self.celestials = new_rtree;
+        updated
     }
 }
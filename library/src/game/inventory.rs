@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::Result;
+
+use super::mining::Ore;
+
+/// Cargo space of a ship, in units of item volume.
+pub const CARGO_CAPACITY: u32 = 500;
+pub const STARTING_CREDITS: u32 = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Item {
+    Credits,
+    Ore(Ore),
+}
+
+impl Item {
+    pub fn volume(&self) -> u32 {
+        match self {
+            Item::Credits => 0,
+            Item::Ore(_) => 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ItemStack {
+    pub item: Item,
+    pub amount: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Inventory {
+    items: BTreeMap<Item, u32>,
+}
+
+impl Inventory {
+    pub fn amount(&self, item: Item) -> u32 {
+        self.items.get(&item).copied().unwrap_or_default()
+    }
+
+    pub fn volume(&self) -> u32 {
+        self.items
+            .iter()
+            .map(|(item, amount)| item.volume() * amount)
+            .sum()
+    }
+
+    pub fn free_volume(&self) -> u32 {
+        CARGO_CAPACITY.saturating_sub(self.volume())
+    }
+
+    pub fn add(&mut self, item: Item, amount: u32) {
+        if amount > 0 {
+            *self.items.entry(item).or_default() += amount;
+        }
+    }
+
+    pub fn remove(&mut self, item: Item, amount: u32) -> Result<()> {
+        let available = self.amount(item);
+        if available < amount {
+            return Err(Error::NotEnoughItems);
+        }
+        if available == amount {
+            self.items.remove(&item);
+        } else {
+            self.items.insert(item, available - amount);
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, stacks: &[ItemStack]) -> bool {
+        Inventory::from_stacks(stacks)
+            .items
+            .iter()
+            .all(|(item, amount)| self.amount(*item) >= *amount)
+    }
+
+    pub fn stacks(&self) -> Vec<ItemStack> {
+        self.items
+            .iter()
+            .map(|(item, amount)| ItemStack {
+                item: *item,
+                amount: *amount,
+            })
+            .collect()
+    }
+
+    pub fn from_stacks(stacks: &[ItemStack]) -> Inventory {
+        let mut inventory = Inventory::default();
+        for stack in stacks {
+            inventory.add(stack.item, stack.amount);
+        }
+        inventory
+    }
+}
+
+/// Moves `first_gives` from `first` to `second` and `second_gives` the
+/// other way. Either both sides are applied or, on error, none is.
+pub fn exchange(
+    first: &mut Inventory,
+    first_gives: &[ItemStack],
+    second: &mut Inventory,
+    second_gives: &[ItemStack],
+) -> Result<()> {
+    let mut new_first = first.clone();
+    let mut new_second = second.clone();
+
+    for stack in first_gives {
+        new_first.remove(stack.item, stack.amount)?;
+        new_second.add(stack.item, stack.amount);
+    }
+    for stack in second_gives {
+        new_second.remove(stack.item, stack.amount)?;
+        new_first.add(stack.item, stack.amount);
+    }
+
+    if new_first.volume() > CARGO_CAPACITY || new_second.volume() > CARGO_CAPACITY {
+        return Err(Error::CargoFull);
+    }
+
+    *first = new_first;
+    *second = new_second;
+    Ok(())
+}
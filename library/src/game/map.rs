@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use crate::protocol::{BodyInfo, MapInfo, SystemInfo};
+use crate::Id;
+
+use super::sensors::Sensors;
+
+/// Star of the system `id` belongs to, following the remembered gravity
+/// centers. Bodies whose chain leads to no remembered star belong to none.
+fn system_of(discovered: &BTreeMap<Id, BodyInfo>, id: Id) -> Option<Id> {
+    let mut current = discovered.get(&id)?;
+    for _ in 0..discovered.len() {
+        if current.element_type == "Star" {
+            return Some(current.id);
+        }
+        current = discovered.get(&current.gravity_center)?;
+    }
+    None
+}
+
+/// Compact view of the systems a player discovered, from what they
+/// remember of them.
+pub fn summary(sensors: &Sensors) -> MapInfo {
+    let mut systems: BTreeMap<Id, SystemInfo> = BTreeMap::new();
+    for (star, discovered_at) in &sensors.systems {
+        if let Some(info) = sensors.discovered.get(star) {
+            systems.insert(
+                *star,
+                SystemInfo {
+                    star: *star,
+                    coords: info.coords,
+                    discovered_at: *discovered_at,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    for info in sensors.discovered.values() {
+        let Some(system) =
+            system_of(&sensors.discovered, info.id).and_then(|star| systems.get_mut(&star))
+        else {
+            continue;
+        };
+        match info.element_type.as_str() {
+            "Planet" => system.planets += 1,
+            "Moon" => system.moons += 1,
+            "Asteroid" => system.asteroids += 1,
+            "Structure" => system.structures += 1,
+            _ => {}
+        }
+    }
+
+    MapInfo {
+        systems: systems.into_values().collect(),
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::{Id, Result};
+
+use super::celestial_body::CelestialBody;
+use super::entity::Entity;
+use super::inventory::Inventory;
+
+pub const MINE_RANGE: f64 = 1000f64;
+/// Units of ore extracted per second of mining.
+pub const MINING_RATE: f64 = 10f64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Ore {
+    Iron,
+    Nickel,
+    Ice,
+    Platinum,
+}
+
+impl Ore {
+    pub const ALL: [Ore; 4] = [Ore::Iron, Ore::Nickel, Ore::Ice, Ore::Platinum];
+
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            Ore::Iron => "iron",
+            Ore::Nickel => "nickel",
+            Ore::Ice => "ice",
+            Ore::Platinum => "platinum",
+        }
+    }
+}
+
+pub type Ores = BTreeMap<Ore, u32>;
+
+pub fn generate_ores<R: Rng>(rng: &mut R) -> Ores {
+    let mut ores = Ores::new();
+    ores.insert(Ore::Iron, rng.gen_range(20..200));
+    ores.insert(Ore::Nickel, rng.gen_range(10..100));
+    ores.insert(Ore::Ice, rng.gen_range(0..150));
+    if rng.gen_bool(0.1) {
+        ores.insert(Ore::Platinum, rng.gen_range(1..20));
+    }
+    ores.retain(|_, amount| *amount > 0);
+    ores
+}
+
+pub fn total(ores: &Ores) -> u32 {
+    ores.values().sum()
+}
+
+/// Moves at most `amount` units from `from` to `to`, in `Ore` order, and
+/// returns how many were moved. Exhausted ores are dropped from `from`.
+pub fn transfer(from: &mut Ores, to: &mut Ores, amount: u32) -> u32 {
+    let mut left = amount;
+    for ore in Ore::ALL {
+        if left == 0 {
+            break;
+        }
+        if let Some(available) = from.get_mut(&ore) {
+            let moved = left.min(*available);
+            *available -= moved;
+            *to.entry(ore).or_default() += moved;
+            left -= moved;
+        }
+    }
+    from.retain(|_, amount| *amount > 0);
+    amount - left
+}
+
+/// Checks whether `miner`, carrying `inventory`, may mine `target`.
+pub fn check_mine(
+    miner_id: Id,
+    miner: &CelestialBody,
+    inventory: &Inventory,
+    target: &CelestialBody,
+) -> Result<()> {
+    if !matches!(target.entity, Entity::Asteroid(_)) {
+        return Err(Error::NotMineable(target.id));
+    }
+
+    if target.owner != Id::MAX && target.owner != miner_id {
+        return Err(Error::AlreadyOwned(target.id));
+    }
+
+    if !in_range(miner, target) {
+        return Err(Error::TooFar(target.id));
+    }
+
+    if inventory.free_volume() == 0 {
+        return Err(Error::CargoFull);
+    }
+
+    Ok(())
+}
+
+pub fn in_range(miner: &CelestialBody, target: &CelestialBody) -> bool {
+    (target.coords - miner.coords).norm() <= MINE_RANGE
+}
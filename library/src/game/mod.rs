@@ -1,4 +1,15 @@
+pub mod brain;
+pub mod building;
 pub mod celestial_body;
+pub mod chat;
+pub mod combat;
 pub mod entity;
 pub mod galaxy;
+pub mod inventory;
+pub mod map;
+pub mod mining;
+pub mod ownership;
 pub mod repr;
+pub mod sensors;
+pub mod spectator;
+pub mod trade;
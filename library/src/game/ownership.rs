@@ -0,0 +1,32 @@
+use crate::error::Error;
+use crate::{Id, Result};
+
+use super::celestial_body::CelestialBody;
+use super::entity::Entity;
+
+pub const CLAIM_RANGE: f64 = 1000f64;
+
+pub fn is_claimable(entity: &Entity) -> bool {
+    matches!(
+        entity,
+        Entity::Asteroid(_) | Entity::Moon(_) | Entity::Planet(_)
+    )
+}
+
+/// Checks whether the player `claimer_id`, flying `claimer`, may take
+/// ownership of `target`. Claiming a body already owned is a no-op.
+pub fn check_claim(claimer_id: Id, claimer: &CelestialBody, target: &CelestialBody) -> Result<()> {
+    if !is_claimable(&target.entity) {
+        return Err(Error::NotClaimable(target.id));
+    }
+
+    if target.owner != Id::MAX && target.owner != claimer_id {
+        return Err(Error::AlreadyOwned(target.id));
+    }
+
+    if (target.coords - claimer.coords).norm() > CLAIM_RANGE {
+        return Err(Error::TooFar(target.id));
+    }
+
+    Ok(())
+}
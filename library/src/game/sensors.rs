@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::protocol::BodyInfo;
+use crate::Id;
+
+use super::celestial_body::CelestialBody;
+use super::chat;
+use super::entity::Entity;
+
+pub const DEFAULT_SENSOR_RANGE: f64 = 3000f64;
+pub const BODIES_PER_INFO: usize = 50;
+
+pub fn body_info(celestial: &CelestialBody) -> BodyInfo {
+    let element_type = match celestial.entity {
+        Entity::Asteroid(_) => "Asteroid",
+        Entity::Star(_) => "Star",
+        Entity::Player(_) => "Player",
+        Entity::Planet(_) => "Planet",
+        Entity::Moon(_) => "Moon",
+        Entity::Structure(_) => "Structure",
+        Entity::Npc(_) => "Npc",
+    };
+    let structure = match &celestial.entity {
+        Entity::Structure(structure) => Some(structure.kind),
+        _ => None,
+    };
+    BodyInfo {
+        coords: [celestial.coords.x, celestial.coords.y, celestial.coords.z],
+        id: celestial.id,
+        element_type: element_type.to_string(),
+        gravity_center: celestial.gravity_center,
+        rotating_speed: celestial.rotating_speed,
+        owner: celestial.get_owner(),
+        structure,
+        stale: false,
+    }
+}
+
+/// What a ship currently detects and what its pilot remembers of bodies
+/// seen before, as they were when last in range.
+#[derive(Clone, Debug)]
+pub struct Sensors {
+    pub(crate) range: f64,
+    pub(crate) discovered: BTreeMap<Id, BodyInfo>,
+    pub(crate) in_range: BTreeSet<Id>,
+    /// Stars of the discovered systems, with when they were first seen.
+    pub(crate) systems: BTreeMap<Id, u64>,
+}
+
+impl Default for Sensors {
+    fn default() -> Self {
+        Sensors {
+            range: DEFAULT_SENSOR_RANGE,
+            discovered: BTreeMap::new(),
+            in_range: BTreeSet::new(),
+            systems: BTreeMap::new(),
+        }
+    }
+}
+
+impl Sensors {
+    /// Restores what a player remembers. Every remembered body is assumed
+    /// in range, so that the first scan reports the others as stale.
+    pub fn from_discovered(
+        discovered: BTreeMap<Id, BodyInfo>,
+        systems: BTreeMap<Id, u64>,
+    ) -> Sensors {
+        Sensors {
+            in_range: discovered.keys().copied().collect(),
+            discovered,
+            systems,
+            ..Default::default()
+        }
+    }
+
+    pub fn range(&self) -> f64 {
+        self.range
+    }
+
+    pub fn is_discovered(&self, id: Id) -> bool {
+        self.discovered.contains_key(&id)
+    }
+
+    /// Records the bodies of `env`, all in range, and returns the infos to
+    /// send: those bodies followed by the ones which just left the range,
+    /// marked stale.
+    pub fn scan(&mut self, env: &[&CelestialBody]) -> Vec<BodyInfo> {
+        let mut infos: Vec<BodyInfo> = env.iter().map(|celestial| body_info(celestial)).collect();
+        let in_range: BTreeSet<Id> = infos.iter().map(|info| info.id).collect();
+
+        for info in &infos {
+            if info.element_type == "Star" && !self.systems.contains_key(&info.id) {
+                self.systems.insert(info.id, chat::now_timestamp());
+            }
+            self.discovered.insert(info.id, info.clone());
+        }
+
+        for id in self.in_range.difference(&in_range) {
+            if let Some(info) = self.discovered.get(id) {
+                infos.push(BodyInfo {
+                    stale: true,
+                    ..info.clone()
+                });
+            }
+        }
+
+        self.in_range = in_range;
+        infos
+    }
+
+    pub fn forget(&mut self, id: Id) {
+        self.discovered.remove(&id);
+        self.in_range.remove(&id);
+        self.systems.remove(&id);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::protocol::{GameInfo, Spectate, SpectateTarget};
+
+use super::entity::Entity;
+use super::galaxy::Galaxy;
+use super::repr::Vector3;
+use super::sensors::{Sensors, BODIES_PER_INFO};
+
+pub const MAX_SPECTATORS: usize = 64;
+
+/// A read-only connection watching the galaxy around a player or a point.
+/// It has no body, so it can't be seen, hit or saved.
+#[derive(Clone, Debug)]
+pub struct Spectator {
+    pub(crate) follow: SpectateTarget,
+    pub(crate) sensors: Sensors,
+    pub(crate) infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+}
+
+impl Spectator {
+    pub fn new(
+        spectate: &Spectate,
+        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+    ) -> Spectator {
+        let mut spectator = Spectator {
+            follow: spectate.follow.clone(),
+            sensors: Sensors::default(),
+            infos_sender,
+        };
+        spectator.sensors.range = spectate.range;
+        spectator
+    }
+
+    pub fn borrow_sensors(&self) -> &Sensors {
+        &self.sensors
+    }
+
+    pub fn retarget(&mut self, spectate: &Spectate) {
+        self.follow = spectate.follow.clone();
+        self.sensors.range = spectate.range;
+    }
+
+    /// Where the spectator looks from, if the followed player is online.
+    pub fn center(&self, galaxy: &Galaxy) -> Option<Vector3> {
+        match &self.follow {
+            SpectateTarget::Point(point) => Some(Vector3::from(point[0], point[1], point[2])),
+            SpectateTarget::Player(nickname) => galaxy
+                .celestials
+                .iter()
+                .find(|body| matches!(&body.entity, Entity::Player(player) if &player.nickname == nickname))
+                .map(|body| body.coords),
+        }
+    }
+
+    pub fn update(&mut self, galaxy: &Galaxy) {
+        let Some(center) = self.center(galaxy) else {
+            return;
+        };
+        let env = galaxy.bodies_in_range(center, self.sensors.range);
+        for bodies in self.sensors.scan(&env).chunks(BODIES_PER_INFO) {
+            let _ = self
+                .infos_sender
+                .try_send(GameInfo::BodiesInSystem(bodies.to_vec()));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::protocol::{TradeInfo, TradeStatus};
+use crate::{Id, Result};
+
+use super::celestial_body::CelestialBody;
+use super::inventory::ItemStack;
+
+pub const TRADE_RANGE: f64 = 1000f64;
+pub const MAX_OFFERS_PER_PLAYER: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeOffer {
+    pub(crate) id: Id,
+    pub(crate) from: Id,
+    pub(crate) to: Id,
+    pub(crate) give: Vec<ItemStack>,
+    pub(crate) want: Vec<ItemStack>,
+}
+
+impl TradeOffer {
+    pub fn info(&self, status: TradeStatus) -> TradeInfo {
+        TradeInfo {
+            id: self.id,
+            from: self.from,
+            to: self.to,
+            give: self.give.clone(),
+            want: self.want.clone(),
+            status,
+        }
+    }
+}
+
+/// Pending offers between players, keyed by offer id. Offers only live in
+/// memory: they are dropped when either side leaves.
+#[derive(Default)]
+pub struct TradeBook {
+    next_id: Id,
+    offers: HashMap<Id, TradeOffer>,
+}
+
+impl TradeBook {
+    pub fn offer(
+        &mut self,
+        from: Id,
+        to: Id,
+        give: Vec<ItemStack>,
+        want: Vec<ItemStack>,
+    ) -> Result<TradeOffer> {
+        if self.offers.values().filter(|o| o.from == from).count() >= MAX_OFFERS_PER_PLAYER {
+            return Err(Error::TooManyOffers);
+        }
+
+        self.next_id += 1;
+        let offer = TradeOffer {
+            id: self.next_id,
+            from,
+            to,
+            give,
+            want,
+        };
+        self.offers.insert(offer.id, offer.clone());
+        Ok(offer)
+    }
+
+    pub fn get(&self, id: Id) -> Option<&TradeOffer> {
+        self.offers.get(&id)
+    }
+
+    pub fn remove(&mut self, id: Id) -> Option<TradeOffer> {
+        self.offers.remove(&id)
+    }
+
+    pub fn remove_involving(&mut self, body_id: Id) -> Vec<TradeOffer> {
+        let ids: Vec<Id> = self
+            .offers
+            .values()
+            .filter(|o| o.from == body_id || o.to == body_id)
+            .map(|o| o.id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| self.offers.remove(&id))
+            .collect()
+    }
+}
+
+pub fn in_range(first: &CelestialBody, second: &CelestialBody) -> bool {
+    (first.coords - second.coords).norm() <= TRADE_RANGE
+}
@@ -1,37 +1,354 @@
 use crate::error::Error;
+use crate::events::{Event, EventBus};
+use crate::export::GalaxyExport;
+use crate::game::brain::{self, BrainKind, NPC_SENSOR_RANGE, NPC_SPEED};
+use crate::game::building;
 use crate::game::celestial_body::CelestialBody;
+use crate::game::chat::{self, ChatConfig, ChatHistory};
+use crate::game::combat::{self, Ship, Shot, ShotTarget};
 use crate::game::entity::Entity;
 use crate::game::galaxy::Galaxy;
+use crate::game::inventory::{self, Inventory, Item, CARGO_CAPACITY, STARTING_CREDITS};
+use crate::game::map;
+use crate::game::mining::{self, Ores};
+use crate::game::ownership;
 use crate::game::repr::Vector3;
-use crate::protocol::GameInfo;
-use crate::sql_database::SqlDatabase;
-use crate::sync_pool::SyncPool;
+use crate::game::spectator::{Spectator, MAX_SPECTATORS};
+use crate::game::trade::{self, TradeBook, TradeOffer};
+use crate::journal::{BodyRecord, Journal, Record};
+use crate::migration;
+use crate::protocol::{
+    self, Build, Chat, ChatChannel, ChatInfo, Fire, FireTarget, GameInfo, HitInfo, InventoryInfo,
+    KillInfo, MiningInfo, PlayerAction, RedirectInfo, Spectate, SpectateTarget, Trade, TradeStatus,
+};
+use crate::scripting::{self, ScriptCommand, Scripts, SCRIPT_SENDER};
+use crate::shard::{Departure, Handoff, ShardConfig, HANDOFF_RETRY_DELAY};
+use crate::sql_database::{Orphan, SqlDatabase};
+use crate::store::{GalaxyStore, MemoryStore, SqliteStore};
+use crate::sync_pool::{self, SyncPool};
 use crate::{Id, Result};
-use is_printable::IsPrintable;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use rand::prelude::*;
 use rand::Rng;
 use rand_chacha::ChaCha8Rng;
+use rhai::{Array, Dynamic, FuncArgs};
+use rstar::primitives::GeomWithData;
+use rstar::RTree;
 use scilib::coordinate::cartesian::Cartesian;
 use scilib::coordinate::spherical::Spherical;
+use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::f64::consts::{PI, TAU};
 use std::fs::File;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_PLAYERS: usize = 256;
+/// How long a system nobody looks at stays loaded.
+pub const DEFAULT_SYSTEM_IDLE: Duration = Duration::from_secs(300);
+/// How often idle systems are looked for, unless they idle for less.
+const SYSTEMS_CHECK_PERIOD: Duration = Duration::from_secs(10);
+/// Farthest from its star a body of a generated system orbits.
+const SYSTEM_RADIUS: f64 = 4500f64;
+/// Seed of the galaxies which weren't given one, as all were before seeds
+/// were saved.
+pub const DEFAULT_GENERATION_SEED: u64 = 0;
 
 pub struct Instance {
     pub(crate) sync_pool: SyncPool,
     pub(crate) galaxy: Galaxy,
+    pub(crate) chat_config: ChatConfig,
+    pub(crate) chat_history: ChatHistory,
+    pub(crate) max_players: usize,
+    pub(crate) trades: TradeBook,
+    pub(crate) pending_shots: Vec<Shot>,
+    pub(crate) scripts: Option<Scripts>,
+    pub(crate) events: EventBus,
+    pub(crate) spectators: BTreeMap<Id, Spectator>,
+    // Spectators have no body: their ids are taken from the top of the id
+    // range so that they never collide with body ids.
+    pub(crate) next_spectator_id: Id,
+    pub(crate) shard: Option<ShardConfig>,
+    pub(crate) departures: Vec<Departure>,
+    // Players being handed off, or whose handoff failed and is retried
+    // after the given instant.
+    pub(crate) pending_handoffs: BTreeMap<Id, Option<Instant>>,
+    // Players who left once handed off, whose connection may still leave.
+    pub(crate) handed_off: BTreeSet<Id>,
+    pub(crate) journal: Journal,
+    // Stars of the systems in the galaxy, with when a player or spectator
+    // last looked at them.
+    pub(crate) systems: BTreeMap<Id, Instant>,
+    pub(crate) system_idle: Duration,
+    // When idle systems were last looked for.
+    pub(crate) systems_checked_at: Option<Instant>,
+    // Every star, loaded or not, at its coordinates.
+    pub(crate) stars: RTree<GeomWithData<[f64; 3], Id>>,
+    // NPCs scripts spawned around stars whose system isn't loaded yet.
+    pub(crate) pending_spawns: Vec<(Id, BrainKind)>,
 }
 
 impl Instance {
     pub async fn save_all(&mut self) -> Result<()> {
+        self.sync_pool.journal_checkpoint = self.journal.sequence();
+        self.sync_pool.systems = self.systems.keys().copied().collect();
         self.sync_pool.save().await?;
+        self.journal.truncate()?;
         Ok(())
     }
 
+    /// Rows of the galaxy file referencing missing bodies or players.
+    pub async fn check(&mut self) -> Result<Vec<Orphan>> {
+        self.sync_pool.store.check().await
+    }
+
+    /// Saves, then exports everything the store has.
+    pub async fn export(&mut self) -> Result<GalaxyExport> {
+        self.save_for_export().await?.await
+    }
+
+    /// Saves the galaxy, then returns the export of what was saved, read
+    /// without the instance.
+    pub(crate) async fn save_for_export(
+        &mut self,
+    ) -> Result<BoxFuture<'static, Result<GalaxyExport>>> {
+        self.save_all().await?;
+        let generation_seed = self.sync_pool.generation_seed;
+        let dump = self.sync_pool.store.dump();
+        Ok(async move {
+            let (bodies, chat) = dump.await?;
+            Ok(GalaxyExport::new(generation_seed, &bodies, chat))
+        }
+        .boxed())
+    }
+
+    /// Saves the bodies and chat of `export`, keeping their ids and the seed
+    /// new systems are generated from, into this instance's store, which
+    /// must still be empty.
+    pub async fn import(&mut self, export: GalaxyExport) -> Result<()> {
+        if !self.sync_pool.synced_bodies.is_empty()
+            || self.sync_pool.store.next_ids().await?.body > 1
+        {
+            return Err(Error::ImportIntoNonEmpty);
+        }
+        self.stage(export);
+        self.save_all().await
+    }
+
+    /// Keeps the bodies and chat of `export` to be saved, with their ids.
+    fn stage(&mut self, export: GalaxyExport) {
+        self.sync_pool.generation_seed = export.generation_seed;
+
+        for record in export.bodies {
+            let body = record.into_body();
+            if let Entity::Star(_) = body.entity {
+                self.add_star(body.id, body.coords);
+            }
+            self.sync_pool.body_next_id = self.sync_pool.body_next_id.max(body.id + 1);
+            if let Entity::Player(player) = &body.entity {
+                self.sync_pool.player_next_id = self.sync_pool.player_next_id.max(player.id + 1);
+            }
+            self.sync_pool.sync_body(&body);
+        }
+        for record in export.chat {
+            self.sync_pool.push_chat(record.system, &record.info);
+        }
+    }
+
+    /// An instance over the same store and journal as this one, which holds
+    /// the galaxy of `export` instead, written over the previous one in a
+    /// single transaction. This one is left without a store, unless
+    /// restoring fails.
+    pub async fn restore(&mut self, export: GalaxyExport) -> Result<Instance> {
+        // Nothing may be left in the journal for the restored galaxy to replay.
+        self.save_all().await?;
+        let mut restored =
+            Instance::from_store(Box::new(MemoryStore::default()), self.journal.path()).await?;
+        restored.stage(export);
+
+        std::mem::swap(&mut self.sync_pool.store, &mut restored.sync_pool.store);
+        restored.sync_pool.journal_checkpoint = restored.journal.sequence();
+        if let Err(err) = restored.sync_pool.save_replacing().await {
+            std::mem::swap(&mut self.sync_pool.store, &mut restored.sync_pool.store);
+            return Err(err);
+        }
+        restored.journal.truncate()?;
+        Ok(restored)
+    }
+
+    /// Journals the players' moves along with the bodies created, claimed
+    /// and removed since the last flush.
+    pub fn flush_journal(&mut self) -> Result<()> {
+        for body in self.galaxy.celestials.iter() {
+            if let Entity::Player(_) = body.entity {
+                self.journal.record_player(body);
+            }
+        }
+        self.journal.flush()
+    }
+
     pub async fn update(&mut self, delta: f64) {
-        self.galaxy.update(delta).await;
-        self.sync_pool.sync(self.galaxy.borrow_bodies());
+        if let Some(scripts) = &mut self.scripts {
+            scripts.reload_if_changed();
+        }
+        self.spawn_pending_npcs().await;
+        let moved = self.galaxy.update(delta).await;
+        self.sync_bodies(&moved);
+        self.update_mining(delta);
+        self.update_combat(delta);
+        for spectator in self.spectators.values_mut() {
+            spectator.update(&self.galaxy);
+        }
+        self.update_departures();
+        self.emit(Event::Tick(delta));
+        self.update_systems().await;
+    }
+
+    /// Keeps the bodies `ids` to be saved, as they now are in the galaxy.
+    fn sync_bodies(&mut self, ids: &HashSet<Id>) {
+        if ids.is_empty() {
+            return;
+        }
+        for body in self.galaxy.celestials.iter() {
+            if ids.contains(&body.id) {
+                self.sync_pool.sync_body(body);
+            }
+        }
+    }
+
+    /// Loads the system of `star` unless it already is, its bodies moved
+    /// along their orbits for as long as it was unloaded.
+    async fn load_system(&mut self, star: Id) -> Result<()> {
+        if let Some(observed_at) = self.systems.get_mut(&star) {
+            *observed_at = Instant::now();
+            return Ok(());
+        }
+        if !matches!(self.sync_pool.get_body(star).await?.entity, Entity::Star(_)) {
+            return Err(Error::BodyNotFound(star));
+        }
+
+        let mut bodies = self.sync_pool.get_rotatings(star).await?;
+        if let Some(saved_at) = self.sync_pool.store.load_saved_at(star).await? {
+            let elapsed = sync_pool::now_millis().saturating_sub(saved_at) as f64 / 1000f64;
+            Galaxy::advance_orbits(&mut bodies, elapsed);
+        }
+        log::info!("Loading system {} with {} bodies", star, bodies.len());
+        for body in bodies {
+            self.sync_pool.sync_body(&body);
+            self.galaxy.celestials.insert(body);
+        }
+        self.systems.insert(star, Instant::now());
+        Ok(())
+    }
+
+    /// Loads the systems players, NPCs and spectators may see bodies of, and
+    /// unloads the others once left alone for `system_idle`.
+    async fn update_systems(&mut self) {
+        let now = Instant::now();
+        let period = SYSTEMS_CHECK_PERIOD.min(self.system_idle);
+        if self
+            .systems_checked_at
+            .is_some_and(|checked_at| now.duration_since(checked_at) < period)
+        {
+            return;
+        }
+        self.systems_checked_at = Some(now);
+
+        let systems = self.galaxy.systems();
+        let mut observed: BTreeSet<Id> = BTreeSet::new();
+        // Where each observer looks from, how far, and the system it doesn't
+        // keep loaded: NPCs roam the system they belong to, which would never
+        // unload otherwise.
+        let mut views: Vec<(Vector3, f64, Option<Id>)> = self
+            .spectators
+            .values()
+            .filter_map(|spectator| {
+                spectator
+                    .center(&self.galaxy)
+                    .map(|center| (center, spectator.sensors.range, None))
+            })
+            .collect();
+        for body in self.galaxy.celestials.iter() {
+            match &body.entity {
+                Entity::Player(player) => {
+                    observed.extend(systems.get(&body.id));
+                    views.push((body.coords, player.sensors.range, None));
+                }
+                Entity::Npc(_) => views.push((
+                    body.coords,
+                    NPC_SENSOR_RANGE,
+                    systems.get(&body.id).copied(),
+                )),
+                _ => {}
+            }
+        }
+        let mut entered = BTreeSet::new();
+        for (center, range, own) in views {
+            for body in self.galaxy.bodies_in_range(center, range) {
+                observed.extend(systems.get(&body.id).filter(|star| Some(**star) != own));
+            }
+            let reach = range + SYSTEM_RADIUS;
+            for star in self
+                .stars
+                .locate_within_distance([center.x, center.y, center.z], reach * reach)
+            {
+                if Some(star.data) != own && !self.systems.contains_key(&star.data) {
+                    entered.insert(star.data);
+                }
+            }
+        }
+        for star in entered {
+            if let Err(err) = self.load_system(star).await {
+                log::warn!("Could not load system {}: {}", star, err);
+            }
+        }
+
+        let mut idle = Vec::new();
+        for (star, observed_at) in self.systems.iter_mut() {
+            if observed.contains(star) {
+                *observed_at = now;
+            } else if now.duration_since(*observed_at) >= self.system_idle {
+                idle.push(*star);
+            }
+        }
+        if !idle.is_empty() {
+            if let Err(err) = self.unload_systems(&idle).await {
+                log::error!("Could not unload systems {:?}: {}", idle, err);
+            }
+        }
+    }
+
+    /// Saves the bodies of the systems of `stars`, then takes them out of
+    /// the galaxy.
+    async fn unload_systems(&mut self, stars: &[Id]) -> Result<()> {
+        let bodies: HashSet<Id> = stars
+            .iter()
+            .flat_map(|star| self.galaxy.system_bodies(*star))
+            .collect();
+        self.sync_pool.save_systems(stars, &bodies).await?;
+        for star in stars {
+            self.systems.remove(star);
+            let removed = self.galaxy.remove_system(*star);
+            log::info!("Unloaded system {} with {} bodies", star, removed.len());
+        }
+        self.sync_pool.forget(bodies);
+        Ok(())
+    }
+
+    /// Spawns the NPCs scripts asked for around stars which weren't loaded.
+    async fn spawn_pending_npcs(&mut self) {
+        for (star, kind) in std::mem::take(&mut self.pending_spawns) {
+            let spawned = match self.load_system(star).await {
+                Ok(()) => self.apply_script_command(ScriptCommand::SpawnNpc { star, kind }),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = spawned {
+                log::warn!("[script] could not spawn an NPC around {}: {}", star, err);
+            }
+        }
     }
 
     pub async fn from_path(db_path: &'_ str) -> Result<Instance> {
@@ -39,107 +356,1224 @@ impl Instance {
             File::create(db_path).map_err(|err| Error::DbFileCreationError(err))?;
         }
 
-        let pool = SqlitePool::connect(db_path)
+        let options = SqliteConnectOptions::from_str(db_path)
+            .map_err(|err| Error::DbOpenError(db_path.to_string(), err))?
+            .foreign_keys(true);
+        let pool = SqlitePool::connect_with(options)
             .await
             .map_err(|err| Error::DbOpenError(db_path.to_string(), err))?;
 
         let mut db = SqlDatabase { pool };
         Instance::init_db(&mut db).await?;
 
-        Ok(Instance {
-            sync_pool: SyncPool::new(db).await?,
+        Instance::from_store(Box::new(SqliteStore::new(db)), &Journal::path_of(db_path)).await
+    }
+
+    /// An instance over the galaxy kept in `store`, journaling its changes
+    /// between saves in the file at `journal_path`.
+    pub async fn from_store(store: Box<dyn GalaxyStore>, journal_path: &str) -> Result<Instance> {
+        let mut sync_pool = SyncPool::new(store).await?;
+        let (journal, records) = Journal::open(journal_path, sync_pool.journal_checkpoint)?;
+        let recovering = !records.is_empty();
+        if recovering {
+            log::info!("Replaying {} journaled records", records.len());
+            sync_pool.replay(records);
+        }
+
+        let chat_config = ChatConfig::default();
+
+        let mut instance = Instance {
+            sync_pool,
             galaxy: Galaxy::default(),
-        })
+            chat_history: ChatHistory::new(chat_config.history_size),
+            chat_config,
+            max_players: DEFAULT_MAX_PLAYERS,
+            trades: TradeBook::default(),
+            pending_shots: Vec::default(),
+            scripts: None,
+            events: EventBus::default(),
+            spectators: BTreeMap::new(),
+            next_spectator_id: Id::MAX - 1,
+            shard: None,
+            departures: Vec::new(),
+            pending_handoffs: BTreeMap::new(),
+            handed_off: BTreeSet::new(),
+            journal,
+            systems: BTreeMap::new(),
+            system_idle: DEFAULT_SYSTEM_IDLE,
+            systems_checked_at: None,
+            stars: RTree::new(),
+            pending_spawns: Vec::new(),
+        };
+        instance.events.register(Instance::persist);
+        instance.events.register(Instance::notify);
+        instance.events.register(Instance::script);
+        // What a crash left in the journal is saved before anything else.
+        if recovering {
+            instance.save_all().await?;
+        } else {
+            instance.journal.truncate()?;
+        }
+        for (star, coords) in instance.sync_pool.store.load_stars().await? {
+            instance.add_star(star, coords);
+        }
+        Ok(instance)
+    }
+
+    fn add_star(&mut self, star: Id, coords: Vector3) {
+        self.stars
+            .insert(GeomWithData::new([coords.x, coords.y, coords.z], star));
     }
 
     pub(crate) async fn init_db(db: &mut SqlDatabase) -> Result<()> {
-        db.create_table(
-            "Body",
-            vec![
-                // "id INTEGER PRIMARY KEY AUTOINCREMENT",
-                "id INTEGER PRIMARY KEY",
-                "owner INTEGER",
-                "coordinate_x REAL NOT NULL",
-                "coordinate_y REAL NOT NULL",
-                "coordinate_z REAL NOT NULL",
-                "local_direction_x REAL NOT NULL",
-                "local_direction_y REAL NOT NULL",
-                "local_direction_z REAL NOT NULL",
-                "local_speed REAL",
-                "angular_speed REAL",
-                "rotating_speed REAL",
-                "gravity_center INTEGER",
-                // "FOREIGN KEY (owner) REFERENCES Player (id) ON DELETE SET NULL",
-                // "FOREIGN KEY (gravity_center) REFERENCES Body (id) ON DELETE SET NULL",
-            ],
-            vec!["id", "owner", "gravity_center"],
-        )
-        .await?;
-
-        db.create_table(
-            "Player",
-            vec![
-                // "id INTEGER PRIMARY KEY AUTOINCREMENT",
-                "id INTEGER PRIMARY KEY",
-                "nickname TEXT",
-                "body_id INTEGER",
-                // "FOREIGN KEY (body_id) REFERENCES Body (id)",
-            ],
-            vec!["id", "body_id", "nickname"],
-        )
-        .await?;
-
-        db.create_table(
-            "Star",
-            vec![
-                // "id INTEGER PRIMARY KEY AUTOINCREMENT",
-                "id INTEGER PRIMARY KEY",
-                "body_id INTEGER",
-                // "FOREIGN KEY (body_id) REFERENCES Body (id)",
-            ],
-            vec!["id", "body_id"],
-        )
-        .await?;
-
-        db.create_table(
-            "Planet",
-            vec![
-                // "id INTEGER PRIMARY KEY AUTOINCREMENT",
-                "id INTEGER PRIMARY KEY",
-                "body_id INTEGER",
-                // "FOREIGN KEY (body_id) REFERENCES Body (id)",
-            ],
-            vec!["id", "body_id"],
-        )
-        .await?;
-
-        db.create_table(
-            "Moon",
-            vec![
-                // "id INTEGER PRIMARY KEY AUTOINCREMENT",
-                "id INTEGER PRIMARY KEY",
-                "body_id INTEGER",
-                // "FOREIGN KEY (body_id) REFERENCES Body (id)",
-            ],
-            vec!["id", "body_id"],
-        )
-        .await?;
-
-        db.create_table(
-            "Asteroid",
-            vec![
-                // "id INTEGER PRIMARY KEY AUTOINCREMENT",
-                "id INTEGER PRIMARY KEY",
-                "body_id INTEGER",
-                // "FOREIGN KEY (body_id) REFERENCES Body (id)",
-            ],
-            vec!["id", "body_id"],
-        )
-        .await?;
+        migration::migrate(db).await
+    }
+
+    pub async fn set_chat_config(&mut self, config: ChatConfig) -> Result<()> {
+        self.chat_history = ChatHistory::new(config.history_size);
+
+        if config.persist_history {
+            for (system, info) in self.sync_pool.get_chat_history(config.history_size).await? {
+                self.chat_history.push(system, info);
+            }
+        }
+
+        self.chat_config = config;
+        Ok(())
+    }
+
+    /// Receives every event of the instance from now on.
+    pub fn subscribe(&mut self) -> tokio::sync::mpsc::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        if !self.events.push(event) {
+            return;
+        }
+        while let Some(event) = self.events.next() {
+            let mut index = 0;
+            while let Some(handler) = self.events.handler(index) {
+                handler(self, &event);
+                index += 1;
+            }
+            self.events.publish(&event);
+        }
+    }
+
+    /// Adds a new body to the galaxy. It is tracked for saving right away
+    /// since looking it up again from the event would scan the galaxy.
+    fn spawn_body(&mut self, body: CelestialBody) {
+        let id = body.id;
+        self.sync_pool.sync_body(&body);
+        self.journal
+            .record(Record::Body(Box::new(BodyRecord::from_body(&body))));
+        self.galaxy.celestials.insert(body);
+        self.emit(Event::BodyCreated(id));
+    }
+
+    fn persist(&mut self, event: &Event) {
+        match event {
+            Event::BodyOwnerChanged { body: id, .. } | Event::PlayerLeft { body: id, .. } => {
+                if let Some(body) = self.galaxy.borrow_body(*id) {
+                    let body = body.clone();
+                    self.sync_pool.sync_body(&body);
+                    self.journal
+                        .record(Record::Body(Box::new(BodyRecord::from_body(&body))));
+                }
+                if let Event::PlayerLeft { body, .. } = event {
+                    self.journal.forget_player(*body);
+                }
+            }
+            Event::BodyRemoved(id) => {
+                self.sync_pool.remove_body(*id);
+                self.journal.record(Record::Removed(*id));
+            }
+            _ => {}
+        }
+    }
 
+    fn notify(&mut self, event: &Event) {
+        match event {
+            Event::PlayerJoined { body, .. } => self.send_chat_history(*body),
+            Event::BodyRemoved(id) => {
+                for body in self.galaxy.celestials.iter_mut() {
+                    if let Entity::Player(player) = &mut body.entity {
+                        player.sensors.forget(*id);
+                        self.sync_pool.sync_body(body);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn script(&mut self, event: &Event) {
+        match event {
+            Event::PlayerJoined {
+                body,
+                player,
+                nickname,
+                first_login,
+            } => {
+                let joined = scripting::to_map([
+                    ("body_id", Dynamic::from_int((*body).into())),
+                    ("player_id", Dynamic::from_int((*player).into())),
+                    ("nickname", nickname.clone().into()),
+                    ("first_login", (*first_login).into()),
+                ]);
+                self.run_hook("on_player_join", (joined,));
+            }
+            Event::PlayerLeft {
+                body,
+                player,
+                nickname,
+            } => {
+                let left = scripting::to_map([
+                    ("body_id", Dynamic::from_int((*body).into())),
+                    ("player_id", Dynamic::from_int((*player).into())),
+                    ("nickname", nickname.clone().into()),
+                ]);
+                self.run_hook("on_player_leave", (left,));
+            }
+            Event::Tick(delta) => {
+                self.run_hook("on_tick", (*delta,));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn load_scripts(&mut self, path: &str) -> Result<()> {
+        self.scripts = Some(Scripts::load(path)?);
+        log::info!("Scripts loaded from {}", path);
         Ok(())
     }
 
+    /// Calls a script hook with a fresh snapshot of the players, then
+    /// applies what the script asked for.
+    fn run_hook(&mut self, hook: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        if !self
+            .scripts
+            .as_ref()
+            .is_some_and(|scripts| scripts.has_hook(hook))
+        {
+            return None;
+        }
+
+        let players = self.players_snapshot();
+        let scripts = self.scripts.as_mut()?;
+        scripts.set_players(players);
+        let result = scripts.call(hook, args);
+        let commands = scripts.take_commands();
+
+        for command in commands {
+            if let Err(err) = self.apply_script_command(command) {
+                log::warn!("Script command from {} refused: {}", hook, err);
+            }
+        }
+
+        result
+    }
+
+    fn players_snapshot(&self) -> Array {
+        self.galaxy
+            .celestials
+            .iter()
+            .filter_map(|body| match &body.entity {
+                Entity::Player(player) => Some(Dynamic::from_map(scripting::to_map([
+                    ("body_id", Dynamic::from_int(body.id.into())),
+                    ("player_id", Dynamic::from_int(player.id.into())),
+                    ("nickname", player.nickname.clone().into()),
+                    ("system", Dynamic::from_int(body.gravity_center.into())),
+                    (
+                        "coords",
+                        Dynamic::from_array(vec![
+                            body.coords.x.into(),
+                            body.coords.y.into(),
+                            body.coords.z.into(),
+                        ]),
+                    ),
+                    (
+                        "credits",
+                        Dynamic::from_int(player.inventory.amount(Item::Credits).into()),
+                    ),
+                    ("hull", player.ship.hull().into()),
+                    ("shield", player.ship.shield().into()),
+                ]))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn apply_script_command(&mut self, command: ScriptCommand) -> Result<()> {
+        match command {
+            ScriptCommand::Notify { body, message } => {
+                chat::validate_message(&message)?;
+                let nickname = match self.galaxy.borrow_body(body) {
+                    Some(CelestialBody {
+                        entity: Entity::Player(player),
+                        ..
+                    }) => player.nickname.clone(),
+                    _ => return Err(Error::BodyNotFound(body)),
+                };
+                self.send_to(
+                    body,
+                    GameInfo::Chat(ChatInfo {
+                        channel: ChatChannel::Direct(nickname),
+                        sender: SCRIPT_SENDER.to_string(),
+                        message,
+                        timestamp: chat::now_timestamp(),
+                    }),
+                );
+            }
+            ScriptCommand::Broadcast(message) => {
+                chat::validate_message(&message)?;
+                let info = GameInfo::Chat(ChatInfo {
+                    channel: ChatChannel::Global,
+                    sender: SCRIPT_SENDER.to_string(),
+                    message,
+                    timestamp: chat::now_timestamp(),
+                });
+                for body in self.galaxy.celestials.iter() {
+                    if let Entity::Player(player) = &body.entity {
+                        let _ = player.infos_sender.try_send(info.clone());
+                    }
+                }
+            }
+            ScriptCommand::Give { body, item, amount } => {
+                let celestial = self
+                    .galaxy
+                    .borrow_body_mut(body)
+                    .ok_or(Error::BodyNotFound(body))?;
+                match &mut celestial.entity {
+                    Entity::Player(player) => {
+                        let volume = u64::from(item.volume()) * u64::from(amount);
+                        if volume > u64::from(player.inventory.free_volume()) {
+                            return Err(Error::CargoFull);
+                        }
+                        player.inventory.add(item, amount);
+                    }
+                    _ => return Err(Error::BodyNotFound(body)),
+                }
+                let celestial = celestial.clone();
+                self.sync_pool.sync_body(&celestial);
+                self.send_inventory(body);
+            }
+            ScriptCommand::SpawnNpc { star, kind } => {
+                let coords = match self.galaxy.borrow_body(star) {
+                    Some(CelestialBody {
+                        entity: Entity::Star(_),
+                        coords,
+                        ..
+                    }) => *coords,
+                    None if !self.systems.contains_key(&star) => {
+                        self.pending_spawns.push((star, kind));
+                        return Ok(());
+                    }
+                    _ => return Err(Error::BodyNotFound(star)),
+                };
+                let mut npc = self.sync_pool.new_npc(kind);
+                let mut rng = ChaCha8Rng::seed_from_u64(npc.id.into());
+                npc.coords = brain::spawn_coords(coords, &mut rng);
+                npc.gravity_center = star;
+                npc.local_speed = NPC_SPEED;
+                log::info!("Script spawned {:?} NPC {} around {}", kind, npc.id, star);
+                self.spawn_body(npc);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lets the scripts veto an action before it is applied.
+    pub fn check_action(&mut self, id: Id, action: &PlayerAction) -> Result<()> {
+        if !self
+            .scripts
+            .as_ref()
+            .is_some_and(|scripts| scripts.has_hook("on_action"))
+        {
+            return Ok(());
+        }
+
+        let action =
+            rhai::serde::to_dynamic(action).map_err(|err| Error::InvalidAction(err.to_string()))?;
+        match self.run_hook("on_action", (Dynamic::from_int(id.into()), action)) {
+            Some(allowed) if allowed.as_bool() == Ok(false) => Err(Error::ActionRefusedByScript),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn chat(&mut self, id: Id, chat: Chat) -> Result<()> {
+        chat::validate_message(&chat.message)?;
+
+        let (sender, system) = match self.galaxy.borrow_body_mut(id) {
+            Some(CelestialBody {
+                entity: Entity::Player(player),
+                gravity_center,
+                ..
+            }) => {
+                if !player.chat_limiter.try_hit() {
+                    return Err(Error::ChatRateLimited);
+                }
+                (player.nickname.clone(), *gravity_center)
+            }
+            _ => return Err(Error::DbUuidNotFound(id)),
+        };
+
+        let info = ChatInfo {
+            channel: chat.channel,
+            sender,
+            message: chat.message,
+            timestamp: chat::now_timestamp(),
+        };
+
+        let mut delivered = false;
+        for celestial in self.galaxy.celestials.iter() {
+            if let Entity::Player(player) = &celestial.entity {
+                let recipient = match &info.channel {
+                    ChatChannel::Global => true,
+                    ChatChannel::System => celestial.gravity_center == system,
+                    ChatChannel::Direct(nickname) => {
+                        let is_recipient = &player.nickname == nickname;
+                        delivered |= is_recipient;
+                        is_recipient || celestial.id == id
+                    }
+                };
+                if recipient {
+                    let _ = player.infos_sender.try_send(GameInfo::Chat(info.clone()));
+                }
+            }
+        }
+        if info.channel == ChatChannel::Global {
+            for spectator in self.spectators.values() {
+                let _ = spectator
+                    .infos_sender
+                    .try_send(GameInfo::Chat(info.clone()));
+            }
+        }
+
+        if let ChatChannel::Direct(nickname) = &info.channel {
+            if !delivered {
+                return Err(Error::ChatRecipientNotFound(nickname.clone()));
+            }
+            return Ok(());
+        }
+
+        if self.chat_config.persist_history {
+            self.sync_pool.push_chat(system, &info);
+        }
+        self.chat_history.push(system, info);
+
+        Ok(())
+    }
+
+    pub fn claim(&mut self, id: Id, target: Id) -> Result<()> {
+        let claimer = self.galaxy.borrow_body(id).ok_or(Error::BodyNotFound(id))?;
+        let claimer_id = match &claimer.entity {
+            Entity::Player(player) => player.id,
+            _ => return Err(Error::BodyNotFound(id)),
+        };
+        let target_body = self
+            .galaxy
+            .borrow_body(target)
+            .ok_or(Error::BodyNotFound(target))?;
+
+        ownership::check_claim(claimer_id, claimer, target_body)?;
+
+        if target_body.owner == claimer_id {
+            return Ok(());
+        }
+
+        let target_body = self.galaxy.borrow_body_mut(target).unwrap();
+        let previous = std::mem::replace(&mut target_body.owner, claimer_id);
+
+        if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body_mut(id)
+        {
+            player.ownings.push(target);
+        }
+
+        log::info!("Player {} claimed body {}", claimer_id, target);
+        self.emit(Event::BodyOwnerChanged {
+            body: target,
+            previous,
+            owner: claimer_id,
+        });
+
+        Ok(())
+    }
+
+    pub fn mine(&mut self, id: Id, target: Id) -> Result<()> {
+        let miner = self.galaxy.borrow_body(id).ok_or(Error::BodyNotFound(id))?;
+        let player = match &miner.entity {
+            Entity::Player(player) => player,
+            _ => return Err(Error::BodyNotFound(id)),
+        };
+        let target_body = self
+            .galaxy
+            .borrow_body(target)
+            .ok_or(Error::BodyNotFound(target))?;
+
+        mining::check_mine(player.id, miner, &player.inventory, target_body)?;
+
+        if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body_mut(id)
+        {
+            player.mining = Some(target);
+            player.mining_progress = 0f64;
+        }
+
+        self.send_mining(id);
+
+        Ok(())
+    }
+
+    fn update_mining(&mut self, delta: f64) {
+        let miners: Vec<(Id, Id)> = self
+            .galaxy
+            .celestials
+            .iter()
+            .filter_map(|body| match &body.entity {
+                Entity::Player(player) => player.mining.map(|target| (body.id, target)),
+                _ => None,
+            })
+            .collect();
+
+        for (id, target) in miners {
+            self.mine_step(id, target, delta);
+        }
+    }
+
+    fn mine_step(&mut self, id: Id, target: Id, delta: f64) {
+        let (allowed, progress, room) = match self.galaxy.borrow_body(id) {
+            Some(
+                miner @ CelestialBody {
+                    entity: Entity::Player(player),
+                    ..
+                },
+            ) => (
+                self.galaxy.borrow_body(target).is_some_and(|asteroid| {
+                    mining::check_mine(player.id, miner, &player.inventory, asteroid).is_ok()
+                }),
+                player.mining_progress + mining::MINING_RATE * delta,
+                player.inventory.free_volume(),
+            ),
+            _ => return,
+        };
+
+        let mut extracted = Ores::new();
+        let mut depleted = false;
+        if allowed {
+            if let Some(CelestialBody {
+                entity: Entity::Asteroid(asteroid),
+                ..
+            }) = self.galaxy.borrow_body_mut(target)
+            {
+                mining::transfer(
+                    &mut asteroid.ores,
+                    &mut extracted,
+                    (progress as u32).min(room),
+                );
+                depleted = asteroid.ores.is_empty();
+            }
+        }
+
+        let moved = mining::total(&extracted);
+        let stopped = if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body_mut(id)
+        {
+            for (ore, amount) in extracted {
+                player.inventory.add(Item::Ore(ore), amount);
+            }
+            player.mining_progress = progress.fract();
+            if depleted {
+                player.ownings.retain(|owning| *owning != target);
+            }
+            let stopped = !allowed || depleted || player.inventory.free_volume() == 0;
+            if stopped {
+                player.mining = None;
+                player.mining_progress = 0f64;
+            }
+            stopped
+        } else {
+            true
+        };
+        if moved > 0 {
+            self.sync_bodies(&HashSet::from([id, target]));
+        }
+
+        if depleted {
+            log::info!("Asteroid {} depleted", target);
+            if let Some(asteroid) = self.galaxy.remove_by_id(target) {
+                for body in self.galaxy.celestials.iter_mut() {
+                    if body.gravity_center == target {
+                        body.gravity_center = asteroid.gravity_center;
+                        self.sync_pool.sync_body(body);
+                    }
+                }
+            }
+            self.emit(Event::BodyRemoved(target));
+        }
+
+        if moved > 0 {
+            self.send_inventory(id);
+        }
+        if stopped {
+            self.send_mining(id);
+        }
+    }
+
+    /// Queues a shot, resolved on the next `update` once every ship has
+    /// moved, so that its outcome only depends on the tick state.
+    pub fn fire(&mut self, id: Id, fire: Fire) -> Result<()> {
+        let target = match fire.target_or_direction {
+            FireTarget::Body(target) => {
+                let (shooter, target_body) =
+                    match (self.galaxy.borrow_body(id), self.galaxy.borrow_body(target)) {
+                        (Some(shooter), Some(target_body)) => (shooter, target_body),
+                        (None, _) => return Err(Error::BodyNotFound(id)),
+                        (_, None) => return Err(Error::BodyNotFound(target)),
+                    };
+                if target == id || target_body.entity.ship().is_none() {
+                    return Err(Error::InvalidTarget(target));
+                }
+                // Ships only lock onto what their sensors detect.
+                if let Entity::Player(player) = &shooter.entity {
+                    if (target_body.coords - shooter.coords).norm() > player.sensors.range() {
+                        return Err(Error::TooFar(target));
+                    }
+                }
+                ShotTarget::Body(target)
+            }
+            FireTarget::Direction(direction) => {
+                let direction = Vector3::from(direction[0], direction[1], direction[2]);
+                ShotTarget::Direction(direction / direction.norm())
+            }
+        };
+
+        match self.galaxy.borrow_body_mut(id) {
+            Some(CelestialBody {
+                entity: Entity::Player(player),
+                ..
+            }) => {
+                player.ship.check_fire()?;
+                player.ship.cooldown = fire.weapon.cooldown();
+            }
+            _ => return Err(Error::BodyNotFound(id)),
+        }
+
+        self.pending_shots.push(Shot {
+            shooter: id,
+            weapon: fire.weapon,
+            target,
+        });
+
+        Ok(())
+    }
+
+    fn update_combat(&mut self, delta: f64) {
+        let mut shots = std::mem::take(&mut self.pending_shots);
+        shots.sort_by_key(|shot| shot.shooter);
+
+        let mut destroyed = Vec::new();
+        for shot in shots {
+            // A ship destroyed earlier in the tick doesn't get to fire back.
+            if !destroyed.contains(&shot.shooter) {
+                if let Some(victim) = self.resolve_shot(&shot) {
+                    destroyed.push(victim);
+                }
+            }
+        }
+
+        for body in self.galaxy.celestials.iter_mut() {
+            if let Some(ship) = body.entity.ship_mut() {
+                ship.update(delta);
+                self.sync_pool.sync_body(body);
+            }
+        }
+    }
+
+    /// Applies `shot` to the first ship on its line of fire and returns the
+    /// ship if it got destroyed.
+    fn resolve_shot(&mut self, shot: &Shot) -> Option<Id> {
+        let origin = self.galaxy.borrow_body(shot.shooter)?.coords;
+        let direction = match shot.target {
+            ShotTarget::Body(target) => {
+                let local = self.galaxy.borrow_body(target)?.coords - origin;
+                let norm = local.norm();
+                if norm <= f64::EPSILON {
+                    return None;
+                }
+                local / norm
+            }
+            ShotTarget::Direction(direction) => direction,
+        };
+
+        let hit = self
+            .galaxy
+            .bodies_in_line_of_fire(origin, direction, shot.weapon.range())
+            .into_iter()
+            .find(|(_, body)| body.id != shot.shooter && body.entity.ship().is_some())
+            .map(|(_, body)| body.id)?;
+
+        let damage = shot.weapon.damage();
+        let body = self.galaxy.borrow_body_mut(hit)?;
+        let home = body.gravity_center;
+        let ship = body.entity.ship_mut()?;
+        ship.take_damage(damage);
+        let ship = ship.clone();
+        self.sync_pool.sync_body(body);
+
+        let info = GameInfo::Hit(HitInfo {
+            shooter: shot.shooter,
+            target: hit,
+            weapon: shot.weapon,
+            damage,
+            hull: ship.hull(),
+            shield: ship.shield(),
+        });
+        self.send_to(shot.shooter, info.clone());
+        self.send_to(hit, info);
+
+        if !ship.is_destroyed() {
+            return None;
+        }
+
+        log::info!("Ship {} destroyed by {}", hit, shot.shooter);
+
+        let info = GameInfo::Kill(KillInfo {
+            killer: shot.shooter,
+            victim: hit,
+            weapon: shot.weapon,
+        });
+        for body in self.galaxy.celestials.iter() {
+            if let Entity::Player(player) = &body.entity {
+                if body.gravity_center == home || body.id == shot.shooter {
+                    let _ = player.infos_sender.try_send(info.clone());
+                }
+            }
+        }
+
+        self.respawn(hit);
+        Some(hit)
+    }
+
+    /// Brings a destroyed ship back next to its home star, repaired.
+    fn respawn(&mut self, id: Id) {
+        let Some(mut body) = self.galaxy.remove_by_id(id) else {
+            return;
+        };
+        if let Some(home_star) = self.galaxy.borrow_body(body.gravity_center) {
+            body.coords = combat::respawn_coords(home_star);
+        }
+        if let Some(ship) = body.entity.ship_mut() {
+            *ship = Ship::default();
+        }
+        let mining = if let Entity::Player(player) = &mut body.entity {
+            player.mining_progress = 0f64;
+            player.mining.take().is_some()
+        } else {
+            false
+        };
+        self.sync_pool.sync_body(&body);
+        self.galaxy.celestials.insert(body);
+
+        if mining {
+            self.send_mining(id);
+        }
+    }
+
+    pub fn build(&mut self, id: Id, build: Build) -> Result<()> {
+        let builder = self.galaxy.borrow_body(id).ok_or(Error::BodyNotFound(id))?;
+        let player = match &builder.entity {
+            Entity::Player(player) => player,
+            _ => return Err(Error::BodyNotFound(id)),
+        };
+        let anchor = self
+            .galaxy
+            .borrow_body(build.anchor)
+            .ok_or(Error::BodyNotFound(build.anchor))?;
+        let anchored = self
+            .galaxy
+            .celestials
+            .iter()
+            .filter(|body| {
+                body.gravity_center == build.anchor && matches!(body.entity, Entity::Structure(_))
+            })
+            .count();
+
+        building::check_build(
+            player.id,
+            builder,
+            &player.inventory,
+            anchor,
+            build.kind,
+            anchored,
+        )?;
+
+        let player_id = player.id;
+        let coords = building::placement(anchor, build.kind, anchored);
+
+        let mut structure = self.sync_pool.new_structure(build.kind);
+        structure.owner = player_id;
+        structure.coords = coords;
+        structure.gravity_center = build.anchor;
+        structure.rotating_speed = building::STRUCTURE_ROTATING_SPEED;
+        let structure_id = structure.id;
+        self.spawn_body(structure);
+
+        if let Some(body) = self.galaxy.borrow_body_mut(id) {
+            if let Entity::Player(player) = &mut body.entity {
+                for stack in building::cost(build.kind) {
+                    player.inventory.remove(stack.item, stack.amount)?;
+                }
+                player.ownings.push(structure_id);
+            }
+            let body = body.clone();
+            self.sync_pool.sync_body(&body);
+        }
+
+        log::info!(
+            "Player {} built {:?} {} around {}",
+            player_id,
+            build.kind,
+            structure_id,
+            build.anchor
+        );
+
+        self.send_inventory(id);
+
+        Ok(())
+    }
+
+    pub fn trade(&mut self, id: Id, trade: Trade) -> Result<()> {
+        match trade {
+            Trade::Offer { to, give, want } => {
+                let from_body = self.galaxy.borrow_body(id).ok_or(Error::BodyNotFound(id))?;
+                let to_body = match self.galaxy.borrow_body(to) {
+                    Some(body) if to != id && matches!(body.entity, Entity::Player(_)) => body,
+                    _ => return Err(Error::BodyNotFound(to)),
+                };
+                if !trade::in_range(from_body, to_body) {
+                    return Err(Error::TooFar(to));
+                }
+                match &from_body.entity {
+                    Entity::Player(player) if player.inventory.contains(&give) => {}
+                    _ => return Err(Error::NotEnoughItems),
+                }
+
+                let offer = self.trades.offer(id, to, give, want)?;
+                self.send_to(id, GameInfo::Trade(offer.info(TradeStatus::Offered)));
+                self.send_to(to, GameInfo::Trade(offer.info(TradeStatus::Offered)));
+            }
+            Trade::Accept { offer } => {
+                let trade_offer = match self.trades.get(offer) {
+                    Some(trade_offer) if trade_offer.to == id => trade_offer.clone(),
+                    _ => return Err(Error::TradeNotFound(offer)),
+                };
+                self.exchange(&trade_offer)?;
+                self.trades.remove(offer);
+
+                for body_id in [trade_offer.from, trade_offer.to] {
+                    self.send_to(
+                        body_id,
+                        GameInfo::Trade(trade_offer.info(TradeStatus::Accepted)),
+                    );
+                    self.send_inventory(body_id);
+                }
+            }
+            Trade::Decline { offer } | Trade::Cancel { offer } => {
+                let status = match trade {
+                    Trade::Decline { .. } => TradeStatus::Declined,
+                    _ => TradeStatus::Cancelled,
+                };
+                let trade_offer = match self.trades.get(offer) {
+                    Some(trade_offer)
+                        if (status == TradeStatus::Declined && trade_offer.to == id)
+                            || (status == TradeStatus::Cancelled && trade_offer.from == id) =>
+                    {
+                        trade_offer.clone()
+                    }
+                    _ => return Err(Error::TradeNotFound(offer)),
+                };
+                self.trades.remove(offer);
+
+                for body_id in [trade_offer.from, trade_offer.to] {
+                    self.send_to(body_id, GameInfo::Trade(trade_offer.info(status.clone())));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies both sides of an offer at once. The two ships are synced
+    /// together so that the next save persists both or neither.
+    fn exchange(&mut self, offer: &TradeOffer) -> Result<()> {
+        let (from_body, to_body) = match (
+            self.galaxy.borrow_body(offer.from),
+            self.galaxy.borrow_body(offer.to),
+        ) {
+            (Some(from_body), Some(to_body)) => (from_body, to_body),
+            _ => return Err(Error::TradeNotFound(offer.id)),
+        };
+        if !trade::in_range(from_body, to_body) {
+            return Err(Error::TooFar(offer.from));
+        }
+
+        let (mut from_inventory, mut to_inventory) = match (&from_body.entity, &to_body.entity) {
+            (Entity::Player(from), Entity::Player(to)) => {
+                (from.inventory.clone(), to.inventory.clone())
+            }
+            _ => return Err(Error::TradeNotFound(offer.id)),
+        };
+
+        inventory::exchange(
+            &mut from_inventory,
+            &offer.give,
+            &mut to_inventory,
+            &offer.want,
+        )?;
+
+        for (body_id, inventory) in [(offer.from, from_inventory), (offer.to, to_inventory)] {
+            if let Some(body) = self.galaxy.borrow_body_mut(body_id) {
+                if let Entity::Player(player) = &mut body.entity {
+                    player.inventory = inventory;
+                }
+                let body = body.clone();
+                self.sync_pool.sync_body(&body);
+            }
+        }
+
+        log::info!(
+            "Trade {} between {} and {} done",
+            offer.id,
+            offer.from,
+            offer.to
+        );
+
+        Ok(())
+    }
+
+    fn send_to(&self, id: Id, info: GameInfo) {
+        if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body(id)
+        {
+            let _ = player.infos_sender.try_send(info);
+        }
+    }
+
+    fn send_inventory(&self, id: Id) {
+        if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body(id)
+        {
+            let _ = player
+                .infos_sender
+                .try_send(GameInfo::Inventory(InventoryInfo {
+                    items: player.inventory.stacks(),
+                    volume: player.inventory.volume(),
+                    capacity: CARGO_CAPACITY,
+                }));
+        }
+    }
+
+    fn send_mining(&self, id: Id) {
+        if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body(id)
+        {
+            let _ = player.infos_sender.try_send(GameInfo::Mining(MiningInfo {
+                target: player.mining,
+            }));
+        }
+    }
+
+    pub fn request_map(&self, id: Id) -> Result<()> {
+        match self.galaxy.borrow_body(id) {
+            Some(CelestialBody {
+                entity: Entity::Player(player),
+                ..
+            }) => {
+                let _ = player
+                    .infos_sender
+                    .try_send(GameInfo::Map(map::summary(&player.sensors)));
+                Ok(())
+            }
+            _ => match self.spectators.get(&id) {
+                Some(spectator) => {
+                    let _ = spectator
+                        .infos_sender
+                        .try_send(GameInfo::Map(map::summary(&spectator.sensors)));
+                    Ok(())
+                }
+                None => Err(Error::BodyNotFound(id)),
+            },
+        }
+    }
+
+    /// Starts watching the galaxy without a body. Spectators don't count
+    /// toward `max_players`.
+    pub fn spectate(
+        &mut self,
+        spectate: &Spectate,
+    ) -> Result<(Id, tokio::sync::mpsc::Receiver<GameInfo>)> {
+        if self.spectators.len() >= MAX_SPECTATORS {
+            return Err(Error::ServerFull);
+        }
+        self.check_spectated(spectate)?;
+
+        let (send, recv) = tokio::sync::mpsc::channel(1000);
+        let id = self.next_spectator_id;
+        self.next_spectator_id -= 1;
+        self.spectators.insert(id, Spectator::new(spectate, send));
+        log::info!("Spectator {} watching {:?}", id, spectate.follow);
+
+        Ok((id, recv))
+    }
+
+    pub fn retarget_spectator(&mut self, id: Id, spectate: &Spectate) -> Result<()> {
+        self.check_spectated(spectate)?;
+        let spectator = self
+            .spectators
+            .get_mut(&id)
+            .ok_or(Error::BodyNotFound(id))?;
+        spectator.retarget(spectate);
+        Ok(())
+    }
+
+    /// Players are only followed from when they are online.
+    fn check_spectated(&self, spectate: &Spectate) -> Result<()> {
+        match &spectate.follow {
+            SpectateTarget::Player(nickname)
+                if !self.galaxy.celestials.iter().any(|body| {
+                    matches!(&body.entity, Entity::Player(player) if &player.nickname == nickname)
+                }) =>
+            {
+                Err(Error::SpectatedPlayerNotFound(nickname.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn spectators_count(&self) -> usize {
+        self.spectators.len()
+    }
+
+    fn send_chat_history(&self, id: Id) {
+        if let Some(CelestialBody {
+            entity: Entity::Player(player),
+            gravity_center,
+            ..
+        }) = self.galaxy.borrow_body(id)
+        {
+            for info in self.chat_history.for_system(*gravity_center) {
+                let _ = player.infos_sender.try_send(GameInfo::Chat(info));
+            }
+        }
+    }
+
+    pub fn set_system_idle(&mut self, system_idle: Duration) {
+        self.system_idle = system_idle;
+    }
+
+    /// Seeds the systems of a galaxy which has no body yet. Others keep the
+    /// seed they were generated from.
+    pub fn set_generation_seed(&mut self, seed: u64) {
+        if self.sync_pool.body_next_id > 1 {
+            if seed != self.sync_pool.generation_seed {
+                log::warn!(
+                    "Galaxy generated from seed {}, keeping it over {}",
+                    self.sync_pool.generation_seed,
+                    seed
+                );
+            }
+            return;
+        }
+        self.sync_pool.generation_seed = seed;
+    }
+
+    /// Stars of the systems in the galaxy.
+    pub fn loaded_systems(&self) -> Vec<Id> {
+        self.systems.keys().copied().collect()
+    }
+
+    pub fn set_max_players(&mut self, max_players: usize) {
+        self.max_players = max_players;
+    }
+
+    pub fn set_shard(&mut self, shard: Option<ShardConfig>) {
+        self.shard = shard;
+    }
+
+    pub fn shard(&self) -> Option<&ShardConfig> {
+        self.shard.as_ref()
+    }
+
+    /// Queues the handoff of players who crossed into a peer's region.
+    fn update_departures(&mut self) {
+        let Some(shard) = &self.shard else {
+            return;
+        };
+        let now = Instant::now();
+
+        for body in self.galaxy.celestials.iter() {
+            let Entity::Player(player) = &body.entity else {
+                continue;
+            };
+            let Some(peer) = shard.owner_of(&body.coords) else {
+                continue;
+            };
+            match self.pending_handoffs.get(&body.id) {
+                Some(None) => continue,
+                Some(Some(retry)) if *retry > now => continue,
+                _ => {}
+            }
+
+            self.pending_handoffs.insert(body.id, None);
+            self.departures.push(Departure {
+                body: body.id,
+                peer: peer.clone(),
+                handoff: Handoff {
+                    nickname: player.nickname.clone(),
+                    coords: [body.coords.x, body.coords.y, body.coords.z],
+                    direction: [
+                        body.local_direction.x,
+                        body.local_direction.y,
+                        body.local_direction.z,
+                    ],
+                    speed: body.local_speed,
+                    items: player.inventory.stacks(),
+                    hull: player.ship.hull,
+                    shield: player.ship.shield,
+                },
+            });
+        }
+    }
+
+    pub fn take_departures(&mut self) -> Vec<Departure> {
+        std::mem::take(&mut self.departures)
+    }
+
+    /// The peer took `id` over: redirects the player there and lets it go.
+    pub async fn complete_departure(&mut self, id: Id, address: &str) -> Result<()> {
+        if self.pending_handoffs.remove(&id).is_none() {
+            // Left while being handed off.
+            return Ok(());
+        }
+        self.send_to(
+            id,
+            GameInfo::Redirect(RedirectInfo {
+                address: address.to_string(),
+            }),
+        );
+        self.leave(id).await?;
+        self.handed_off.insert(id);
+        Ok(())
+    }
+
+    pub fn cancel_departure(&mut self, id: Id) {
+        if let Some(retry) = self.pending_handoffs.get_mut(&id) {
+            *retry = Some(Instant::now() + HANDOFF_RETRY_DELAY);
+        }
+    }
+
+    /// Keeps a player handed off by a peer as one of ours, to be loaded
+    /// when it logs in, journaled right away so that it survives a crash
+    /// before the next save.
+    pub async fn accept_handoff(&mut self, handoff: &Handoff) -> Result<()> {
+        protocol::validate_nickname(&handoff.nickname)?;
+        let finite = handoff
+            .coords
+            .iter()
+            .chain(handoff.direction.iter())
+            .chain([handoff.speed, handoff.hull, handoff.shield].iter())
+            .all(|value| value.is_finite());
+        if !finite {
+            return Err(Error::HandoffRefused("not finite".to_string()));
+        }
+
+        let coords = Vector3::from(handoff.coords[0], handoff.coords[1], handoff.coords[2]);
+        if !self
+            .shard
+            .as_ref()
+            .is_some_and(|shard| shard.region.contains(&coords))
+        {
+            return Err(Error::HandoffRefused(format!(
+                "{:?} is out of this shard",
+                handoff.coords
+            )));
+        }
+        let online = self.galaxy.celestials.iter().any(|body| {
+            matches!(&body.entity, Entity::Player(player) if player.nickname == handoff.nickname)
+        });
+        if online {
+            return Err(Error::PlayerAlreadyAuthenticated);
+        }
+
+        let (send, _) = tokio::sync::mpsc::channel(1);
+        let mut player = match self
+            .sync_pool
+            .get_player(&handoff.nickname, send.clone())
+            .await
+        {
+            Ok(player) => player,
+            // New to this shard, without a home system here.
+            Err(Error::DbLoadPlayerByNicknameNotFound) => {
+                self.sync_pool.new_player(&handoff.nickname, send)
+            }
+            Err(err) => return Err(err),
+        };
+
+        player.coords = coords;
+        player.local_direction = Vector3::from(
+            handoff.direction[0],
+            handoff.direction[1],
+            handoff.direction[2],
+        );
+        player.local_speed = handoff.speed;
+        if let Entity::Player(entity) = &mut player.entity {
+            entity.inventory = Inventory::from_stacks(&handoff.items);
+            entity.ship.hull = handoff.hull.clamp(0f64, combat::MAX_HULL);
+            entity.ship.shield = handoff.shield.clamp(0f64, combat::MAX_SHIELD);
+        }
+        self.sync_pool.sync_body(&player);
+        self.journal
+            .record(Record::Body(Box::new(BodyRecord::from_body(&player))));
+        self.journal.flush()?;
+
+        log::info!("{} handed off to this shard", handoff.nickname);
+        Ok(())
+    }
+
+    pub fn players_count(&self) -> usize {
+        self.galaxy
+            .celestials
+            .iter()
+            .filter(|c| matches!(c.entity, Entity::Player(_)))
+            .count()
+    }
+
+    pub fn player_id(&self, body_id: Id) -> Option<Id> {
+        match self.galaxy.borrow_body(body_id) {
+            Some(CelestialBody {
+                entity: Entity::Player(player),
+                ..
+            }) => Some(player.id),
+            _ => None,
+        }
+    }
+
     pub fn borrow_galaxy(&self) -> &Galaxy {
         &self.galaxy
     }
@@ -152,9 +1586,7 @@ impl Instance {
         &mut self,
         nickname: String,
     ) -> Result<(Id, tokio::sync::mpsc::Receiver<GameInfo>)> {
-        if nickname.is_empty() || !nickname.is_printable() {
-            return Err(Error::InvalidNickname);
-        }
+        protocol::validate_nickname(&nickname)?;
 
         for celestial in &self.galaxy.celestials {
             if let Entity::Player(player) = &celestial.entity {
@@ -167,32 +1599,61 @@ impl Instance {
         let (send, recv) = tokio::sync::mpsc::channel(1000);
         let player = self.sync_pool.get_player(&nickname, send).await?;
 
-        let star = self.sync_pool.get_body(player.gravity_center).await?;
-        let rotatings = self.sync_pool.get_rotatings(star.id).await?;
+        // Players saved here may have been handed off since.
+        if let Some(peer) = self
+            .shard
+            .as_ref()
+            .and_then(|shard| shard.owner_of(&player.coords))
+        {
+            return Err(Error::PlayerHandedOff(peer.address.clone()));
+        }
+
+        // Players handed off to this shard may have no home system here.
+        if player.gravity_center != Id::MAX {
+            self.load_system(player.gravity_center).await?;
+        }
 
         let id = player.id;
 
         self.galaxy.celestials.insert(player);
 
-        for rotating in rotatings {
-            self.galaxy.celestials.insert(rotating);
-        }
-
         Ok((id, recv))
     }
 
     pub async fn leave(&mut self, id: Id) -> Result<()> {
         log::info!("Leave for {}", id);
+
+        if self.spectators.remove(&id).is_some() {
+            return Ok(());
+        }
+        self.pending_handoffs.remove(&id);
+
+        for offer in self.trades.remove_involving(id) {
+            let other = if offer.from == id {
+                offer.to
+            } else {
+                offer.from
+            };
+            self.send_to(other, GameInfo::Trade(offer.info(TradeStatus::Cancelled)));
+        }
+
         let maybe_player = self.galaxy.celestials.iter_mut().find(|c| c.id == id);
 
         if let Some(player) = maybe_player {
             let player = player.clone();
+            self.handed_off.remove(&id);
+            if let Entity::Player(entity) = &player.entity {
+                self.emit(Event::PlayerLeft {
+                    body: id,
+                    player: entity.id,
+                    nickname: entity.nickname.clone(),
+                });
+            }
             let maybe_removed = self.galaxy.celestials.remove(&player);
 
             if let Some(mut removed) = maybe_removed {
                 if let Entity::Player(player) = &mut removed.entity {
                     player.actions.clear();
-                    self.sync_pool.sync_body(&removed);
                     // self.sync_pool.save_and_unload_player(removed.id).await?;
                 } else {
                     unreachable!()
@@ -201,6 +1662,8 @@ impl Instance {
                 log::error!("COULD NOT REMOVE PLAYER FROM TREE");
                 return Err(Error::Error);
             }
+        } else if self.handed_off.remove(&id) {
+            return Ok(());
         } else {
             log::error!(
                 "Leave called but player {} not found. Container size is {}",
@@ -217,6 +1680,10 @@ impl Instance {
         &mut self,
         nickname: &String,
     ) -> Result<(Id, tokio::sync::mpsc::Receiver<GameInfo>)> {
+        if self.players_count() >= self.max_players {
+            return Err(Error::ServerFull);
+        }
+
         let maybe_id = self.load_player_by_nickname(nickname.clone()).await;
 
         match maybe_id {
@@ -224,7 +1691,7 @@ impl Instance {
                 log::info!("New player, generating spawning bodies...");
                 let (star, asteroids) = self.gen_system().await?;
                 let player_coords = {
-                    let mut rng = ChaCha8Rng::seed_from_u64(0);
+                    let mut rng = ChaCha8Rng::seed_from_u64(self.sync_pool.generation_seed);
                     let phi = rng.gen_range(-TAU..TAU);
                     let theta = rng.gen_range(PI - 0.1..PI + 0.1);
                     let distance = rng.gen_range(1200f64..1750f64);
@@ -232,32 +1699,93 @@ impl Instance {
                 };
 
                 let star_id = star.id;
-                self.galaxy.celestials.insert(star);
+                let star_coords = star.coords;
+                let count = |is_kind: fn(&Entity) -> bool| {
+                    asteroids
+                        .iter()
+                        .filter(|body| is_kind(&body.entity))
+                        .count() as rhai::INT
+                };
+                let system = scripting::to_map([
+                    ("star", Dynamic::from_int(star_id.into())),
+                    (
+                        "coords",
+                        Dynamic::from_array(vec![
+                            star_coords.x.into(),
+                            star_coords.y.into(),
+                            star_coords.z.into(),
+                        ]),
+                    ),
+                    (
+                        "planets",
+                        Dynamic::from_int(count(|entity| matches!(entity, Entity::Planet(_)))),
+                    ),
+                    (
+                        "moons",
+                        Dynamic::from_int(count(|entity| matches!(entity, Entity::Moon(_)))),
+                    ),
+                    (
+                        "asteroids",
+                        Dynamic::from_int(count(|entity| matches!(entity, Entity::Asteroid(_)))),
+                    ),
+                    (
+                        "npcs",
+                        Dynamic::from_int(count(|entity| matches!(entity, Entity::Npc(_)))),
+                    ),
+                ]);
+                self.spawn_body(star);
 
                 for body in asteroids {
-                    self.galaxy.celestials.insert(body);
+                    self.spawn_body(body);
                 }
+                self.systems.insert(star_id, Instant::now());
+                self.add_star(star_id, star_coords);
+
+                self.run_hook("on_generate_system", (system,));
 
                 let (send, recv) = tokio::sync::mpsc::channel::<GameInfo>(1000);
 
                 let mut player = self.sync_pool.new_player(&nickname, send);
                 player.coords = player_coords;
+                if let Entity::Player(entity) = &mut player.entity {
+                    entity.inventory.add(Item::Credits, STARTING_CREDITS);
+                }
                 player.local_speed = 100f64;
                 player.gravity_center = star_id;
 
                 let id = player.id;
 
-                self.galaxy.celestials.insert(player);
+                self.spawn_body(player);
+                self.player_joined(id, true);
 
                 Ok((id, recv))
             }
-            Ok(id) => Ok(id),
+            Ok((id, recv)) => {
+                self.player_joined(id, false);
+                Ok((id, recv))
+            }
             Err(err) => Err(err),
         }
     }
 
+    fn player_joined(&mut self, id: Id, first_login: bool) {
+        let Some(CelestialBody {
+            entity: Entity::Player(player),
+            ..
+        }) = self.galaxy.borrow_body(id)
+        else {
+            return;
+        };
+        self.emit(Event::PlayerJoined {
+            body: id,
+            player: player.id,
+            nickname: player.nickname.clone(),
+            first_login,
+        });
+    }
+
     pub async fn gen_system(&mut self) -> Result<(CelestialBody, Vec<CelestialBody>)> {
-        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut rng = ChaCha8Rng::seed_from_u64(self.sync_pool.generation_seed);
         let phi = rng.gen_range(-TAU..TAU);
         let theta = rng.gen_range(PI - 0.1..PI + 0.1);
         let distance = rng.gen_range(10000f64..100000f64);
@@ -322,8 +1850,22 @@ impl Instance {
             asteroid.gravity_center = star.id;
         }
 
+        for asteroid in &mut asteroids {
+            if let Entity::Asteroid(entity) = &mut asteroid.entity {
+                entity.ores = mining::generate_ores(&mut rng);
+            }
+        }
+
         bodies.append(&mut asteroids);
 
+        for kind in BrainKind::ALL {
+            let mut npc = self.sync_pool.new_npc(kind);
+            npc.coords = brain::spawn_coords(coords, &mut rng);
+            npc.gravity_center = star.id;
+            npc.local_speed = NPC_SPEED;
+            bodies.push(npc);
+        }
+
         Ok((star, bodies))
     }
 }
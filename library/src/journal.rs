@@ -0,0 +1,370 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::game::brain::BrainKind;
+use crate::game::celestial_body::CelestialBody;
+use crate::game::combat::Ship;
+use crate::game::entity::asteroid::Asteroid;
+use crate::game::entity::moon::Moon;
+use crate::game::entity::npc::Npc;
+use crate::game::entity::planet::Planet;
+use crate::game::entity::player::Player;
+use crate::game::entity::star::Star;
+use crate::game::entity::structure::{Structure, StructureKind};
+use crate::game::entity::Entity;
+use crate::game::inventory::{Inventory, ItemStack};
+use crate::game::mining::Ores;
+use crate::game::repr::Vector3;
+use crate::game::sensors::Sensors;
+use crate::protocol::BodyInfo;
+use crate::Id;
+use crate::Result;
+
+pub const JOURNAL_EXTENSION: &str = "journal";
+
+/// What a body is saved as, apart from what is rebuilt on load like the
+/// ownings of a player or its connection.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EntityRecord {
+    Player {
+        id: Id,
+        nickname: String,
+        items: Vec<ItemStack>,
+        hull: f64,
+        shield: f64,
+        /// Left out while only the bodies already discovered moved.
+        discovered: Option<Vec<BodyInfo>>,
+        systems: BTreeMap<Id, u64>,
+    },
+    Star {
+        id: Id,
+    },
+    Planet {
+        id: Id,
+    },
+    Moon {
+        id: Id,
+    },
+    Asteroid {
+        id: Id,
+        ores: Ores,
+    },
+    Structure {
+        id: Id,
+        kind: StructureKind,
+    },
+    Npc {
+        id: Id,
+        brain: BrainKind,
+        hull: f64,
+        shield: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BodyRecord {
+    pub id: Id,
+    pub owner: Id,
+    pub coords: [f64; 3],
+    pub direction: [f64; 3],
+    pub local_speed: f64,
+    pub angular_speed: f64,
+    pub rotating_speed: f64,
+    pub gravity_center: Id,
+    pub entity: EntityRecord,
+}
+
+fn ship(hull: f64, shield: f64) -> Ship {
+    Ship {
+        hull,
+        shield,
+        ..Default::default()
+    }
+}
+
+impl BodyRecord {
+    pub fn from_body(body: &CelestialBody) -> BodyRecord {
+        BodyRecord::snapshot(body, true)
+    }
+
+    fn snapshot(body: &CelestialBody, with_discoveries: bool) -> BodyRecord {
+        let entity = match &body.entity {
+            Entity::Player(player) => EntityRecord::Player {
+                id: player.id,
+                nickname: player.nickname.clone(),
+                items: player.inventory.stacks(),
+                hull: player.ship.hull,
+                shield: player.ship.shield,
+                discovered: with_discoveries
+                    .then(|| player.sensors.discovered.values().cloned().collect()),
+                systems: player.sensors.systems.clone(),
+            },
+            Entity::Star(star) => EntityRecord::Star { id: star.id },
+            Entity::Planet(planet) => EntityRecord::Planet { id: planet.id },
+            Entity::Moon(moon) => EntityRecord::Moon { id: moon.id },
+            Entity::Asteroid(asteroid) => EntityRecord::Asteroid {
+                id: asteroid.id,
+                ores: asteroid.ores.clone(),
+            },
+            Entity::Structure(structure) => EntityRecord::Structure {
+                id: structure.id,
+                kind: structure.kind,
+            },
+            Entity::Npc(npc) => EntityRecord::Npc {
+                id: npc.id,
+                brain: npc.brain.kind(),
+                hull: npc.ship.hull,
+                shield: npc.ship.shield,
+            },
+        };
+
+        BodyRecord {
+            id: body.id,
+            owner: body.owner,
+            coords: [body.coords.x, body.coords.y, body.coords.z],
+            direction: [
+                body.local_direction.x,
+                body.local_direction.y,
+                body.local_direction.z,
+            ],
+            local_speed: body.local_speed,
+            angular_speed: body.angular_speed,
+            rotating_speed: body.rotating_speed,
+            gravity_center: body.gravity_center,
+            entity,
+        }
+    }
+
+    /// The body back, players without a connection until they log in.
+    pub fn into_body(self) -> CelestialBody {
+        let entity = match self.entity {
+            EntityRecord::Player {
+                id,
+                nickname,
+                items,
+                hull,
+                shield,
+                discovered,
+                systems,
+            } => {
+                let (send, _) = tokio::sync::mpsc::channel(1);
+                let mut player = Player::new(id, nickname, send);
+                player.inventory = Inventory::from_stacks(&items);
+                player.ship = ship(hull, shield);
+                *player.sensors = Sensors::from_discovered(
+                    discovered
+                        .into_iter()
+                        .flatten()
+                        .map(|info| (info.id, info))
+                        .collect(),
+                    systems,
+                );
+                Entity::Player(player)
+            }
+            EntityRecord::Star { id } => Entity::Star(Star { id }),
+            EntityRecord::Planet { id } => Entity::Planet(Planet { id }),
+            EntityRecord::Moon { id } => Entity::Moon(Moon { id }),
+            EntityRecord::Asteroid { id, ores } => {
+                let mut asteroid = Asteroid::new(id);
+                asteroid.ores = ores;
+                Entity::Asteroid(asteroid)
+            }
+            EntityRecord::Structure { id, kind } => Entity::Structure(Structure::new(id, kind)),
+            EntityRecord::Npc {
+                id,
+                brain,
+                hull,
+                shield,
+            } => {
+                let mut npc = Npc::new(id, brain.brain());
+                npc.ship = ship(hull, shield);
+                Entity::Npc(npc)
+            }
+        };
+
+        CelestialBody::new(
+            self.id,
+            self.owner,
+            Vector3::from(self.coords[0], self.coords[1], self.coords[2]),
+            Vector3::from(self.direction[0], self.direction[1], self.direction[2]),
+            self.local_speed,
+            self.angular_speed,
+            self.rotating_speed,
+            self.gravity_center,
+            entity,
+        )
+    }
+
+    fn discovered_mut(&mut self) -> Option<&mut Option<Vec<BodyInfo>>> {
+        match &mut self.entity {
+            EntityRecord::Player { discovered, .. } => Some(discovered),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Record {
+    Body(Box<BodyRecord>),
+    Removed(Id),
+}
+
+/// Records written at once, numbered so that the ones a save already
+/// covers are skipped on replay.
+#[derive(Serialize, Deserialize)]
+struct Batch {
+    sequence: u32,
+    records: Vec<Record>,
+}
+
+/// Append-only log of what changed since the last save, one JSON batch per
+/// line. It is flushed much more often than the instance is saved, replayed
+/// when the instance is opened again after a crash and emptied on save.
+pub struct Journal {
+    path: String,
+    file: File,
+    sequence: u32,
+    pending: Vec<Record>,
+    // Last journaled state of the players, without their discoveries but
+    // with the ids of the bodies discovered, as players are only journaled
+    // again once they changed.
+    players: HashMap<Id, (BodyRecord, Vec<Id>)>,
+}
+
+impl Journal {
+    pub fn path_of(db_path: &str) -> String {
+        format!("{}.{}", db_path, JOURNAL_EXTENSION)
+    }
+
+    /// Opens the journal at `path`, created if missing, and returns the
+    /// records of the batches numbered after `checkpoint`.
+    pub fn open(path: &str, checkpoint: u32) -> Result<(Journal, Vec<Record>)> {
+        let error = |err: std::io::Error| Error::JournalError(path.to_string(), err.to_string());
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(error(err)),
+        };
+
+        let mut sequence = checkpoint;
+        let mut records = Vec::new();
+        let mut discoveries = HashMap::new();
+        for line in content.lines() {
+            // A crash may have cut the last batch short.
+            let Ok(batch) = serde_json::from_str::<Batch>(line) else {
+                log::warn!("Ignoring the end of journal {}: torn batch", path);
+                break;
+            };
+            for mut record in batch.records {
+                if let Record::Body(body) = &mut record {
+                    let id = body.id;
+                    if let Some(discovered) = body.discovered_mut() {
+                        match discovered {
+                            Some(discovered) => {
+                                discoveries.insert(id, discovered.clone());
+                            }
+                            None => *discovered = discoveries.get(&id).cloned(),
+                        }
+                    }
+                }
+                if batch.sequence > checkpoint {
+                    records.push(record);
+                }
+            }
+            sequence = sequence.max(batch.sequence);
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(error)?;
+
+        Ok((
+            Journal {
+                path: path.to_string(),
+                file,
+                sequence,
+                pending: Vec::new(),
+                players: HashMap::new(),
+            },
+            records,
+        ))
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Number of the last batch written.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn record(&mut self, record: Record) {
+        self.pending.push(record);
+    }
+
+    /// Records `player` if it changed since it was last journaled.
+    pub fn record_player(&mut self, player: &CelestialBody) {
+        let Entity::Player(entity) = &player.entity else {
+            return;
+        };
+        let ids: Vec<Id> = entity.sensors.discovered.keys().copied().collect();
+        let mut record = BodyRecord::snapshot(player, false);
+
+        let last = self.players.get(&player.id);
+        let same_discoveries = last.is_some_and(|(_, last_ids)| *last_ids == ids);
+        if same_discoveries && last.is_some_and(|(last, _)| *last == record) {
+            return;
+        }
+
+        self.players.insert(player.id, (record.clone(), ids));
+        if !same_discoveries {
+            record = BodyRecord::from_body(player);
+        }
+        self.pending.push(Record::Body(Box::new(record)));
+    }
+
+    pub fn forget_player(&mut self, id: Id) {
+        self.players.remove(&id);
+    }
+
+    /// Writes the pending records to disk as one batch.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let batch = Batch {
+            sequence: self.sequence + 1,
+            records: std::mem::take(&mut self.pending),
+        };
+        let mut line = serde_json::to_vec(&batch).unwrap();
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .and_then(|()| self.file.sync_data())
+            .map_err(|err| Error::JournalError(self.path.clone(), err.to_string()))?;
+
+        self.sequence = batch.sequence;
+        Ok(())
+    }
+
+    /// Forgets every record, once saved.
+    pub fn truncate(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.file
+            .set_len(0)
+            .and_then(|()| self.file.sync_data())
+            .map_err(|err| Error::JournalError(self.path.clone(), err.to_string()))?;
+        // Players are journaled whole again, their discoveries included.
+        self.players.clear();
+        Ok(())
+    }
+}
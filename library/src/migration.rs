@@ -0,0 +1,360 @@
+//! Versions of the galaxy file schema and the steps between them.
+
+use crate::error::Error;
+use crate::game::mining::Ore;
+use crate::sql_database::{create_table_statements, rebuild_table_statements, SqlDatabase};
+use crate::Result;
+
+/// Version of the schema this server writes.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// Brings the schema of `db` up to [`SCHEMA_VERSION`], one version at a time.
+pub(crate) async fn migrate(db: &mut SqlDatabase) -> Result<()> {
+    let version = db.schema_version().await?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::DbSchemaTooNew(version, SCHEMA_VERSION));
+    }
+
+    for next in version + 1..=SCHEMA_VERSION {
+        log::info!("Migrating the galaxy schema to version {}", next);
+        db.migrate_to(next, step(next)?).await?;
+    }
+
+    if version < SCHEMA_VERSION {
+        for orphan in db.foreign_key_check().await? {
+            log::warn!("Inconsistent galaxy file, {}", orphan);
+        }
+    }
+    Ok(())
+}
+
+/// Statements taking the schema from `version - 1` to `version`.
+fn step(version: u32) -> Result<Vec<String>> {
+    match version {
+        1 => create_tables(),
+        2 => add_foreign_keys(),
+        3 => add_star_systems(),
+        4 => add_galaxy(),
+        _ => unreachable!("no migration to schema version {}", version),
+    }
+}
+
+/// Version 1: the tables as they were when versioning started. Files from
+/// before that get the tables they miss.
+fn create_tables() -> Result<Vec<String>> {
+    let mut statements = Vec::new();
+    statements.extend(create_table_statements(
+        "Body",
+        vec![
+            // "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            "id INTEGER PRIMARY KEY",
+            "owner INTEGER",
+            "coordinate_x REAL NOT NULL",
+            "coordinate_y REAL NOT NULL",
+            "coordinate_z REAL NOT NULL",
+            "local_direction_x REAL NOT NULL",
+            "local_direction_y REAL NOT NULL",
+            "local_direction_z REAL NOT NULL",
+            "local_speed REAL",
+            "angular_speed REAL",
+            "rotating_speed REAL",
+            "gravity_center INTEGER",
+        ],
+        vec!["id", "owner", "gravity_center"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Player",
+        vec![
+            // "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            "id INTEGER PRIMARY KEY",
+            "nickname TEXT",
+            "body_id INTEGER",
+        ],
+        vec!["id", "body_id", "nickname"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Star",
+        vec![
+            // "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER",
+        ],
+        vec!["id", "body_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Planet",
+        vec![
+            // "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER",
+        ],
+        vec!["id", "body_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Moon",
+        vec![
+            // "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER",
+        ],
+        vec!["id", "body_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Asteroid",
+        vec![
+            // "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER",
+        ],
+        vec!["id", "body_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Npc",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER",
+            "brain TEXT NOT NULL",
+            "hull REAL NOT NULL",
+            "shield REAL NOT NULL",
+        ],
+        vec!["body_id"],
+    )?);
+
+    let mut ore_columns = vec!["id INTEGER PRIMARY KEY".to_string()];
+    for ore in Ore::ALL {
+        ore_columns.push(format!("{} INTEGER NOT NULL DEFAULT 0", ore.column()));
+    }
+    let ore_columns: Vec<&str> = ore_columns.iter().map(String::as_str).collect();
+
+    statements.extend(create_table_statements(
+        "Structure",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER",
+            "kind TEXT NOT NULL",
+        ],
+        vec!["body_id"],
+    )?);
+
+    statements.extend(create_table_statements("AsteroidOre", ore_columns, vec![])?);
+
+    statements.extend(create_table_statements(
+        "Inventory",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "player_id INTEGER NOT NULL",
+            "item TEXT NOT NULL",
+            "amount INTEGER NOT NULL",
+        ],
+        vec!["player_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Ship",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "hull REAL NOT NULL",
+            "shield REAL NOT NULL",
+        ],
+        vec![],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Discovery",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "player_id INTEGER NOT NULL",
+            "body_id INTEGER NOT NULL",
+            "info TEXT NOT NULL",
+        ],
+        vec!["player_id", "body_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "DiscoveredSystem",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "player_id INTEGER NOT NULL",
+            "star_id INTEGER NOT NULL",
+            "discovered_at INTEGER NOT NULL",
+        ],
+        vec!["player_id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Chat",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "channel TEXT NOT NULL",
+            "system_id INTEGER",
+            "sender TEXT NOT NULL",
+            "message TEXT NOT NULL",
+            "timestamp INTEGER NOT NULL",
+        ],
+        vec!["id"],
+    )?);
+
+    statements.extend(create_table_statements(
+        "Journal",
+        vec!["id INTEGER PRIMARY KEY", "sequence INTEGER NOT NULL"],
+        vec![],
+    )?);
+
+    Ok(statements)
+}
+
+/// Version 2: rows reference their body or player through deferred foreign
+/// keys, checked when a save commits.
+fn add_foreign_keys() -> Result<Vec<String>> {
+    let mut statements = Vec::new();
+    statements.extend(rebuild_table_statements(
+        "Body",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "owner INTEGER REFERENCES Player ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED",
+            "coordinate_x REAL NOT NULL",
+            "coordinate_y REAL NOT NULL",
+            "coordinate_z REAL NOT NULL",
+            "local_direction_x REAL NOT NULL",
+            "local_direction_y REAL NOT NULL",
+            "local_direction_z REAL NOT NULL",
+            "local_speed REAL",
+            "angular_speed REAL",
+            "rotating_speed REAL",
+            "gravity_center INTEGER REFERENCES Body ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED",
+        ],
+        vec!["id", "owner", "gravity_center"],
+    )?);
+
+    statements.extend(rebuild_table_statements(
+        "Player",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "nickname TEXT",
+            "body_id INTEGER REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+        ],
+        vec!["id", "body_id", "nickname"],
+    )?);
+
+    for table in ["Star", "Planet", "Moon", "Asteroid"] {
+        statements.extend(rebuild_table_statements(
+            table,
+            vec![
+                "id INTEGER PRIMARY KEY",
+                "body_id INTEGER REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            ],
+            vec!["id", "body_id"],
+        )?);
+    }
+
+    statements.extend(rebuild_table_statements(
+        "Npc",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "brain TEXT NOT NULL",
+            "hull REAL NOT NULL",
+            "shield REAL NOT NULL",
+        ],
+        vec!["body_id"],
+    )?);
+
+    statements.extend(rebuild_table_statements(
+        "Structure",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "body_id INTEGER REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "kind TEXT NOT NULL",
+        ],
+        vec!["body_id"],
+    )?);
+
+    let mut ore_columns = vec![
+        "id INTEGER PRIMARY KEY REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED"
+            .to_string(),
+    ];
+    for ore in Ore::ALL {
+        ore_columns.push(format!("{} INTEGER NOT NULL DEFAULT 0", ore.column()));
+    }
+    let ore_columns: Vec<&str> = ore_columns.iter().map(String::as_str).collect();
+    statements.extend(rebuild_table_statements(
+        "AsteroidOre",
+        ore_columns,
+        vec![],
+    )?);
+
+    statements.extend(rebuild_table_statements(
+        "Inventory",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "player_id INTEGER NOT NULL REFERENCES Player ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "item TEXT NOT NULL",
+            "amount INTEGER NOT NULL",
+        ],
+        vec!["player_id"],
+    )?);
+
+    statements.extend(rebuild_table_statements(
+        "Ship",
+        vec![
+            "id INTEGER PRIMARY KEY REFERENCES Player ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "hull REAL NOT NULL",
+            "shield REAL NOT NULL",
+        ],
+        vec![],
+    )?);
+
+    // Discoveries outlive the bodies they describe, they only follow players.
+    statements.extend(rebuild_table_statements(
+        "Discovery",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "player_id INTEGER NOT NULL REFERENCES Player ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "body_id INTEGER NOT NULL",
+            "info TEXT NOT NULL",
+        ],
+        vec!["player_id", "body_id"],
+    )?);
+
+    statements.extend(rebuild_table_statements(
+        "DiscoveredSystem",
+        vec![
+            "id INTEGER PRIMARY KEY",
+            "player_id INTEGER NOT NULL REFERENCES Player ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "star_id INTEGER NOT NULL",
+            "discovered_at INTEGER NOT NULL",
+        ],
+        vec!["player_id"],
+    )?);
+    Ok(statements)
+}
+
+/// Version 3: when the bodies of each system were last saved, to move them
+/// along their orbits for the time they were unloaded.
+fn add_star_systems() -> Result<Vec<String>> {
+    let mut statements = Vec::new();
+    statements.extend(create_table_statements(
+        "StarSystem",
+        vec![
+            "id INTEGER PRIMARY KEY REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED",
+            "saved_at INTEGER NOT NULL",
+        ],
+        vec![],
+    )?);
+    Ok(statements)
+}
+
+/// Version 4: the seed the systems of the galaxy are generated from.
+fn add_galaxy() -> Result<Vec<String>> {
+    create_table_statements(
+        "Galaxy",
+        vec!["id INTEGER PRIMARY KEY", "generation_seed INTEGER NOT NULL"],
+        vec![],
+    )
+}
@@ -1,10 +1,44 @@
+use is_printable::IsPrintable;
 use serde::{Deserialize, Serialize};
 
-use crate::Id;
+use crate::error::Error;
+use crate::game::chat;
+use crate::game::combat::Weapon;
+use crate::game::entity::structure::StructureKind;
+use crate::game::inventory::ItemStack;
+use crate::game::repr::Vector3;
+use crate::{Id, Result};
+
+pub const MAX_NICKNAME_LEN: usize = 32;
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MAX_TRADE_STACKS: usize = 16;
+pub const MAX_SPECTATOR_RANGE: f64 = 20000f64;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Login {
     pub nickname: String,
+    #[serde(default)]
+    pub version: u32,
+    /// Instance to join when the connection URL doesn't name one.
+    #[serde(default)]
+    pub instance: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SpectateTarget {
+    Player(String),
+    Point([f64; 3]),
+}
+
+/// Read-only login, or change of what is watched once spectating.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Spectate {
+    #[serde(default)]
+    pub version: u32,
+    pub follow: SpectateTarget,
+    pub range: f64,
+    #[serde(default)]
+    pub instance: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -13,30 +47,349 @@ pub struct ShipState {
     pub direction: [f64; 3],
 }
 
+impl ShipState {
+    /// Unit vector the ship wants to thrust along, if any.
+    pub fn heading(&self) -> Option<Vector3> {
+        if !self.throttle_up {
+            return None;
+        }
+        let wanted = Vector3::from(self.direction[0], self.direction[1], self.direction[2]);
+        let norm = wanted.norm();
+        if norm.is_finite() && norm > f64::EPSILON {
+            Some(wanted / norm)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatChannel {
+    Global,
+    System,
+    Direct(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Chat {
+    pub channel: ChatChannel,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Claim {
+    pub target: Id,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mine {
+    pub target: Id,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Build {
+    pub kind: StructureKind,
+    pub anchor: Id,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum FireTarget {
+    Body(Id),
+    Direction([f64; 3]),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Fire {
+    pub weapon: Weapon,
+    pub target_or_direction: FireTarget,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Trade {
+    Offer {
+        to: Id,
+        give: Vec<ItemStack>,
+        want: Vec<ItemStack>,
+    },
+    Accept {
+        offer: Id,
+    },
+    Decline {
+        offer: Id,
+    },
+    Cancel {
+        offer: Id,
+    },
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum PlayerAction {
     Login(Login),
+    Spectate(Spectate),
     ShipState(ShipState),
+    Chat(Chat),
+    Claim(Claim),
+    Mine(Mine),
+    Trade(Trade),
+    Build(Build),
+    Fire(Fire),
+    RequestMap,
+}
+
+pub fn validate_nickname(nickname: &str) -> Result<()> {
+    if nickname.is_empty()
+        || nickname.chars().count() > MAX_NICKNAME_LEN
+        || !nickname.is_printable()
+    {
+        return Err(Error::InvalidNickname);
+    }
+    Ok(())
+}
+
+impl PlayerAction {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            // Nicknames are checked by `Instance::authenticate`, which answers
+            // with `LoginResult::InvalidNickname` instead of a strike.
+            PlayerAction::Login(_) => Ok(()),
+            PlayerAction::Spectate(spectate) => {
+                if !(spectate.range > 0f64 && spectate.range <= MAX_SPECTATOR_RANGE) {
+                    return Err(Error::InvalidAction(
+                        "Spectate.range is out of bounds".to_string(),
+                    ));
+                }
+                match &spectate.follow {
+                    SpectateTarget::Player(nickname) => validate_nickname(nickname),
+                    SpectateTarget::Point(point) if point.iter().any(|c| !c.is_finite()) => Err(
+                        Error::InvalidAction("Spectate.follow is not finite".to_string()),
+                    ),
+                    SpectateTarget::Point(_) => Ok(()),
+                }
+            }
+            PlayerAction::ShipState(ship_state) => {
+                if ship_state.direction.iter().any(|c| !c.is_finite()) {
+                    return Err(Error::InvalidAction(
+                        "ShipState.direction is not finite".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            PlayerAction::Chat(chat) => {
+                if let ChatChannel::Direct(nickname) = &chat.channel {
+                    validate_nickname(nickname)?;
+                }
+                chat::validate_message(&chat.message)
+            }
+            PlayerAction::Trade(Trade::Offer { give, want, .. }) => {
+                if give.is_empty() && want.is_empty() {
+                    return Err(Error::InvalidAction("empty trade offer".to_string()));
+                }
+                if give.len() + want.len() > MAX_TRADE_STACKS
+                    || give
+                        .iter()
+                        .chain(want.iter())
+                        .any(|stack| stack.amount == 0)
+                {
+                    return Err(Error::InvalidAction("invalid trade offer".to_string()));
+                }
+                Ok(())
+            }
+            PlayerAction::Fire(Fire {
+                target_or_direction: FireTarget::Direction(direction),
+                ..
+            }) => {
+                let norm = direction.iter().map(|c| c * c).sum::<f64>().sqrt();
+                if !norm.is_finite() || norm <= f64::EPSILON {
+                    return Err(Error::InvalidAction(
+                        "Fire.direction is not a valid direction".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+            PlayerAction::Claim(_)
+            | PlayerAction::Mine(_)
+            | PlayerAction::Trade(_)
+            | PlayerAction::Build(_)
+            | PlayerAction::Fire(_)
+            | PlayerAction::RequestMap => Ok(()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PlayerInfo {
     pub coords: [f64; 3],
+    #[serde(default)]
+    pub hull: f64,
+    #[serde(default)]
+    pub shield: f64,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
 pub struct BodyInfo {
     pub coords: [f64; 3],
     pub rotating_speed: f64,
     pub gravity_center: Id,
     pub id: Id,
     pub element_type: String,
+    #[serde(default)]
+    pub owner: Option<Id>,
+    #[serde(default)]
+    pub structure: Option<StructureKind>,
+    /// Last known state of a body out of sensor range.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatInfo {
+    pub channel: ChatChannel,
+    pub sender: String,
+    pub message: String,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InventoryInfo {
+    pub items: Vec<ItemStack>,
+    pub volume: u32,
+    pub capacity: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MiningInfo {
+    pub target: Option<Id>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TradeStatus {
+    Offered,
+    Accepted,
+    Declined,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TradeInfo {
+    pub id: Id,
+    pub from: Id,
+    pub to: Id,
+    pub give: Vec<ItemStack>,
+    pub want: Vec<ItemStack>,
+    pub status: TradeStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HitInfo {
+    pub shooter: Id,
+    pub target: Id,
+    pub weapon: Weapon,
+    pub damage: f64,
+    pub hull: f64,
+    pub shield: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KillInfo {
+    pub killer: Id,
+    pub victim: Id,
+    pub weapon: Weapon,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SystemInfo {
+    pub star: Id,
+    pub coords: [f64; 3],
+    pub discovered_at: u64,
+    pub planets: u32,
+    pub moons: u32,
+    pub asteroids: u32,
+    pub structures: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MapInfo {
+    pub systems: Vec<SystemInfo>,
+}
+
+/// The player crossed into the region of another server, which the client
+/// has to log in to. The connection is closed right after.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RedirectInfo {
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum LoginResult {
+    Ok {
+        player_id: Id,
+        body_id: Id,
+        session: String,
+    },
+    InvalidNickname,
+    AlreadyConnected,
+    Spectating {
+        spectator_id: Id,
+        session: String,
+    },
+    ServerFull,
+    UnknownInstance,
+    Redirect {
+        address: String,
+    },
+    VersionMismatch {
+        server_version: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    InvalidAction,
+    RateLimited,
+    NotFound,
+    Refused,
+    Internal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ErrorInfo {
+    pub kind: ErrorKind,
+    pub message: String,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct AuthInfo {
-    pub(crate) success: bool,
-    pub(crate) message: String,
+impl From<&Error> for ErrorInfo {
+    fn from(err: &Error) -> Self {
+        let kind = match err {
+            Error::InvalidAction(_) | Error::InvalidChatMessage | Error::InvalidNickname => {
+                ErrorKind::InvalidAction
+            }
+            Error::ChatRateLimited | Error::RateLimited | Error::WeaponCooldown => {
+                ErrorKind::RateLimited
+            }
+            Error::ChatRecipientNotFound(_)
+            | Error::DbUuidNotFound(_)
+            | Error::BodyNotFound(_)
+            | Error::SpectatedPlayerNotFound(_)
+            | Error::TradeNotFound(_) => ErrorKind::NotFound,
+            Error::NotClaimable(_)
+            | Error::NotMineable(_)
+            | Error::AlreadyOwned(_)
+            | Error::TooFar(_)
+            | Error::CargoFull
+            | Error::NotEnoughItems
+            | Error::TooManyOffers
+            | Error::NotOwned(_)
+            | Error::TooManyStructures(_)
+            | Error::InvalidTarget(_)
+            | Error::ActionRefusedByScript
+            | Error::SpectatorReadOnly => ErrorKind::Refused,
+            _ => ErrorKind::Internal,
+        };
+        ErrorInfo {
+            kind,
+            message: err.to_string(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -44,4 +397,13 @@ pub enum GameInfo {
     Player(PlayerInfo),
     BodiesInSystem(Vec<BodyInfo>),
     PlayersInSystem(Vec<PlayerInfo>),
+    Chat(ChatInfo),
+    Inventory(InventoryInfo),
+    Mining(MiningInfo),
+    Trade(TradeInfo),
+    Hit(HitInfo),
+    Kill(KillInfo),
+    Map(MapInfo),
+    Error(ErrorInfo),
+    Redirect(RedirectInfo),
 }
@@ -0,0 +1,47 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    capacity: usize,
+    window: Duration,
+    hits: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            capacity,
+            window,
+            hits: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records a hit at `now` and tells whether it fits in the window.
+    /// Refused hits are not recorded.
+    pub fn try_hit_at(&mut self, now: Instant) -> bool {
+        while let Some(oldest) = self.hits.front() {
+            if now.duration_since(*oldest) >= self.window {
+                self.hits.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.hits.len() >= self.capacity {
+            return false;
+        }
+
+        self.hits.push_back(now);
+        true
+    }
+
+    /// Hits recorded within the window as of the last one.
+    pub fn hits(&self) -> usize {
+        self.hits.len()
+    }
+
+    pub fn try_hit(&mut self) -> bool {
+        self.try_hit_at(Instant::now())
+    }
+}
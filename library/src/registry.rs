@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::error::Error;
+use crate::game::chat::ChatConfig;
+use crate::instance::{Instance, DEFAULT_MAX_PLAYERS, DEFAULT_SYSTEM_IDLE};
+use crate::journal::Journal;
+use crate::shard::{self, ShardConfig};
+use crate::snapshot::{SnapshotConfig, SnapshotInfo, Snapshots, SNAPSHOTS_DIR};
+use crate::store::PostgresStore;
+use crate::Result;
+
+pub const DEFAULT_INSTANCE: &str = "default";
+pub const INSTANCE_FILE_EXTENSION: &str = "sbdb";
+pub const MAX_INSTANCE_NAME_LEN: usize = 32;
+pub const TICK_DURATION: Duration = Duration::from_millis(250);
+pub const SAVE_PERIOD: Duration = Duration::from_secs(30);
+pub const JOURNAL_PERIOD: Duration = Duration::from_secs(1);
+
+/// Applied to every instance the registry opens.
+#[derive(Clone, Debug)]
+pub struct InstanceSettings {
+    pub chat_config: ChatConfig,
+    pub max_players: usize,
+    pub scripts: Option<String>,
+    pub shard: Option<ShardConfig>,
+    pub snapshots: Option<SnapshotConfig>,
+    pub system_idle: Duration,
+    /// Seed of the systems of new galaxies.
+    pub generation_seed: Option<u64>,
+}
+
+impl Default for InstanceSettings {
+    fn default() -> Self {
+        InstanceSettings {
+            chat_config: ChatConfig::default(),
+            max_players: DEFAULT_MAX_PLAYERS,
+            scripts: None,
+            shard: None,
+            snapshots: None,
+            system_idle: DEFAULT_SYSTEM_IDLE,
+            generation_seed: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InstanceSummary {
+    pub name: String,
+    pub path: Option<String>,
+    pub players: usize,
+    pub spectators: usize,
+}
+
+struct Hosted {
+    instance: Arc<Mutex<Instance>>,
+    path: Option<String>,
+    closing: watch::Sender<bool>,
+    tick_loop: JoinHandle<Result<()>>,
+    snapshot_loop: Option<JoinHandle<()>>,
+}
+
+/// Named instances served by one server, each ticking and saving on its
+/// own task. Connections to an instance are told through a watch channel
+/// when it is unloaded.
+pub struct Registry {
+    data_dir: PathBuf,
+    settings: InstanceSettings,
+    admin_token: Option<String>,
+    hosted: Mutex<BTreeMap<String, Hosted>>,
+}
+
+pub fn validate_instance_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > MAX_INSTANCE_NAME_LEN
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(Error::InvalidInstanceName(name.to_string()));
+    }
+    Ok(())
+}
+
+async fn tick_loop(
+    name: String,
+    instance: Arc<Mutex<Instance>>,
+    mut closing: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut ref_instant = tokio::time::Instant::now();
+    let mut update_tick_delay = tokio::time::interval(TICK_DURATION);
+    let mut save_tick_delay = tokio::time::interval(SAVE_PERIOD);
+    save_tick_delay.tick().await;
+    let mut journal_tick_delay =
+        tokio::time::interval_at(ref_instant + JOURNAL_PERIOD, JOURNAL_PERIOD);
+
+    loop {
+        tokio::select! {
+            now = update_tick_delay.tick() => {
+                let delta = now - ref_instant;
+                if delta > TICK_DURATION {
+                    log::warn!("Instance loop is too slow: {}s", delta.as_secs_f64());
+                }
+                ref_instant = now;
+                let departures = {
+                    let mut instance = instance.lock().await;
+                    instance.update(delta.as_secs_f64()).await;
+                    instance.take_departures()
+                };
+                for departure in departures {
+                    tokio::spawn(shard::hand_off(Arc::clone(&instance), name.clone(), departure));
+                }
+            },
+            _ = journal_tick_delay.tick() => {
+                if let Err(err) = instance.lock().await.flush_journal() {
+                    log::error!("Failed to flush the journal: {}", err);
+                }
+            },
+            _ = save_tick_delay.tick() => {
+                if let Err(err) = instance.lock().await.save_all().await {
+                    log::error!("Failed to save instance properly: {}", err);
+                }
+            },
+            _ = closing.changed() => {
+                return instance.lock().await.save_all().await;
+            },
+        }
+    }
+}
+
+async fn snapshot_loop(
+    name: String,
+    instance: Arc<Mutex<Instance>>,
+    snapshots: Snapshots,
+    config: SnapshotConfig,
+    mut closing: watch::Receiver<bool>,
+) {
+    let mut snapshot_tick_delay = tokio::time::interval(config.period);
+    snapshot_tick_delay.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = snapshot_tick_delay.tick() => {
+                match snapshots.take(&instance, Some(config.retention.clone())).await {
+                    Ok(snapshot) => log::info!("Instance {} snapshot {} taken", name, snapshot.id),
+                    Err(err) => log::error!("Failed to snapshot instance {}: {}", name, err),
+                }
+            },
+            _ = closing.changed() => return,
+        }
+    }
+}
+
+impl Registry {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Registry {
+        Registry {
+            data_dir: data_dir.into(),
+            settings: InstanceSettings::default(),
+            admin_token: None,
+            hosted: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set_instance_settings(&mut self, settings: InstanceSettings) {
+        self.settings = settings;
+    }
+
+    /// Enables the admin API for requests bearing `token`.
+    pub fn set_admin_token(&mut self, token: String) {
+        self.admin_token = Some(token);
+    }
+
+    pub fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref()
+    }
+
+    pub fn path_of(&self, name: &str) -> Result<PathBuf> {
+        validate_instance_name(name)?;
+        Ok(self
+            .data_dir
+            .join(format!("{}.{}", name, INSTANCE_FILE_EXTENSION)))
+    }
+
+    /// Serves an already opened instance under `name`.
+    pub async fn host(
+        &self,
+        name: &str,
+        instance: Arc<Mutex<Instance>>,
+        path: Option<String>,
+    ) -> Result<()> {
+        validate_instance_name(name)?;
+        let mut hosted = self.hosted.lock().await;
+        if hosted.contains_key(name) {
+            return Err(Error::InstanceAlreadyExists(name.to_string()));
+        }
+
+        let (closing, closing_recv) = watch::channel(false);
+        let snapshot_loop = match &self.settings.snapshots {
+            Some(config) => Some(tokio::spawn(snapshot_loop(
+                name.to_string(),
+                Arc::clone(&instance),
+                self.snapshots_of(name)?,
+                config.clone(),
+                closing_recv.clone(),
+            ))),
+            None => None,
+        };
+        let tick_loop = tokio::spawn(tick_loop(
+            name.to_string(),
+            Arc::clone(&instance),
+            closing_recv,
+        ));
+        hosted.insert(
+            name.to_string(),
+            Hosted {
+                instance,
+                path,
+                closing,
+                tick_loop,
+                snapshot_loop,
+            },
+        );
+        log::info!("Instance {} hosted", name);
+        Ok(())
+    }
+
+    /// Opens the sqlite file at `path`, created if missing, and hosts it.
+    pub async fn load_path(&self, name: &str, path: &str) -> Result<()> {
+        validate_instance_name(name)?;
+        if self.hosted.lock().await.contains_key(name) {
+            return Err(Error::InstanceAlreadyExists(name.to_string()));
+        }
+
+        let instance = Instance::from_path(path).await?;
+        self.host_configured(name, instance, Some(path.to_string()))
+            .await
+    }
+
+    /// Opens the galaxy kept in the PostgreSQL database at `url`, journaled
+    /// next to where the data directory would keep `name`.
+    pub async fn open_postgres(&self, name: &str, url: &str) -> Result<Instance> {
+        let journal_path = Journal::path_of(&Registry::path_str(&self.path_of(name)?));
+        let store = PostgresStore::connect(url).await?;
+        Instance::from_store(Box::new(store), &journal_path).await
+    }
+
+    /// Hosts the galaxy kept in the PostgreSQL database at `url`.
+    pub async fn load_postgres(&self, name: &str, url: &str) -> Result<()> {
+        validate_instance_name(name)?;
+        if self.hosted.lock().await.contains_key(name) {
+            return Err(Error::InstanceAlreadyExists(name.to_string()));
+        }
+
+        let instance = self.open_postgres(name, url).await?;
+        self.host_configured(name, instance, None).await
+    }
+
+    async fn host_configured(
+        &self,
+        name: &str,
+        mut instance: Instance,
+        path: Option<String>,
+    ) -> Result<()> {
+        instance
+            .set_chat_config(self.settings.chat_config.clone())
+            .await?;
+        instance.set_max_players(self.settings.max_players);
+        if let Some(scripts) = &self.settings.scripts {
+            instance.load_scripts(scripts)?;
+        }
+        instance.set_shard(self.settings.shard.clone());
+        instance.set_system_idle(self.settings.system_idle);
+        if let Some(seed) = self.settings.generation_seed {
+            instance.set_generation_seed(seed);
+        }
+
+        self.host(name, Arc::new(Mutex::new(instance)), path).await
+    }
+
+    /// Hosts a new, empty instance in the data directory.
+    pub async fn create(&self, name: &str) -> Result<()> {
+        let path = self.path_of(name)?;
+        if path.exists() {
+            return Err(Error::InstanceAlreadyExists(name.to_string()));
+        }
+        self.load_path(name, &Registry::path_str(&path)).await
+    }
+
+    /// Hosts an instance saved in the data directory.
+    pub async fn load(&self, name: &str) -> Result<()> {
+        let path = self.path_of(name)?;
+        if !path.exists() {
+            return Err(Error::InstanceNotFound(name.to_string()));
+        }
+        self.load_path(name, &Registry::path_str(&path)).await
+    }
+
+    fn path_str(path: &Path) -> String {
+        path.to_string_lossy().to_string()
+    }
+
+    /// Disconnects the players of `name`, stops its loops and saves it.
+    async fn close(&self, name: &str) -> Result<(Arc<Mutex<Instance>>, Option<String>)> {
+        let hosted = self
+            .hosted
+            .lock()
+            .await
+            .remove(name)
+            .ok_or(Error::InstanceNotFound(name.to_string()))?;
+
+        let _ = hosted.closing.send(true);
+        if let Some(snapshot_loop) = hosted.snapshot_loop {
+            let _ = snapshot_loop.await;
+        }
+        hosted
+            .tick_loop
+            .await
+            .map_err(|err| Error::InstanceLoopError(err.to_string()))??;
+        Ok((hosted.instance, hosted.path))
+    }
+
+    pub async fn unload(&self, name: &str) -> Result<()> {
+        self.close(name).await?;
+        log::info!("Instance {} unloaded", name);
+        Ok(())
+    }
+
+    fn snapshots_of(&self, name: &str) -> Result<Snapshots> {
+        validate_instance_name(name)?;
+        Ok(Snapshots::new(self.data_dir.join(SNAPSHOTS_DIR).join(name)))
+    }
+
+    /// Snapshots of `name`, oldest first.
+    pub fn snapshots(&self, name: &str) -> Result<Vec<SnapshotInfo>> {
+        self.snapshots_of(name)?.list()
+    }
+
+    /// Snapshots `name` now, with the retention of the periodic snapshots.
+    pub async fn snapshot(&self, name: &str) -> Result<SnapshotInfo> {
+        let snapshots = self.snapshots_of(name)?;
+        let (instance, _) = self
+            .get(name)
+            .await
+            .ok_or(Error::InstanceNotFound(name.to_string()))?;
+        let retention = self
+            .settings
+            .snapshots
+            .as_ref()
+            .map(|config| config.retention.clone());
+        snapshots.take(&instance, retention).await
+    }
+
+    /// Brings `name` back to snapshot `id`, its players disconnected. What
+    /// it held before is snapshotted first, to go back to if need be.
+    pub async fn rollback(&self, name: &str, id: u64) -> Result<()> {
+        let snapshots = self.snapshots_of(name)?;
+        let export = snapshots.read(id)?;
+        // Closed first, so that nothing changes between the snapshot of what
+        // it held and the restore.
+        let (instance, path) = self.close(name).await?;
+        let before = match snapshots.take(&instance, None).await {
+            Ok(before) => before,
+            Err(err) => {
+                self.host(name, instance, path).await?;
+                return Err(err);
+            }
+        };
+        log::info!(
+            "Instance {} snapshot {} taken before rolling back",
+            name,
+            before.id
+        );
+
+        let restored = instance.lock().await.restore(export).await;
+        let restored = match restored {
+            Ok(restored) => restored,
+            Err(err) => {
+                // The store wasn't touched, the instance goes on as it was.
+                self.host(name, instance, path).await?;
+                return Err(err);
+            }
+        };
+        self.host_configured(name, restored, path).await?;
+        log::info!("Instance {} rolled back to snapshot {}", name, id);
+        Ok(())
+    }
+
+    /// Unloads every instance, going on when one fails to save.
+    pub async fn unload_all(&self) -> Result<()> {
+        let names: Vec<String> = self.hosted.lock().await.keys().cloned().collect();
+        let mut result = Ok(());
+        for name in names {
+            if let Err(err) = self.unload(&name).await {
+                log::error!("Failed to unload instance {}: {}", name, err);
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    pub async fn get(&self, name: &str) -> Option<(Arc<Mutex<Instance>>, watch::Receiver<bool>)> {
+        self.hosted
+            .lock()
+            .await
+            .get(name)
+            .map(|hosted| (Arc::clone(&hosted.instance), hosted.closing.subscribe()))
+    }
+
+    pub async fn list(&self) -> Vec<InstanceSummary> {
+        let hosted = self.hosted.lock().await;
+        let mut summaries = Vec::with_capacity(hosted.len());
+        for (name, hosted) in hosted.iter() {
+            let instance = hosted.instance.lock().await;
+            summaries.push(InstanceSummary {
+                name: name.clone(),
+                path: hosted.path.clone(),
+                players: instance.players_count(),
+                spectators: instance.spectators_count(),
+            });
+        }
+        summaries
+    }
+}
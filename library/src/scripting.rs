@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+
+use crate::error::Error;
+use crate::game::brain::BrainKind;
+use crate::game::inventory::Item;
+use crate::{Id, Result};
+
+pub const MAX_OPERATIONS: u64 = 1_000_000;
+/// Wall clock budget of a single hook call.
+pub const HOOK_TIME_LIMIT: Duration = Duration::from_millis(50);
+pub const SCRIPT_SENDER: &str = "[server]";
+
+/// What scripts ask the instance to do. Commands are queued while a hook
+/// runs and applied by the instance once it returned, after validation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptCommand {
+    Notify { body: Id, message: String },
+    Broadcast(String),
+    Give { body: Id, item: Item, amount: u32 },
+    SpawnNpc { star: Id, kind: BrainKind },
+}
+
+#[derive(Default)]
+struct ScriptState {
+    commands: Vec<ScriptCommand>,
+    players: Array,
+    deadline: Option<Instant>,
+}
+
+/// Game rules hooks, loaded from a Rhai script and reloaded whenever the
+/// file changes.
+///
+/// Scripts only see snapshots of the game and act on it through queued
+/// commands: they can't reach the galaxy, the database, the file system
+/// or other modules, and every call is bounded in operations and time.
+pub struct Scripts {
+    engine: Engine,
+    ast: AST,
+    path: PathBuf,
+    version: Option<(SystemTime, u64)>,
+    state: Arc<Mutex<ScriptState>>,
+}
+
+fn version(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn id_arg(value: rhai::INT) -> Option<Id> {
+    Id::try_from(value).ok()
+}
+
+impl Scripts {
+    pub fn load(path: &str) -> Result<Scripts> {
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        let engine = Scripts::engine(&state);
+        let path = PathBuf::from(path);
+        let version = version(&path);
+        let ast = Scripts::compile(&engine, &path)?;
+
+        Ok(Scripts {
+            engine,
+            ast,
+            path,
+            version,
+            state,
+        })
+    }
+
+    fn compile(engine: &Engine, path: &Path) -> Result<AST> {
+        engine
+            .compile_file(path.to_path_buf())
+            .map_err(|err| Error::ScriptLoadError(path.display().to_string(), err.to_string()))
+    }
+
+    fn engine(state: &Arc<Mutex<ScriptState>>) -> Engine {
+        let mut engine = Engine::new();
+
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(1_000);
+
+        let progress_state = Arc::clone(state);
+        engine.on_progress(move |operations| {
+            if operations % 1024 != 0 {
+                return None;
+            }
+            match progress_state.lock().unwrap().deadline {
+                Some(deadline) if Instant::now() > deadline => Some("time limit exceeded".into()),
+                _ => None,
+            }
+        });
+        engine.on_print(|text| log::info!("[script] {}", text));
+        engine.on_debug(|text, _, position| log::debug!("[script] {} {}", position, text));
+
+        let players_state = Arc::clone(state);
+        engine.register_fn("players", move || {
+            players_state.lock().unwrap().players.clone()
+        });
+
+        let notify_state = Arc::clone(state);
+        engine.register_fn("notify", move |body: rhai::INT, message: &str| {
+            if let Some(body) = id_arg(body) {
+                notify_state
+                    .lock()
+                    .unwrap()
+                    .commands
+                    .push(ScriptCommand::Notify {
+                        body,
+                        message: message.to_string(),
+                    });
+            }
+        });
+
+        let broadcast_state = Arc::clone(state);
+        engine.register_fn("broadcast", move |message: &str| {
+            broadcast_state
+                .lock()
+                .unwrap()
+                .commands
+                .push(ScriptCommand::Broadcast(message.to_string()));
+        });
+
+        let give_state = Arc::clone(state);
+        engine.register_fn(
+            "give",
+            move |body: rhai::INT, item: Dynamic, amount: rhai::INT| {
+                let item = rhai::serde::from_dynamic::<Item>(&item);
+                match (id_arg(body), item, u32::try_from(amount)) {
+                    (Some(body), Ok(item), Ok(amount)) if amount > 0 => {
+                        give_state
+                            .lock()
+                            .unwrap()
+                            .commands
+                            .push(ScriptCommand::Give { body, item, amount });
+                    }
+                    _ => log::warn!("[script] invalid give({}, _, {})", body, amount),
+                }
+            },
+        );
+
+        let spawn_state = Arc::clone(state);
+        engine.register_fn("spawn_npc", move |star: rhai::INT, brain: &str| {
+            match (id_arg(star), BrainKind::from_str(brain)) {
+                (Some(star), Some(kind)) => spawn_state
+                    .lock()
+                    .unwrap()
+                    .commands
+                    .push(ScriptCommand::SpawnNpc { star, kind }),
+                _ => log::warn!("[script] invalid spawn_npc({}, {})", star, brain),
+            }
+        });
+
+        engine
+    }
+
+    /// Recompiles the script if the file changed since it was loaded. A
+    /// script that doesn't compile anymore is reported and the previous
+    /// one kept running.
+    pub fn reload_if_changed(&mut self) {
+        let version = version(&self.path);
+        if version.is_none() || version == self.version {
+            return;
+        }
+        self.version = version;
+
+        match Scripts::compile(&self.engine, &self.path) {
+            Ok(ast) => {
+                log::info!("Scripts reloaded from {}", self.path.display());
+                self.ast = ast;
+            }
+            Err(err) => log::error!("Keeping previous scripts: {}", err),
+        }
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == hook)
+    }
+
+    pub fn set_players(&mut self, players: Array) {
+        self.state.lock().unwrap().players = players;
+    }
+
+    /// Calls `hook` if the script defines it. Script errors, including
+    /// exceeded limits, are logged and the hook treated as absent.
+    pub fn call(&mut self, hook: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        if !self.has_hook(hook) {
+            return None;
+        }
+
+        self.state.lock().unwrap().deadline = Some(Instant::now() + HOOK_TIME_LIMIT);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new().eval_ast(false).rewind_scope(true),
+            &mut Scope::new(),
+            &self.ast,
+            hook,
+            args,
+        );
+        self.state.lock().unwrap().deadline = None;
+
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                log::error!("Script hook {} failed: {}", hook, err);
+                // Half run hooks don't get to act.
+                self.state.lock().unwrap().commands.clear();
+                None
+            }
+        }
+    }
+
+    pub fn take_commands(&mut self) -> Vec<ScriptCommand> {
+        std::mem::take(&mut self.state.lock().unwrap().commands)
+    }
+}
+
+pub fn to_map<const N: usize>(entries: [(&str, Dynamic); N]) -> Map {
+    entries
+        .into_iter()
+        .map(|(key, value)| (key.into(), value))
+        .collect()
+}
@@ -3,6 +3,7 @@ use crate::instance::Instance;
 use crate::network;
 use crate::network::tls::ClientPki;
 use crate::network::tls::ServerPki;
+use crate::registry::{Registry, DEFAULT_INSTANCE, TICK_DURATION};
 use crate::service;
 use crate::Result;
 use crossbeam::channel::Receiver;
@@ -23,7 +24,11 @@ use tokio::task::JoinHandle;
 
 pub enum InstanceConfig {
     UserInstance(Arc<Mutex<Instance>>),
-    UserSqliteDb { path: String },
+    UserSqliteDb {
+        path: String,
+    },
+    /// Several named instances, the default one served at `/`.
+    Registry(Arc<Registry>),
 }
 
 pub enum TcpConfig {
@@ -47,12 +52,19 @@ pub async fn run(
     server_config: ServerConfig<'_>,
     stop: crossbeam::channel::Receiver<()>,
 ) -> Result<()> {
-    let instance = match instance_config {
-        InstanceConfig::UserInstance(instance) => instance,
+    let registry = match instance_config {
+        InstanceConfig::UserInstance(instance) => {
+            let registry = Registry::new(".");
+            registry.host(DEFAULT_INSTANCE, instance, None).await?;
+            Arc::new(registry)
+        }
         InstanceConfig::UserSqliteDb { path } => {
             info!("Loading {}", path);
-            Arc::new(Mutex::new(Instance::from_path(path.as_str()).await?))
+            let registry = Registry::new(".");
+            registry.load_path(DEFAULT_INSTANCE, path.as_str()).await?;
+            Arc::new(registry)
         }
+        InstanceConfig::Registry(registry) => registry,
     };
 
     let listener = match server_config.tcp {
@@ -68,13 +80,11 @@ pub async fn run(
         None
     };
 
-    let mut ref_instant = tokio::time::Instant::now();
     let mut tls_handlers = FuturesUnordered::new();
     let mut http_handlers = FuturesUnordered::new();
     let mut ws_handlers = FuturesUnordered::new();
-    let tick_value = std::time::Duration::from_millis(250);
-    let mut update_tick_delay = tokio::time::interval(tick_value);
-    let mut save_tick_delay = tokio::time::interval(std::time::Duration::from_secs(30));
+    // Instances tick on their own tasks, this one only watches connections.
+    let mut update_tick_delay = tokio::time::interval(TICK_DURATION);
     let mut http_hdl_recvs: Vec<Receiver<JoinHandle<Result<()>>>> = Vec::new();
     let mut ws_hdl_recvs: Vec<Receiver<JoinHandle<Result<()>>>> = Vec::new();
 
@@ -84,13 +94,12 @@ pub async fn run(
     );
 
     // update_tick_delay.tick().await;
-    save_tick_delay.tick().await;
 
     loop {
         tokio::select! {
             // ----------------------------------------------------
             // ON UPDATE TICK DELAY--------------------------------
-            now = update_tick_delay.tick() => {
+            _ = update_tick_delay.tick() => {
 
                 let mut must_stop = false;
                 if stop.try_recv().is_ok() {
@@ -161,15 +170,8 @@ pub async fn run(
                     }
                 }
 
-                let delta = now - ref_instant;
-                if delta > tick_value {
-                    log::warn!("Server loop is too slow: {}s", delta.as_secs_f64());
-                }
-                ref_instant = now;
-                instance.lock().await.update(delta.as_secs_f64()).await;
-
                 if must_stop || critical{
-                    let save_result = instance.lock().await.save_all().await;
+                    let save_result = registry.unload_all().await;
                     if save_result.is_err() {
                         log::error!("Failed to save instance properly: {}", save_result.err().unwrap());
                         return Err(Error::Error);
@@ -182,20 +184,11 @@ pub async fn run(
                 }
             },
             // ----------------------------------------------------
-            // ON SAVE TICK DELAY----------------------------------
-            _ = save_tick_delay.tick() => {
-
-                let save_result = instance.lock().await.save_all().await;
-                if save_result.is_err() {
-                    log::error!("Failed to save instance properly: {}", save_result.err().unwrap());
-                }
-            },
-            // ----------------------------------------------------
             // ON TCP ACCEPT---------------------------------------
             Ok((stream, addr)) = listener.accept() => {
                 info!("TCP accept from: {}", addr);
 
-                let cln = Arc::clone(&instance);
+                let cln = Arc::clone(&registry);
                 let (http_hdl_send, http_hdl_recv) = crossbeam::channel::bounded::<tokio::task::JoinHandle<Result<()>>>(1);
                 let (ws_hdl_send, ws_hdl_recv) = crossbeam::channel::bounded::<tokio::task::JoinHandle<Result<()>>>(1);
                 http_hdl_recvs.push(http_hdl_recv);
@@ -209,7 +202,7 @@ pub async fn run(
                     });
                     tls_handlers.push(hdl);
                 } else {
-                    http_handlers.push(run_http(stream, Arc::clone(&instance), ws_hdl_send));
+                    http_handlers.push(run_http(stream, Arc::clone(&registry), ws_hdl_send));
                 }
             },
         }
@@ -217,7 +210,7 @@ pub async fn run(
 
     fn run_http<T>(
         stream: T,
-        instance: Arc<Mutex<Instance>>,
+        registry: Arc<Registry>,
         ws_hdl_sender: crossbeam::channel::Sender<tokio::task::JoinHandle<Result<()>>>,
     ) -> tokio::task::JoinHandle<Result<()>>
     where
@@ -229,15 +222,13 @@ pub async fn run(
     {
         let io = TokioIo::new(stream);
         let hdl = tokio::task::spawn(async move {
-            let instance = Arc::clone(&instance);
-
             http1::Builder::new()
                 .serve_connection(
                     io,
                     service_fn(move |req: Request<hyper::body::Incoming>| {
-                        let instance = Arc::clone(&instance);
+                        let registry = Arc::clone(&registry);
                         let ws_hdl_sender = ws_hdl_sender.clone();
-                        service::serve_http(req, instance, ws_hdl_sender)
+                        service::serve_http(req, registry, ws_hdl_sender)
                     }),
                 )
                 .with_upgrades()
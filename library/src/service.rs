@@ -1,37 +1,178 @@
+use crate::admin;
 use crate::error::Error;
 use crate::game::entity::Entity;
 use crate::instance::Instance;
-use crate::protocol::AuthInfo;
+use crate::protocol::ErrorInfo;
+use crate::protocol::GameInfo;
+use crate::protocol::LoginResult;
 use crate::protocol::PlayerAction;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::rate_limit::RateLimiter;
+use crate::registry::{self, Registry, DEFAULT_INSTANCE};
+use crate::shard;
 use crate::Id;
 use futures::SinkExt;
 use futures::StreamExt;
 use http_body_util::Full;
 use hyper::body::Bytes;
-use hyper::{Request, Response, StatusCode};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_tungstenite::tungstenite::protocol::WebSocketConfig;
 use hyper_tungstenite::tungstenite::Message;
-use hyper_tungstenite::HyperWebsocket;
+use hyper_tungstenite::{HyperWebsocket, HyperWebsocketStream};
 use log::error;
 use log::info;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
 extern crate scopeguard;
 
 use crate::Result;
 
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+pub const MAX_STRIKES: usize = 5;
+pub const STRIKES_WINDOW: Duration = Duration::from_secs(600);
+pub const MESSAGES_RATE_CAPACITY: usize = 40;
+pub const MESSAGES_RATE_WINDOW: Duration = Duration::from_secs(1);
+pub const MAX_PENDING_ACTIONS: usize = 32;
+pub const INSTANCE_PATH_PREFIX: &str = "/ws/";
+pub const LEFT_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum Admission {
+    Action(PlayerAction),
+    Dropped(ErrorInfo),
+    Disconnect,
+}
+
+/// Per connection bookkeeping: every refused message is a strike, and a peer
+/// reaching `MAX_STRIKES` within `STRIKES_WINDOW` is disconnected.
+struct ConnectionGuard {
+    limiter: RateLimiter,
+    strikes: RateLimiter,
+}
+
+impl ConnectionGuard {
+    fn new() -> ConnectionGuard {
+        ConnectionGuard {
+            limiter: RateLimiter::new(MESSAGES_RATE_CAPACITY, MESSAGES_RATE_WINDOW),
+            strikes: RateLimiter::new(MAX_STRIKES - 1, STRIKES_WINDOW),
+        }
+    }
+
+    fn reject(&mut self, id: Id, err: Error) -> Admission {
+        let struck_out = !self.strikes.try_hit();
+        log::warn!(
+            "Strike {}/{} for {}: {}",
+            self.strikes.hits() + usize::from(struck_out),
+            MAX_STRIKES,
+            id,
+            err
+        );
+        if struck_out {
+            Admission::Disconnect
+        } else {
+            Admission::Dropped(ErrorInfo::from(&err))
+        }
+    }
+
+    fn admit(&mut self, id: Id, msg: &str) -> Admission {
+        if !self.limiter.try_hit() {
+            return self.reject(id, Error::RateLimited);
+        }
+
+        match serde_json::from_str::<PlayerAction>(msg) {
+            Err(err) => self.reject(id, Error::InvalidAction(format!("invalid JSON: {}", err))),
+            Ok(action) => match action.validate() {
+                Err(err) => self.reject(id, err),
+                Ok(()) => Admission::Action(action),
+            },
+        }
+    }
+}
+
+async fn send_json<T: serde::Serialize>(websocket: &mut HyperWebsocketStream, value: &T) {
+    let str = serde_json::to_string(value).unwrap();
+    let result = websocket.send(Message::text(str)).await;
+    if result.is_err() {
+        info!("Message send error: {}", result.err().unwrap());
+    }
+}
+
+/// Closes the connection of a player who already left the instance.
+async fn hang_up(websocket: &mut HyperWebsocketStream) {
+    let _ = websocket.close(None).await;
+    // Wait for the client to hang up: dropping the socket while it still
+    // sends would reset the connection before it reads the last infos.
+    let _ = tokio::time::timeout(LEFT_CLOSE_TIMEOUT, async {
+        while let Some(Ok(_)) = websocket.next().await {}
+    })
+    .await;
+}
+
+async fn disconnect(
+    websocket: &mut HyperWebsocketStream,
+    instance: &Arc<Mutex<Instance>>,
+    id: Id,
+) -> Result<()> {
+    info!("Disconnecting {}", id);
+    let _ = websocket.close(None).await;
+    if id != Id::MAX {
+        instance.lock().await.leave(id).await?;
+    }
+    Ok(())
+}
+
+/// Instance a login goes to: the one named by the URL, which the login may
+/// only repeat, else the one named by the login, else the default one.
+async fn resolve_instance(
+    registry: &Registry,
+    from_path: Option<&str>,
+    from_login: Option<&str>,
+) -> Option<(Arc<Mutex<Instance>>, watch::Receiver<bool>)> {
+    let name = match (from_path, from_login) {
+        (Some(from_path), Some(from_login)) if from_path != from_login => return None,
+        (Some(name), _) | (None, Some(name)) => name,
+        (None, None) => DEFAULT_INSTANCE,
+    };
+    registry.get(name).await
+}
+
 pub async fn serve_http(
     mut request: Request<hyper::body::Incoming>,
-    instance: Arc<Mutex<Instance>>,
+    registry: Arc<Registry>,
     ws_hdl_sender: crossbeam::channel::Sender<tokio::task::JoinHandle<Result<()>>>,
 ) -> hyper::Result<Response<Full<Bytes>>> {
     let response_body = Full::<Bytes>::new("".into());
     let mut response = Response::<Full<Bytes>>::new(response_body);
     *response.status_mut() = StatusCode::BAD_REQUEST;
 
+    let path = request.uri().path().to_string();
+    if path.starts_with(admin::ADMIN_PREFIX) && !hyper_tungstenite::is_upgrade_request(&request) {
+        return Ok(admin::serve_admin(&request, &registry).await);
+    }
+    if path.starts_with(shard::HANDOFF_PREFIX) && request.method() == Method::POST {
+        return Ok(shard::serve_handoff(request, &registry).await);
+    }
+
     if hyper_tungstenite::is_upgrade_request(&request) {
-        info!("Upgrade request");
-        let res = hyper_tungstenite::upgrade(&mut request, None);
+        info!("Upgrade request on {}", path);
+        let path_instance = match path.as_str() {
+            "" | "/" => None,
+            path => match path.strip_prefix(INSTANCE_PATH_PREFIX) {
+                Some(name) if registry::validate_instance_name(name).is_ok() => {
+                    Some(name.to_string())
+                }
+                _ => {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                    return Ok(response);
+                }
+            },
+        };
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(MAX_MESSAGE_SIZE))
+            .max_frame_size(Some(MAX_MESSAGE_SIZE));
+        let res = hyper_tungstenite::upgrade(&mut request, Some(config));
         if res.is_err() {
             let err_str: String = res.err().unwrap().to_string();
 
@@ -45,8 +186,7 @@ pub async fn serve_http(
         let (ws_resp, websocket) = res.unwrap();
 
         let hdl = tokio::spawn(async move {
-            let instance_cln = Arc::clone(&instance);
-            serve_websocket(websocket, instance_cln).await?;
+            serve_websocket(websocket, registry, path_instance).await?;
             Ok(())
         });
 
@@ -60,88 +200,165 @@ pub async fn serve_http(
     }
 }
 
-async fn serve_websocket(websocket: HyperWebsocket, instance: Arc<Mutex<Instance>>) -> Result<()> {
+async fn serve_websocket(
+    websocket: HyperWebsocket,
+    registry: Arc<Registry>,
+    path_instance: Option<String>,
+) -> Result<()> {
     let mut websocket = websocket.await.map_err(|_err| Error::Error)?;
 
     let mut id = Id::MAX;
 
     let mut authenticated = false;
 
+    let mut spectating = false;
+
     // let mut tick_delay = tokio::time::interval(std::time::Duration::from_millis(250));
 
-    let recv = loop {
+    let mut connection = ConnectionGuard::new();
+
+    let (recv, instance, mut closing) = loop {
         tokio::select! {
             Some(message) = websocket.next() => {
                 if message.is_err() {
                     info!("Websocket read error: {}", message.err().unwrap());
-                    if id != u32::MAX {
-                        instance.lock().await.leave(id).await?;
-                    }
                     return Ok(());
                 }
                 match message.unwrap() {
                     Message::Text(msg) => {
-                        let maybe_action: serde_json::Result<PlayerAction> =
-                            serde_json::from_str(msg.as_str());
-
-                        let mut login_info = AuthInfo {
-                            success: false,
-                            message: "".to_string(),
+                        let action = match connection.admit(id, msg.as_str()) {
+                            Admission::Action(action) => action,
+                            Admission::Dropped(_) => continue,
+                            Admission::Disconnect => {
+                                let _ = websocket.close(None).await;
+                                return Ok(());
+                            }
                         };
-                        if maybe_action.is_err() {
-                            login_info.message = "Invalid JSON".to_string();
-                        } else {
-                            let maybe_login = maybe_action.unwrap();
 
-                            if let PlayerAction::Login(login) = maybe_login {
-                                if authenticated {
-                                    log::info!("{} already authenticated, closing him.", id);
+                        if let PlayerAction::Login(login) = action {
+                            if login.version != PROTOCOL_VERSION {
+                                info!("Login refused for {}: version {}", login.nickname, login.version);
+                                send_json(&mut websocket, &LoginResult::VersionMismatch {
+                                    server_version: PROTOCOL_VERSION,
+                                }).await;
+                                let _ = websocket.close(None).await;
+                                return Ok(());
+                            }
+
+                            let Some((instance, closing)) = resolve_instance(
+                                &registry,
+                                path_instance.as_deref(),
+                                login.instance.as_deref(),
+                            ).await else {
+                                info!("Login refused for {}: unknown instance", login.nickname);
+                                send_json(&mut websocket, &LoginResult::UnknownInstance).await;
+                                let _ = websocket.close(None).await;
+                                return Ok(());
+                            };
+
+                            let mut guard = instance.lock().await;
+
+                            info!("Login request for {}", login.nickname);
+                            let (body_id, infos_recv) = match guard.authenticate(&login.nickname).await {
+                                Ok(authenticated) => authenticated,
+                                Err(err) => {
+                                    info!("Login error: {}", err);
+                                    let login_result = match err {
+                                        Error::InvalidNickname => LoginResult::InvalidNickname,
+                                        Error::PlayerAlreadyAuthenticated => LoginResult::AlreadyConnected,
+                                        Error::ServerFull => LoginResult::ServerFull,
+                                        Error::PlayerHandedOff(address) => LoginResult::Redirect { address },
+                                        _ => {
+                                            let _ = websocket.close(None).await;
+                                            return Ok(());
+                                        }
+                                    };
+                                    send_json(&mut websocket, &login_result).await;
                                     let _ = websocket.close(None).await;
-                                    continue;
-                                }
-                                let mut guard = instance.lock().await;
-
-                                info!("Login request for {}", login.nickname);
-                                let maybe_uuid = guard.authenticate(&login.nickname).await;
-                                if maybe_uuid.is_err() {
-                                    login_info.message = format!("{}", maybe_uuid.err().unwrap());
-                                    info!("Login error: {}", login_info.message);
-                                    return Ok(())
+                                    return Ok(());
                                 }
+                            };
 
-                                let (player_id, infos_recv) = maybe_uuid.unwrap();
+                            id = body_id;
 
-                                id = player_id;
+                            info!("Login success for {}", id);
+                            authenticated = true;
 
+                            let login_result = LoginResult::Ok {
+                                player_id: guard.player_id(body_id).unwrap_or(Id::MAX),
+                                body_id,
+                                session: Uuid::new_v4().to_string(),
+                            };
 
-                                info!("Login success for {}", id);
-                                authenticated = true;
+                            send_json(&mut websocket, &login_result).await;
 
-                                login_info.success = true;
-                                login_info.message = id.to_string();
+                            drop(guard);
+                            break (infos_recv, instance, closing);
 
+                        } else if let PlayerAction::Spectate(spectate) = action {
+                            if spectate.version != PROTOCOL_VERSION {
+                                info!("Spectate refused: version {}", spectate.version);
+                                send_json(&mut websocket, &LoginResult::VersionMismatch {
+                                    server_version: PROTOCOL_VERSION,
+                                }).await;
+                                let _ = websocket.close(None).await;
+                                return Ok(());
+                            }
 
-                                let maybe_login_info_str = serde_json::to_string(&login_info);
-                                assert!(maybe_login_info_str.is_ok());
-                                let result = websocket
-                                    .send(Message::text(maybe_login_info_str.unwrap()))
-                                    .await;
-                                if result.is_err() {
-                                    info!("Message send error: {}", result.err().unwrap());
+                            let Some((instance, closing)) = resolve_instance(
+                                &registry,
+                                path_instance.as_deref(),
+                                spectate.instance.as_deref(),
+                            ).await else {
+                                info!("Spectate refused: unknown instance");
+                                send_json(&mut websocket, &LoginResult::UnknownInstance).await;
+                                let _ = websocket.close(None).await;
+                                return Ok(());
+                            };
+
+                            let (spectator_id, infos_recv) = match instance.lock().await.spectate(&spectate) {
+                                Ok(spectating) => spectating,
+                                Err(err) => {
+                                    info!("Spectate error: {}", err);
+                                    match err {
+                                        Error::ServerFull => send_json(&mut websocket, &LoginResult::ServerFull).await,
+                                        _ => send_json(&mut websocket, &GameInfo::Error(ErrorInfo::from(&err))).await,
+                                    }
+                                    let _ = websocket.close(None).await;
+                                    return Ok(());
                                 }
+                            };
 
-                                break infos_recv;
+                            id = spectator_id;
+                            authenticated = true;
+                            spectating = true;
 
-                            } else {
-                                log::info!("Client not authenticated, closing him");
-                                let _ = websocket.close(None).await;
-                                return Ok(());
-                            }
+                            send_json(&mut websocket, &LoginResult::Spectating {
+                                spectator_id,
+                                session: Uuid::new_v4().to_string(),
+                            }).await;
+
+                            break (infos_recv, instance, closing);
+
+                        } else {
+                            log::info!("Client not authenticated, closing him");
+                            let _ = websocket.close(None).await;
+                            return Ok(());
                         }
 
                     }
                     Message::Binary(msg) => {
                         log::info!("{:?}", msg);
+                        match connection.reject(id, Error::InvalidAction("unexpected binary message".to_string())) {
+                            Admission::Disconnect => {
+                                let _ = websocket.close(None).await;
+                                return Ok(());
+                            }
+                            Admission::Dropped(error_info) if authenticated => {
+                                send_json(&mut websocket, &GameInfo::Error(error_info)).await;
+                            }
+                            _ => {}
+                        }
                     }
                     Message::Ping(msg) => {
                         log::info!("{:?}", msg);
@@ -151,11 +368,6 @@ async fn serve_websocket(websocket: HyperWebsocket, instance: Arc<Mutex<Instance
                     }
                     Message::Close(msg) => {
                         info!("WS close request received: {:?}", msg);
-                        if id != Id::MAX {
-                            instance.lock().await.leave(id).await?;
-                        } else {
-                            log::error!("Id is not assigned but closed received!");
-                        }
                         return Ok(());
                     }
                     Message::Frame(msg) => {
@@ -170,7 +382,16 @@ async fn serve_websocket(websocket: HyperWebsocket, instance: Arc<Mutex<Instance
 
     loop {
         tokio::select! {
+            _ = closing.changed() => {
+                info!("Instance closing, disconnecting {}", id);
+                return disconnect(&mut websocket, &instance, id).await;
+            },
             game_info = stream.next() => {
+                let Some(game_info) = game_info else {
+                    info!("{} no longer in the instance", id);
+                    hang_up(&mut websocket).await;
+                    return Ok(());
+                };
                 let str = serde_json::to_string(&game_info).unwrap();
                 let result = websocket.send(Message::text(str)).await;
                 if result.is_err() {
@@ -179,6 +400,12 @@ async fn serve_websocket(websocket: HyperWebsocket, instance: Arc<Mutex<Instance
                     let _ = websocket.close(None).await;
                     return Ok(());
                 }
+                // The player was handed off and already left the instance.
+                if let GameInfo::Redirect(redirect) = game_info {
+                    info!("{} redirected to {}", id, redirect.address);
+                    hang_up(&mut websocket).await;
+                    return Ok(());
+                }
             },
             Some(message) = websocket.next() => {
                 if message.is_err() {
@@ -188,42 +415,96 @@ async fn serve_websocket(websocket: HyperWebsocket, instance: Arc<Mutex<Instance
                 }
                 match message.unwrap() {
                     Message::Text(msg) => {
-                        let maybe_action: serde_json::Result<PlayerAction> =
-                            serde_json::from_str(msg.as_str());
-
-                        let mut login_info = AuthInfo {
-                            success: false,
-                            message: "".to_string(),
+                        let action = match connection.admit(id, msg.as_str()) {
+                            Admission::Action(action) => action,
+                            Admission::Dropped(error_info) => {
+                                send_json(&mut websocket, &GameInfo::Error(error_info)).await;
+                                continue;
+                            }
+                            Admission::Disconnect => {
+                                return disconnect(&mut websocket, &instance, id).await;
+                            }
                         };
-                        if maybe_action.is_err() {
-                            login_info.message = "Invalid JSON".to_string();
-                        } else {
-                            let maybe_login = maybe_action.unwrap();
 
-                            if let PlayerAction::Login(_login) = maybe_login {
-                                if authenticated {
-                                    log::info!("{} already authenticated, closing him.", id);
-                                    let _ = websocket.close(None).await;
-                                    return Ok(());
+                        if spectating {
+                            let result = match action {
+                                PlayerAction::Spectate(spectate) => {
+                                    instance.lock().await.retarget_spectator(id, &spectate)
                                 }
-                            } else {
+                                PlayerAction::RequestMap => instance.lock().await.request_map(id),
+                                _ => Err(Error::SpectatorReadOnly),
+                            };
+                            if let Err(err) = result {
+                                info!("Action from spectator {} refused: {}", id, err);
+                                send_json(&mut websocket, &GameInfo::Error(ErrorInfo::from(&err))).await;
+                            }
+                            continue;
+                        }
+
+                        if !matches!(action, PlayerAction::Login(_)) {
+                            if let Err(err) = instance.lock().await.check_action(id, &action) {
+                                info!("Action from {} refused: {}", id, err);
+                                send_json(&mut websocket, &GameInfo::Error(ErrorInfo::from(&err))).await;
+                                continue;
+                            }
+                        }
+
+                        let result = match action {
+                            PlayerAction::Login(_login) => {
+                                log::info!("{} already authenticated, closing him.", id);
+                                send_json(&mut websocket, &LoginResult::AlreadyConnected).await;
+                                return disconnect(&mut websocket, &instance, id).await;
+                            }
+                            PlayerAction::Spectate(_) => Err(Error::InvalidAction(
+                                "already playing".to_string(),
+                            )),
+                            PlayerAction::Chat(chat) => instance.lock().await.chat(id, chat),
+                            PlayerAction::Claim(claim) => {
+                                instance.lock().await.claim(id, claim.target)
+                            }
+                            PlayerAction::Mine(mine) => {
+                                instance.lock().await.mine(id, mine.target)
+                            }
+                            PlayerAction::Trade(trade) => instance.lock().await.trade(id, trade),
+                            PlayerAction::Build(build) => instance.lock().await.build(id, build),
+                            PlayerAction::Fire(fire) => instance.lock().await.fire(id, fire),
+                            PlayerAction::RequestMap => instance.lock().await.request_map(id),
+                            action => {
                                 let mut instance = instance.lock().await;
                                 let maybe_element = instance.borrow_galaxy_mut().borrow_body_mut(id);
                                 if let Some(maybe_player) = maybe_element {
                                     if let Entity::Player(player) =
                                         &mut maybe_player.entity
                                     {
-                                        player.actions.push(maybe_login);
+                                        if player.actions.len() >= MAX_PENDING_ACTIONS {
+                                            player.actions.remove(0);
+                                        }
+                                        player.actions.push(action);
                                     }
                                 } else {
                                     error!("Can't find player {}", id);
                                 }
+                                Ok(())
                             }
+                        };
+
+                        if let Err(err) = result {
+                            info!("Action from {} refused: {}", id, err);
+                            send_json(&mut websocket, &GameInfo::Error(ErrorInfo::from(&err))).await;
                         }
 
                     }
                     Message::Binary(msg) => {
                         log::info!("{:?}", msg);
+                        match connection.reject(id, Error::InvalidAction("unexpected binary message".to_string())) {
+                            Admission::Disconnect => {
+                                return disconnect(&mut websocket, &instance, id).await;
+                            }
+                            Admission::Dropped(error_info) if authenticated => {
+                                send_json(&mut websocket, &GameInfo::Error(error_info)).await;
+                            }
+                            _ => {}
+                        }
                     }
                     Message::Ping(msg) => {
                         log::info!("{:?}", msg);
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Body, Bytes};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, HOST};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::error::Error;
+use crate::game::inventory::ItemStack;
+use crate::game::repr::Vector3;
+use crate::instance::Instance;
+use crate::registry::Registry;
+use crate::Id;
+use crate::Result;
+
+pub const HANDOFF_PREFIX: &str = "/shard/handoff/";
+pub const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay before handing off again a player whose handoff failed.
+pub const HANDOFF_RETRY_DELAY: Duration = Duration::from_secs(5);
+pub const MAX_HANDOFF_SIZE: usize = 64 * 1024;
+
+/// Axis aligned box of space, bounds included.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl Region {
+    pub fn contains(&self, coords: &Vector3) -> bool {
+        let coords = [coords.x, coords.y, coords.z];
+        (0..3).all(|axis| self.min[axis] <= coords[axis] && coords[axis] <= self.max[axis])
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Peer {
+    /// `host:port` of the peer server, which clients are redirected to.
+    pub address: String,
+    pub region: Region,
+}
+
+/// The part of the galaxy an instance owns and the servers owning the
+/// neighbouring parts. Instances of the same name hand players over to
+/// each other, authenticated by a secret shared by every server.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ShardConfig {
+    pub region: Region,
+    pub peers: Vec<Peer>,
+    pub secret: String,
+}
+
+impl ShardConfig {
+    pub fn load(path: &str) -> Result<ShardConfig> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| Error::ShardConfigError(path.to_string(), err.to_string()))?;
+        serde_json::from_str(&content)
+            .map_err(|err| Error::ShardConfigError(path.to_string(), err.to_string()))
+    }
+
+    /// The peer owning `coords`, if this shard doesn't.
+    pub fn owner_of(&self, coords: &Vector3) -> Option<&Peer> {
+        if self.region.contains(coords) {
+            return None;
+        }
+        self.peers.iter().find(|peer| peer.region.contains(coords))
+    }
+}
+
+/// What a server needs to take over a player. Ownings, discoveries and
+/// pending trades stay behind, since they refer to bodies of the sender.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Handoff {
+    pub nickname: String,
+    pub coords: [f64; 3],
+    pub direction: [f64; 3],
+    pub speed: f64,
+    pub items: Vec<ItemStack>,
+    pub hull: f64,
+    pub shield: f64,
+}
+
+/// A player who crossed into the region of `peer` and is being handed off.
+#[derive(Clone, Debug)]
+pub struct Departure {
+    pub body: Id,
+    pub peer: Peer,
+    pub handoff: Handoff,
+}
+
+/// Hands `departure` off to its peer's instance of the same name, then
+/// redirects the player there, or retries later if the peer refused.
+pub async fn hand_off(instance: Arc<Mutex<Instance>>, name: String, departure: Departure) {
+    let Some(secret) = instance
+        .lock()
+        .await
+        .shard()
+        .map(|shard| shard.secret.clone())
+    else {
+        return;
+    };
+
+    let address = &departure.peer.address;
+    match send_handoff(address, &secret, &name, &departure.handoff).await {
+        Ok(()) => {
+            log::info!("{} handed off to {}", departure.handoff.nickname, address);
+            let result = instance
+                .lock()
+                .await
+                .complete_departure(departure.body, address)
+                .await;
+            if let Err(err) = result {
+                log::error!(
+                    "Failed to complete the departure of {}: {}",
+                    departure.body,
+                    err
+                );
+            }
+        }
+        Err(err) => {
+            log::warn!("{}", err);
+            instance.lock().await.cancel_departure(departure.body);
+        }
+    }
+}
+
+fn handoff_error(address: &str, err: impl ToString) -> Error {
+    Error::HandoffError(address.to_string(), err.to_string())
+}
+
+/// Posts `handoff` to the instance `name` of the peer at `address`, which
+/// saves the player as its own before answering.
+pub async fn send_handoff(
+    address: &str,
+    secret: &str,
+    name: &str,
+    handoff: &Handoff,
+) -> Result<()> {
+    let request = async {
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|err| handoff_error(address, err))?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|err| handoff_error(address, err))?;
+        tokio::spawn(connection);
+
+        let request = Request::post(format!("{}{}", HANDOFF_PREFIX, name))
+            .header(HOST, address)
+            .header(AUTHORIZATION, format!("Bearer {}", secret))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::<Bytes>::new(
+                serde_json::to_vec(handoff).unwrap().into(),
+            ))
+            .map_err(|err| handoff_error(address, err))?;
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|err| handoff_error(address, err))?;
+
+        if response.status() != StatusCode::OK {
+            return Err(handoff_error(address, response.status()));
+        }
+        Ok(())
+    };
+
+    tokio::time::timeout(HANDOFF_TIMEOUT, request)
+        .await
+        .map_err(|err| handoff_error(address, err))?
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::<Bytes>::new(body.into()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Takes over a player handed off by a peer, answering once it is saved.
+pub async fn serve_handoff<B>(request: Request<B>, registry: &Registry) -> Response<Full<Bytes>>
+where
+    B: Body,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let name = request
+        .uri()
+        .path()
+        .strip_prefix(HANDOFF_PREFIX)
+        .unwrap_or_default()
+        .to_string();
+    let bearer = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let body = match Limited::new(request.into_body(), MAX_HANDOFF_SIZE)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(err) => return respond(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    let handoff: Handoff = match serde_json::from_slice(&body) {
+        Ok(handoff) => handoff,
+        Err(err) => return respond(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+
+    let Some((instance, _)) = registry.get(&name).await else {
+        return respond(StatusCode::NOT_FOUND, String::new());
+    };
+    let secret = instance
+        .lock()
+        .await
+        .shard()
+        .map(|shard| shard.secret.clone());
+    let authorized = secret
+        .zip(bearer)
+        .is_some_and(|(secret, bearer)| bool::from(bearer.as_bytes().ct_eq(secret.as_bytes())));
+    if !authorized {
+        return respond(StatusCode::UNAUTHORIZED, String::new());
+    }
+
+    // Journaled by the instance, the player is saved along with the next
+    // save rather than while the loop waits for the lock.
+    let result = instance.lock().await.accept_handoff(&handoff).await;
+    match result {
+        Ok(()) => respond(StatusCode::OK, String::new()),
+        Err(err) => {
+            log::warn!("Handoff of {} refused: {}", handoff.nickname, err);
+            let status = match err {
+                Error::PlayerAlreadyAuthenticated => StatusCode::CONFLICT,
+                Error::InvalidNickname | Error::HandoffRefused(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            respond(status, err.to_string())
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Point-in-time copies of the galaxy of an instance, taken while it runs,
+//! to roll it back to when something went wrong.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::error::Error;
+use crate::export::GalaxyExport;
+use crate::instance::Instance;
+use crate::Result;
+
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+pub const SNAPSHOT_EXTENSION: &str = "json";
+
+const HOUR_MS: u64 = 3_600_000;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// Which snapshots are kept once a new one is taken: the last ones, and the
+/// last one of each of the latest hours and days having any.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_hourly: usize,
+    pub keep_daily: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_last: 6,
+            keep_hourly: 24,
+            keep_daily: 7,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Ids among `ids`, which are when the snapshots were taken, the policy
+    /// drops.
+    pub fn expired(&self, ids: &[u64]) -> Vec<u64> {
+        let mut newest_first = ids.to_vec();
+        newest_first.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut kept: BTreeSet<u64> = newest_first.iter().take(self.keep_last).copied().collect();
+        for (period, count) in [(HOUR_MS, self.keep_hourly), (DAY_MS, self.keep_daily)] {
+            let mut periods = BTreeSet::new();
+            for id in &newest_first {
+                if periods.len() == count {
+                    break;
+                }
+                if periods.insert(id / period) {
+                    kept.insert(*id);
+                }
+            }
+        }
+
+        newest_first
+            .into_iter()
+            .filter(|id| !kept.contains(id))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotConfig {
+    pub period: Duration,
+    pub retention: RetentionPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SnapshotInfo {
+    /// Milliseconds since the epoch when it was taken.
+    pub id: u64,
+    pub size: u64,
+}
+
+/// Snapshots of one instance, galaxy exports in a directory of their own.
+#[derive(Clone, Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+}
+
+impl Snapshots {
+    pub fn new(dir: impl Into<PathBuf>) -> Snapshots {
+        Snapshots { dir: dir.into() }
+    }
+
+    fn path_of(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, SNAPSHOT_EXTENSION))
+    }
+
+    fn error(&self, err: impl ToString) -> Error {
+        Error::SnapshotError(self.dir.to_string_lossy().to_string(), err.to_string())
+    }
+
+    /// Exports `instance`, held only while it saves. The galaxy is then read
+    /// back from its store, as unloaded systems are only there, and written
+    /// with `retention` applied off the runtime, without it.
+    pub async fn take(
+        &self,
+        instance: &Mutex<Instance>,
+        retention: Option<RetentionPolicy>,
+    ) -> Result<SnapshotInfo> {
+        let export = instance.lock().await.save_for_export().await?;
+        let export = export.await?;
+        let snapshots = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let info = snapshots.write(&export)?;
+            if let Some(retention) = retention {
+                snapshots.prune(&retention)?;
+            }
+            Ok(info)
+        })
+        .await
+        .map_err(|err| self.error(err))?
+    }
+
+    /// Oldest first.
+    pub fn list(&self) -> Result<Vec<SnapshotInfo>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(self.error(err)),
+        };
+
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|err| self.error(err))?;
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SNAPSHOT_EXTENSION)
+            {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            else {
+                continue;
+            };
+            let size = entry.metadata().map_err(|err| self.error(err))?.len();
+            snapshots.push(SnapshotInfo { id, size });
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.id);
+        Ok(snapshots)
+    }
+
+    /// Writes `export` as a new snapshot, only listed once complete.
+    pub fn write(&self, export: &GalaxyExport) -> Result<SnapshotInfo> {
+        fs::create_dir_all(&self.dir).map_err(|err| self.error(err))?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut id = now;
+        while self.path_of(id).exists() {
+            id += 1;
+        }
+
+        let path = self.path_of(id);
+        let partial = path.with_extension("partial");
+        export.write(&partial.to_string_lossy())?;
+        fs::rename(&partial, &path).map_err(|err| self.error(err))?;
+        let size = fs::metadata(&path).map_err(|err| self.error(err))?.len();
+        Ok(SnapshotInfo { id, size })
+    }
+
+    pub fn read(&self, id: u64) -> Result<GalaxyExport> {
+        let path = self.path_of(id);
+        if !path.exists() {
+            return Err(Error::SnapshotNotFound(id.to_string()));
+        }
+        GalaxyExport::read(&path.to_string_lossy())
+    }
+
+    /// Deletes the snapshots `policy` drops, returning their ids.
+    pub fn prune(&self, policy: &RetentionPolicy) -> Result<Vec<u64>> {
+        let ids: Vec<u64> = self.list()?.iter().map(|snapshot| snapshot.id).collect();
+        let expired = policy.expired(&ids);
+        for id in &expired {
+            fs::remove_file(self.path_of(*id)).map_err(|err| self.error(err))?;
+        }
+        Ok(expired)
+    }
+}
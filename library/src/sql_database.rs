@@ -1,12 +1,373 @@
 use crate::error::Error;
+use crate::game::mining::Ore;
 use crate::{Id, Result};
-use sqlx::sqlite::SqliteRow;
-use sqlx::{Pool, Sqlite};
+use itertools::Itertools;
+use sqlx::query::Query;
+use sqlx::sqlite::{SqliteArguments, SqliteRow};
+use sqlx::{Connection, Pool, Row, Sqlite};
+use std::fmt::Display;
+
+/// Parameters bound in a single execution, the lowest limit SQLite was ever
+/// built with.
+pub const MAX_BOUND_PARAMETERS: usize = 999;
+
+/// Tables of the schema. Values are always bound, so these and [`COLUMNS`] are
+/// the only names ever written into SQL.
+pub const TABLES: [&str; 19] = [
+    "sqlite_master",
+    "schema_version",
+    "Body",
+    "Player",
+    "Star",
+    "Planet",
+    "Moon",
+    "Asteroid",
+    "Npc",
+    "Structure",
+    "AsteroidOre",
+    "Inventory",
+    "Ship",
+    "Discovery",
+    "DiscoveredSystem",
+    "Chat",
+    "Journal",
+    "StarSystem",
+    "Galaxy",
+];
+
+/// Columns of the schema, besides the ore columns of `AsteroidOre`.
+pub const COLUMNS: [&str; 34] = [
+    "id",
+    "name",
+    "owner",
+    "coordinate_x",
+    "coordinate_y",
+    "coordinate_z",
+    "local_direction_x",
+    "local_direction_y",
+    "local_direction_z",
+    "local_speed",
+    "angular_speed",
+    "rotating_speed",
+    "gravity_center",
+    "nickname",
+    "body_id",
+    "brain",
+    "hull",
+    "shield",
+    "kind",
+    "player_id",
+    "item",
+    "amount",
+    "info",
+    "star_id",
+    "discovered_at",
+    "channel",
+    "system_id",
+    "sender",
+    "message",
+    "timestamp",
+    "sequence",
+    "version",
+    "saved_at",
+    "generation_seed",
+];
+
+pub(crate) fn table(name: &str) -> Result<&str> {
+    if TABLES.contains(&name) {
+        Ok(name)
+    } else {
+        Err(Error::DbInvalidIdentifier(name.to_string()))
+    }
+}
+
+/// The table `name` refers to, whatever its case.
+pub(crate) fn table_named(name: &str) -> Result<&'static str> {
+    TABLES
+        .iter()
+        .find(|table| table.eq_ignore_ascii_case(name))
+        .copied()
+        .ok_or_else(|| Error::DbInvalidIdentifier(name.to_string()))
+}
+
+/// Checks a column name, which may be qualified by its table.
+pub(crate) fn column(name: &str) -> Result<&str> {
+    let unqualified = match name.split_once('.') {
+        Some((table_name, column_name)) => {
+            table(table_name)?;
+            column_name
+        }
+        None => name,
+    };
+    if COLUMNS.contains(&unqualified) || Ore::ALL.iter().any(|ore| ore.column() == unqualified) {
+        Ok(name)
+    } else {
+        Err(Error::DbInvalidIdentifier(name.to_string()))
+    }
+}
+
+/// Checks a column declaration: a known column followed by its type and
+/// constraints, which are plain words and may reference a known table.
+fn declaration(entry: &str) -> Result<&str> {
+    let (name, rest) = entry.split_once(' ').unwrap_or((entry, ""));
+    column(name)?;
+    if !rest
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ' ')
+    {
+        return Err(Error::DbInvalidIdentifier(entry.to_string()));
+    }
+    let mut words = rest.split(' ');
+    while let Some(word) = words.next() {
+        if word == "REFERENCES" {
+            table(words.next().unwrap_or_default())?;
+        }
+    }
+    Ok(entry)
+}
+
+/// A row referencing another one which doesn't exist.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Orphan {
+    pub table: String,
+    pub rowid: i64,
+    pub column: String,
+    pub parent: String,
+}
+
+impl Display for Orphan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} row {}: {} references a missing {}",
+            self.table, self.rowid, self.column, self.parent
+        )
+    }
+}
 
 pub struct SqlDatabase {
     pub(crate) pool: Pool<Sqlite>,
 }
 
+/// A value bound to a statement parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl Display for SqlValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SqlValue::Null => write!(f, "NULL"),
+            SqlValue::Integer(value) => write!(f, "{}", value),
+            SqlValue::Real(value) => write!(f, "{}", value),
+            SqlValue::Text(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+fn bind<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    value: &'q SqlValue,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    match value {
+        SqlValue::Null => query.bind(None::<i64>),
+        SqlValue::Integer(value) => query.bind(*value),
+        SqlValue::Real(value) => query.bind(*value),
+        SqlValue::Text(value) => query.bind(value.as_str()),
+    }
+}
+
+/// A prepared statement and the rows bound to it, executed as many rows at
+/// once as the parameters limit allows.
+#[derive(Clone, Debug)]
+pub struct Statement {
+    identifiers: Vec<String>,
+    head: String,
+    placeholders: String,
+    tail: String,
+    width: usize,
+    rows: Vec<Vec<SqlValue>>,
+}
+
+impl Statement {
+    /// Upserts rows of `width` values into `table_name`, updating the columns
+    /// of `upserts` on an id conflict.
+    pub fn upsert(table_name: &str, width: usize, upserts: Vec<(&str, &str)>) -> Statement {
+        let mut tail = " ON CONFLICT(id) DO UPDATE SET ".to_string();
+        tail += &upserts
+            .iter()
+            .map(|(column, excluded)| format!("{}=excluded.{}", column, excluded))
+            .join(",");
+
+        let mut identifiers = vec![table_name.to_string()];
+        for (column, excluded) in &upserts {
+            identifiers.push(column.to_string());
+            identifiers.push(excluded.to_string());
+        }
+
+        Statement {
+            identifiers,
+            head: format!("INSERT INTO {} VALUES ", table_name),
+            placeholders: format!("({})", vec!["?"; width].join(",")),
+            tail,
+            width,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Inserts rows of the values of `columns` into `table_name`, the others
+    /// taking their default, such as a generated id.
+    pub fn insert(table_name: &str, columns: Vec<&str>) -> Statement {
+        let mut identifiers = vec![table_name.to_string()];
+        identifiers.extend(columns.iter().map(|column| column.to_string()));
+
+        Statement {
+            identifiers,
+            head: format!("INSERT INTO {} ({}) VALUES ", table_name, columns.join(",")),
+            placeholders: format!("({})", vec!["?"; columns.len()].join(",")),
+            tail: String::new(),
+            width: columns.len(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Deletes the rows of `table_name` whose `column_name` is one of the
+    /// values pushed.
+    pub fn delete(table_name: &str, column_name: &str) -> Statement {
+        Statement {
+            identifiers: vec![table_name.to_string(), column_name.to_string()],
+            head: format!("DELETE FROM {} WHERE {} IN (", table_name, column_name),
+            placeholders: "?".to_string(),
+            tail: ")".to_string(),
+            width: 1,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<SqlValue>) {
+        debug_assert_eq!(self.width, row.len());
+        self.rows.push(row);
+    }
+
+    pub fn with(mut self, rows: Vec<Vec<SqlValue>>) -> Statement {
+        for row in rows {
+            self.push(row);
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Rejects the statement if it names anything outside of the schema.
+    pub(crate) fn check(&self) -> Result<()> {
+        table(&self.identifiers[0])?;
+        for name in &self.identifiers[1..] {
+            column(name)?;
+        }
+        Ok(())
+    }
+
+    /// The SQL and rows of each execution, with `$1`, `$2`... placeholders
+    /// when `numbered`. Full chunks share their SQL, so it is only prepared
+    /// once.
+    pub(crate) fn executions(&self, numbered: bool) -> Vec<(String, &[Vec<SqlValue>])> {
+        let per_execution = (MAX_BOUND_PARAMETERS / self.width).max(1);
+        self.rows
+            .chunks(per_execution)
+            .map(|rows| (self.sql(rows.len(), numbered), rows))
+            .collect()
+    }
+
+    fn sql(&self, rows: usize, numbered: bool) -> String {
+        let placeholders = vec![self.placeholders.as_str(); rows].join(",");
+        let placeholders = if numbered {
+            let mut parameter = 0;
+            placeholders
+                .split('?')
+                .enumerate()
+                .map(|(i, part)| {
+                    if i == 0 {
+                        part.to_string()
+                    } else {
+                        parameter += 1;
+                        format!("${}{}", parameter, part)
+                    }
+                })
+                .collect()
+        } else {
+            placeholders
+        };
+        format!("{}{}{}", self.head, placeholders, self.tail)
+    }
+}
+
+/// Statements creating table `name` with `entries` and `indexes`, unless it
+/// already exists.
+pub fn create_table_statements(
+    name: &str,
+    entries: Vec<&str>,
+    indexes: Vec<&str>,
+) -> Result<Vec<String>> {
+    table(name)?;
+    for entry in &entries {
+        declaration(entry)?;
+    }
+    for index in &indexes {
+        column(index)?;
+    }
+
+    let mut statements = vec![format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        name,
+        entries.join(",")
+    )];
+    for index in indexes {
+        statements.push(format!(
+            "CREATE INDEX IF NOT EXISTS {}_index_{} ON {} ({})",
+            index, name, name, index
+        ));
+    }
+    Ok(statements)
+}
+
+/// Statements recreating table `name` with `entries`, keeping its rows, as
+/// SQLite can't add constraints to existing columns. The columns must stay
+/// the same and in the same order, and foreign keys must be off so that
+/// dropping the table doesn't cascade to the rows referencing it.
+pub fn rebuild_table_statements(
+    name: &str,
+    entries: Vec<&str>,
+    indexes: Vec<&str>,
+) -> Result<Vec<String>> {
+    table(name)?;
+    for entry in &entries {
+        declaration(entry)?;
+    }
+    for index in &indexes {
+        column(index)?;
+    }
+
+    let mut statements = vec![
+        format!("CREATE TABLE {}_rebuilt ({})", name, entries.join(",")),
+        format!("INSERT INTO {}_rebuilt SELECT * FROM {}", name, name),
+        format!("DROP TABLE {}", name),
+        format!("ALTER TABLE {}_rebuilt RENAME TO {}", name, name),
+    ];
+    for index in indexes {
+        statements.push(format!(
+            "CREATE INDEX {}_index_{} ON {} ({})",
+            index, name, name, index
+        ));
+    }
+    Ok(statements)
+}
+
 impl SqlDatabase {
     pub async fn create_table(
         &mut self,
@@ -14,59 +375,146 @@ impl SqlDatabase {
         entries: Vec<&str>,
         indexes: Vec<&str>,
     ) -> Result<()> {
-        if self
-            .select_from_where_equals("sqlite_master ", "name", name)
-            .await?
-            .len()
-            > 0
-        {
-            return Ok(());
-        }
-
-        let mut sql_str = format!("CREATE TABLE {} (", name);
-        for entry in entries {
-            sql_str += format!("{},", entry).as_str();
+        for statement in create_table_statements(name, entries, indexes)? {
+            sqlx::query(&statement)
+                .execute(&self.pool)
+                .await
+                .map_err(|err| Error::DbCreateTableError(name.to_string(), err))?;
         }
-        sql_str = sql_str.strip_suffix(",").unwrap().to_string();
-        sql_str += ");";
+        Ok(())
+    }
 
-        sqlx::query(&sql_str)
-            .execute(&self.pool)
+    /// Runs `statements` and sets the schema version to `version` in one
+    /// transaction, with foreign keys off, so that a file is either migrated
+    /// to `version` or left as it was.
+    pub async fn migrate_to(&mut self, version: u32, statements: Vec<String>) -> Result<()> {
+        let mut connection = self
+            .pool
+            .acquire()
             .await
-            .map_err(|err| Error::DbCreateTableError(name.to_string(), err))?;
+            .map_err(Error::DbTransactionError)?;
+        sqlx::query("PRAGMA foreign_keys = OFF")
+            .execute(&mut *connection)
+            .await
+            .map_err(Error::DbTransactionError)?;
 
-        for index in indexes {
+        let migrated = async {
+            let mut transaction = connection
+                .begin()
+                .await
+                .map_err(Error::DbTransactionError)?;
+            for statement in &statements {
+                sqlx::query(statement)
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|err| Error::DbCreateTableError(statement.clone(), err))?;
+            }
             sqlx::query(
-                format!(
-                    "CREATE INDEX {}_index_{} ON {} ({})",
-                    index, name, name, index
-                )
-                .as_str(),
+                "INSERT INTO schema_version VALUES (1, ?) \
+                 ON CONFLICT(id) DO UPDATE SET version = excluded.version",
             )
-            .execute(&self.pool)
+            .bind(version as i64)
+            .execute(&mut *transaction)
             .await
-            .map_err(|err| Error::DbCreateTableError(name.to_string(), err))?;
+            .map_err(|err| Error::SqlDbInsertError("schema_version".to_string(), err))?;
+            transaction
+                .commit()
+                .await
+                .map_err(Error::DbTransactionError)
         }
-        Ok(())
+        .await;
+
+        sqlx::query("PRAGMA foreign_keys = ON")
+            .execute(&mut *connection)
+            .await
+            .map_err(Error::DbTransactionError)?;
+        migrated
+    }
+
+    /// Rows whose foreign keys reference missing rows.
+    pub async fn foreign_key_check(&mut self) -> Result<Vec<Orphan>> {
+        let rows = sqlx::query(
+            "SELECT c.\"table\", c.rowid, c.parent, f.\"from\" \
+             FROM pragma_foreign_key_check() AS c \
+             JOIN pragma_foreign_key_list(c.\"table\") AS f ON f.id = c.fkid",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::DbLoadError)?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(Orphan {
+                    table: row.try_get(0).map_err(Error::DbLoadError)?,
+                    rowid: row.try_get(1).map_err(Error::DbLoadError)?,
+                    parent: row.try_get(2).map_err(Error::DbLoadError)?,
+                    column: row.try_get(3).map_err(Error::DbLoadError)?,
+                })
+            })
+            .collect()
     }
+
     pub async fn select_from_where_equals(
         &mut self,
         table_name: &str,
         column_name: &str,
-        value: &str,
+        value: &SqlValue,
     ) -> Result<Vec<SqliteRow>> {
-        let rows =
-            sqlx::query(format!("SELECT * FROM {} WHERE {}=?", table_name, column_name).as_str())
-                .bind(value)
-                .fetch_all(&self.pool)
-                .await
-                .map_err(|err| {
-                    Error::DbSelectFromWhereError(
-                        table_name.to_string(),
-                        format!("{}={}", column_name, value),
-                        err,
-                    )
-                })?;
+        table(table_name)?;
+        column(column_name)?;
+
+        let sql = format!("SELECT * FROM {} WHERE {}=?", table_name, column_name);
+        let rows = bind(sqlx::query(&sql), value)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|err| {
+                Error::DbSelectFromWhereError(
+                    table_name.to_string(),
+                    format!("{}={}", column_name, value),
+                    err,
+                )
+            })?;
+
+        Ok(rows)
+    }
+
+    pub async fn select_all_rows(&mut self, table_name: &str) -> Result<Vec<SqliteRow>> {
+        table(table_name)?;
+
+        sqlx::query(format!("SELECT * FROM {}", table_name).as_str())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|err| {
+                Error::DbSelectFromWhereError(table_name.to_string(), "all".to_string(), err)
+            })
+    }
+
+    pub async fn select_last_rows(
+        &mut self,
+        table_name: &str,
+        order_column_name: &str,
+        limit: usize,
+    ) -> Result<Vec<SqliteRow>> {
+        table(table_name)?;
+        column(order_column_name)?;
+
+        let rows = sqlx::query(
+            format!(
+                "SELECT * FROM {} ORDER BY {} DESC LIMIT ?",
+                table_name, order_column_name
+            )
+            .as_str(),
+        )
+        .bind(limit.min(i64::MAX as usize) as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| {
+            Error::DbSelectFromWhereError(
+                table_name.to_string(),
+                format!("last {} by {}", limit, order_column_name),
+                err,
+            )
+        })?;
 
         Ok(rows)
     }
@@ -81,9 +529,16 @@ impl SqlDatabase {
         where_column_name: &str,
         where_value_name: &str,
     ) -> Result<Vec<SqliteRow>> {
+        table(first_table_name)?;
+        table(second_table_name)?;
+        column(join_left)?;
+        column(join_right)?;
+        column(where_column_name)?;
+
         let mut select_part = "".to_string();
 
         for select_item in select {
+            column(select_item)?;
             select_part += select_item;
             select_part += ",";
         }
@@ -121,39 +576,40 @@ impl SqlDatabase {
         Ok(rows)
     }
 
-    fn vec_to_insert_str(
-        table_name: &str,
-        values: Vec<Vec<String>>,
-        upserts: Vec<(&str, &str)>,
-    ) -> String {
-        let mut insert_sql_str = format!("INSERT INTO {} VALUES ", table_name);
-
-        for line in values {
-            insert_sql_str += "(";
-            for value in line {
-                insert_sql_str += value.as_str();
-                insert_sql_str += ",";
-            }
-            insert_sql_str = insert_sql_str.strip_suffix(",").unwrap().to_string();
-            insert_sql_str += "),";
+    /// Runs `statements` in order inside one transaction: either all of them
+    /// are applied or none is.
+    pub async fn execute_in_transaction(&mut self, statements: Vec<Statement>) -> Result<()> {
+        for statement in &statements {
+            statement.check()?;
         }
 
-        insert_sql_str = insert_sql_str.strip_suffix(",").unwrap().to_string();
+        let mut transaction = self.pool.begin().await.map_err(Error::DbTransactionError)?;
 
-        insert_sql_str += "ON CONFLICT(id) DO UPDATE SET ";
-
-        for upsert in upserts {
-            insert_sql_str += upsert.0;
-            insert_sql_str += "=";
-            insert_sql_str += "excluded.";
-            insert_sql_str += upsert.1;
-            insert_sql_str += ",";
+        for statement in statements {
+            for (sql, rows) in statement.executions(false) {
+                let mut query = sqlx::query(&sql);
+                for value in rows.iter().flatten() {
+                    query = bind(query, value);
+                }
+                query
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|err| Error::SqlDbInsertError(sql.clone(), err))?;
+            }
         }
 
-        insert_sql_str.strip_suffix(",").unwrap().to_string()
+        transaction
+            .commit()
+            .await
+            .map_err(Error::DbTransactionError)?;
+
+        Ok(())
     }
 
     pub async fn max_in(&mut self, table_name: &str, column_name: &str) -> Result<Option<Id>> {
+        table(table_name)?;
+        column(column_name)?;
+
         let result: sqlx::Result<i64> =
             sqlx::query_scalar(format!("SELECT MAX({}) FROM {}", column_name, table_name).as_str())
                 .fetch_one(&self.pool)
@@ -166,35 +622,55 @@ impl SqlDatabase {
         }
     }
 
+    /// Version of the schema, 0 for files from before versioning.
+    pub async fn schema_version(&mut self) -> Result<u32> {
+        self.create_table(
+            "schema_version",
+            vec!["id INTEGER PRIMARY KEY", "version INTEGER NOT NULL"],
+            vec![],
+        )
+        .await?;
+
+        let rows = self
+            .select_from_where_equals("schema_version", "id", &SqlValue::Integer(1))
+            .await?;
+        match rows.first() {
+            Some(row) => {
+                let version: i64 = row.try_get("version").map_err(Error::DbLoadError)?;
+                Ok(version as u32)
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub async fn set_schema_version(&mut self, version: u32) -> Result<()> {
+        self.insert_row_into(
+            "schema_version",
+            vec![SqlValue::Integer(1), SqlValue::Integer(version as i64)],
+            vec![("version", "version")],
+        )
+        .await
+    }
+
     pub async fn insert_row_into(
         &mut self,
         table_name: &str,
-        row: Vec<String>,
+        row: Vec<SqlValue>,
         upserts: Vec<(&str, &str)>,
     ) -> Result<()> {
-        let insert_sql_str = Self::vec_to_insert_str(table_name, vec![row], upserts);
-
-        sqlx::query(&insert_sql_str)
-            .execute(&self.pool)
-            .await
-            .map_err(|err| Error::SqlDbInsertError(insert_sql_str, err))?;
-
-        Ok(())
+        self.insert_rows_into(table_name, vec![row], upserts).await
     }
 
     pub async fn insert_rows_into(
         &mut self,
         table_name: &str,
-        values: Vec<Vec<String>>,
+        values: Vec<Vec<SqlValue>>,
         upserts: Vec<(&str, &str)>,
     ) -> Result<()> {
-        let insert_sql_str = Self::vec_to_insert_str(table_name, values, upserts);
-
-        sqlx::query(&insert_sql_str)
-            .execute(&self.pool)
-            .await
-            .map_err(|err| Error::SqlDbInsertError(insert_sql_str, err))?;
-
-        Ok(())
+        let Some(width) = values.first().map(Vec::len) else {
+            return Ok(());
+        };
+        let statement = Statement::upsert(table_name, width, upserts).with(values);
+        self.execute_in_transaction(vec![statement]).await
     }
 }
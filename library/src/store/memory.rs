@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use crate::error::Error;
+use crate::game::celestial_body::CelestialBody;
+use crate::game::entity::npc::Npc;
+use crate::game::entity::player::Player;
+use crate::game::entity::Entity;
+use crate::game::repr::Vector3;
+use crate::game::sensors::Sensors;
+use crate::protocol::{ChatInfo, GameInfo};
+use crate::sql_database::Orphan;
+use crate::store::{ChatEntry, Dump, GalaxyStore, NextIds, SaveBatch};
+use crate::{Id, Result};
+
+/// The galaxy in memory, lost with the store: for tests which don't need a
+/// file.
+#[derive(Default)]
+pub struct MemoryStore {
+    bodies: BTreeMap<Id, CelestialBody>,
+    chat: BTreeMap<Id, ChatEntry>,
+    saved_at: BTreeMap<Id, u64>,
+    journal_checkpoint: u32,
+    generation_seed: Option<u64>,
+}
+
+impl MemoryStore {
+    /// What the SQL stores would keep of `body`.
+    fn stored(body: &CelestialBody) -> CelestialBody {
+        let mut stored = body.clone();
+        match &body.entity {
+            Entity::Player(player) => {
+                stored.entity = Entity::Player(Self::player(player, player.infos_sender.clone()));
+            }
+            Entity::Npc(npc) => {
+                let mut stored_npc = Npc::new(npc.id, npc.brain.kind().brain());
+                stored_npc.ship.hull = npc.ship.hull;
+                stored_npc.ship.shield = npc.ship.shield;
+                stored.entity = Entity::Npc(stored_npc);
+            }
+            _ => {}
+        }
+        stored
+    }
+
+    fn player(player: &Player, infos_sender: tokio::sync::mpsc::Sender<GameInfo>) -> Player {
+        let mut stored = Player::new(player.id, player.nickname.clone(), infos_sender);
+        stored.inventory = player.inventory.clone();
+        stored.ship.hull = player.ship.hull;
+        stored.ship.shield = player.ship.shield;
+        *stored.sensors = Sensors::from_discovered(
+            player.sensors.discovered.clone(),
+            player.sensors.systems.clone(),
+        );
+        stored
+    }
+
+    fn player_ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.bodies.values().filter_map(|body| match &body.entity {
+            Entity::Player(player) => Some(player.id),
+            _ => None,
+        })
+    }
+
+    fn ids_where(&self, matches: impl Fn(&CelestialBody) -> bool) -> Vec<Id> {
+        self.bodies
+            .values()
+            .filter(|body| matches(body))
+            .map(|body| body.id)
+            .collect()
+    }
+}
+
+impl GalaxyStore for MemoryStore {
+    fn next_ids(&mut self) -> BoxFuture<'_, Result<NextIds>> {
+        let next_ids = NextIds {
+            body: self.bodies.keys().max().map_or(1, |id| id + 1),
+            player: self.player_ids().max().map_or(1, |id| id + 1),
+            chat: self.chat.keys().max().map_or(1, |id| id + 1),
+            journal_checkpoint: self.journal_checkpoint,
+            generation_seed: self.generation_seed,
+        };
+        async move { Ok(next_ids) }.boxed()
+    }
+
+    fn load_body(&mut self, id: Id) -> BoxFuture<'_, Result<CelestialBody>> {
+        let body = match self.bodies.get(&id) {
+            Some(CelestialBody {
+                entity: Entity::Player(_),
+                ..
+            }) => Err(Error::BodyIsPlayer(id)),
+            Some(body) => Ok(Self::stored(body)),
+            None => Err(Error::DbUuidNotFound(id)),
+        };
+        async move { body }.boxed()
+    }
+
+    fn load_player<'a>(
+        &'a mut self,
+        nickname: &'a str,
+        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+    ) -> BoxFuture<'a, Result<CelestialBody>> {
+        let player = self.bodies.values().find_map(|body| match &body.entity {
+            Entity::Player(player) if player.nickname == nickname => {
+                let mut loaded = body.clone();
+                loaded.entity = Entity::Player(Self::player(player, infos_sender.clone()));
+                Some(loaded)
+            }
+            _ => None,
+        });
+        async move { player.ok_or(Error::DbLoadPlayerByNicknameNotFound) }.boxed()
+    }
+
+    fn load_rotatings(&mut self, id: Id) -> BoxFuture<'_, Result<Vec<Id>>> {
+        let ids = self.ids_where(|body| body.gravity_center == id);
+        async move { Ok(ids) }.boxed()
+    }
+
+    fn load_saved_at(&mut self, star: Id) -> BoxFuture<'_, Result<Option<u64>>> {
+        let saved_at = self.saved_at.get(&star).copied();
+        async move { Ok(saved_at) }.boxed()
+    }
+
+    fn load_stars(&mut self) -> BoxFuture<'_, Result<Vec<(Id, Vector3)>>> {
+        let stars = self
+            .bodies
+            .values()
+            .filter(|body| matches!(body.entity, Entity::Star(_)))
+            .map(|body| (body.id, body.coords))
+            .collect();
+        async move { Ok(stars) }.boxed()
+    }
+
+    fn load_ownings(&mut self, player_id: Id) -> BoxFuture<'_, Result<Vec<Id>>> {
+        let ids = self.ids_where(|body| body.owner == player_id);
+        async move { Ok(ids) }.boxed()
+    }
+
+    fn load_chat(&mut self, limit: usize) -> BoxFuture<'_, Result<Vec<(Id, ChatInfo)>>> {
+        let skipped = self.chat.len().saturating_sub(limit);
+        let chat = self
+            .chat
+            .values()
+            .skip(skipped)
+            .map(|entry| (entry.system, entry.info.clone()))
+            .collect();
+        async move { Ok(chat) }.boxed()
+    }
+
+    fn dump(&self) -> BoxFuture<'static, Result<Dump>> {
+        let bodies = self.bodies.values().map(Self::stored).collect();
+        let chat = self
+            .chat
+            .values()
+            .map(|entry| (entry.system, entry.info.clone()))
+            .collect();
+        async move { Ok((bodies, chat)) }.boxed()
+    }
+
+    fn save<'a>(&'a mut self, batch: SaveBatch<'a>) -> BoxFuture<'a, Result<()>> {
+        for body in batch.bodies {
+            self.bodies.insert(body.id, Self::stored(body));
+        }
+        for star in batch.systems {
+            self.saved_at.insert(*star, batch.saved_at);
+        }
+        for id in batch.removals {
+            self.bodies.remove(id);
+            self.saved_at.remove(id);
+        }
+        for entry in batch.chat {
+            self.chat.insert(entry.id, entry.clone());
+        }
+        self.journal_checkpoint = batch.journal_checkpoint;
+        self.generation_seed = Some(batch.generation_seed);
+        async move { Ok(()) }.boxed()
+    }
+
+    fn replace<'a>(&'a mut self, batch: SaveBatch<'a>) -> BoxFuture<'a, Result<()>> {
+        self.bodies.clear();
+        self.chat.clear();
+        self.saved_at.clear();
+        self.save(batch)
+    }
+
+    fn check(&mut self) -> BoxFuture<'_, Result<Vec<Orphan>>> {
+        let players: Vec<Id> = self.player_ids().collect();
+        let mut orphans = Vec::new();
+        for body in self.bodies.values() {
+            if body.gravity_center != Id::MAX && !self.bodies.contains_key(&body.gravity_center) {
+                orphans.push(Orphan {
+                    table: "Body".to_string(),
+                    rowid: body.id as i64,
+                    column: "gravity_center".to_string(),
+                    parent: "Body".to_string(),
+                });
+            }
+            if body.owner != Id::MAX && !players.contains(&body.owner) {
+                orphans.push(Orphan {
+                    table: "Body".to_string(),
+                    rowid: body.id as i64,
+                    column: "owner".to_string(),
+                    parent: "Player".to_string(),
+                });
+            }
+        }
+        async move { Ok(orphans) }.boxed()
+    }
+}
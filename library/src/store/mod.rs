@@ -0,0 +1,102 @@
+use futures::future::BoxFuture;
+
+use crate::game::celestial_body::CelestialBody;
+use crate::game::repr::Vector3;
+use crate::protocol::{ChatInfo, GameInfo};
+use crate::sql_database::Orphan;
+use crate::{Id, Result};
+
+pub mod memory;
+pub mod postgres;
+pub mod sql;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+/// First ids free in a store, the last journal batch its state covers and
+/// the seed of its galaxy, unless it was never saved.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NextIds {
+    pub body: Id,
+    pub player: Id,
+    pub chat: Id,
+    pub journal_checkpoint: u32,
+    pub generation_seed: Option<u64>,
+}
+
+/// A chat message kept in the history, sent in `system`.
+#[derive(Clone, Debug)]
+pub struct ChatEntry {
+    pub id: Id,
+    pub system: Id,
+    pub info: ChatInfo,
+}
+
+/// Every body and chat message of a store, as `GalaxyStore::dump` reads
+/// them.
+pub type Dump = (Vec<CelestialBody>, Vec<(Id, ChatInfo)>);
+
+/// Everything a save writes, at once.
+pub struct SaveBatch<'a> {
+    /// Bodies changed since the last save, players along with their
+    /// inventory, ship and discoveries.
+    pub bodies: Vec<&'a CelestialBody>,
+    pub removals: &'a [Id],
+    pub chat: &'a [ChatEntry],
+    pub journal_checkpoint: u32,
+    pub generation_seed: u64,
+    /// Stars of the systems all of whose bodies are saved as they were at
+    /// `saved_at`, in milliseconds since the epoch.
+    pub systems: &'a [Id],
+    pub saved_at: u64,
+}
+
+/// Where the galaxy is kept between runs. Loads return what a save stored:
+/// brains, mining targets and other transient state start over.
+pub trait GalaxyStore: Send {
+    fn next_ids(&mut self) -> BoxFuture<'_, Result<NextIds>>;
+
+    /// Any body but a player's, which is loaded by nickname.
+    fn load_body(&mut self, id: Id) -> BoxFuture<'_, Result<CelestialBody>>;
+
+    /// The body of the player named `nickname` with what they carry, their
+    /// ownings left out.
+    fn load_player<'a>(
+        &'a mut self,
+        nickname: &'a str,
+        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+    ) -> BoxFuture<'a, Result<CelestialBody>>;
+
+    /// Ids of the bodies whose gravity center is `id`.
+    fn load_rotatings(&mut self, id: Id) -> BoxFuture<'_, Result<Vec<Id>>>;
+
+    /// When the bodies of the system of `star` were last saved, in
+    /// milliseconds since the epoch.
+    fn load_saved_at(&mut self, star: Id) -> BoxFuture<'_, Result<Option<u64>>>;
+
+    /// Ids and coordinates of every star.
+    fn load_stars(&mut self) -> BoxFuture<'_, Result<Vec<(Id, Vector3)>>>;
+
+    /// Ids of the bodies owned by `player_id`.
+    fn load_ownings(&mut self, player_id: Id) -> BoxFuture<'_, Result<Vec<Id>>>;
+
+    /// The last `limit` chat messages, oldest first, with their system.
+    fn load_chat(&mut self, limit: usize) -> BoxFuture<'_, Result<Vec<(Id, ChatInfo)>>>;
+
+    /// Every body, by id, players with what they carry but their ownings,
+    /// and every chat message, oldest first, all as of one moment. Read
+    /// apart from the store, which may go on being written meanwhile.
+    fn dump(&self) -> BoxFuture<'static, Result<Dump>>;
+
+    /// Writes `batch` entirely or not at all.
+    fn save<'a>(&'a mut self, batch: SaveBatch<'a>) -> BoxFuture<'a, Result<()>>;
+
+    /// Removes every body and chat message, then writes `batch`, entirely
+    /// or not at all.
+    fn replace<'a>(&'a mut self, batch: SaveBatch<'a>) -> BoxFuture<'a, Result<()>>;
+
+    /// Rows referencing missing bodies or players.
+    fn check(&mut self) -> BoxFuture<'_, Result<Vec<Orphan>>>;
+}
@@ -0,0 +1,430 @@
+use std::future::Future;
+
+use sqlx::postgres::{PgArguments, PgConnection, PgPool, PgRow};
+use sqlx::query::Query;
+use sqlx::{Connection, Postgres, Row};
+
+use crate::error::Error;
+use crate::game::mining::Ore;
+use crate::sql_database::{self, Orphan, SqlValue, Statement};
+use crate::store::sql::{Fields, SqlBackend, SqlStore};
+use crate::{Id, Result};
+
+/// Version of the PostgreSQL schema this server writes.
+pub const POSTGRES_SCHEMA_VERSION: u32 = 4;
+
+const REFERENCE_BODY: &str = "REFERENCES Body ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED";
+const REFERENCE_PLAYER: &str = "REFERENCES Player ON DELETE CASCADE DEFERRABLE INITIALLY DEFERRED";
+
+pub struct PgDatabase {
+    pub(crate) pool: PgPool,
+    /// Connection of a snapshot, reading in a transaction of its own.
+    pub(crate) snapshot: Option<PgConnection>,
+}
+
+/// The galaxy in a PostgreSQL database, for hosted deployments.
+pub type PostgresStore = SqlStore<PgDatabase>;
+
+fn bind<'q>(
+    query: Query<'q, Postgres, PgArguments>,
+    value: &'q SqlValue,
+) -> Query<'q, Postgres, PgArguments> {
+    match value {
+        SqlValue::Null => query.bind(None::<i64>),
+        SqlValue::Integer(value) => query.bind(*value),
+        SqlValue::Real(value) => query.bind(*value),
+        SqlValue::Text(value) => query.bind(value.as_str()),
+    }
+}
+
+/// Same tables as the sqlite files, with foreign keys from the start.
+fn schema() -> Vec<String> {
+    let mut ore_columns = format!("id BIGINT PRIMARY KEY {}", REFERENCE_BODY);
+    for ore in Ore::ALL {
+        ore_columns += &format!(", {} BIGINT NOT NULL DEFAULT 0", ore.column());
+    }
+
+    let mut statements = vec![
+        "CREATE TABLE Body (id BIGINT PRIMARY KEY, owner BIGINT, \
+         coordinate_x DOUBLE PRECISION NOT NULL, coordinate_y DOUBLE PRECISION NOT NULL, \
+         coordinate_z DOUBLE PRECISION NOT NULL, local_direction_x DOUBLE PRECISION NOT NULL, \
+         local_direction_y DOUBLE PRECISION NOT NULL, local_direction_z DOUBLE PRECISION NOT NULL, \
+         local_speed DOUBLE PRECISION, angular_speed DOUBLE PRECISION, \
+         rotating_speed DOUBLE PRECISION, gravity_center BIGINT REFERENCES Body \
+         ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED)"
+            .to_string(),
+        format!(
+            "CREATE TABLE Player (id BIGINT PRIMARY KEY, nickname TEXT, body_id BIGINT {})",
+            REFERENCE_BODY
+        ),
+        "ALTER TABLE Body ADD FOREIGN KEY (owner) REFERENCES Player \
+         ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED"
+            .to_string(),
+    ];
+    // New stars, planets, moons and asteroids are saved without an id, which
+    // sqlite picks as their rowid.
+    statements.push(
+        "CREATE FUNCTION default_id() RETURNS trigger AS $$ BEGIN \
+         IF NEW.id IS NULL THEN NEW.id := nextval(pg_get_serial_sequence(TG_TABLE_NAME, 'id')); \
+         END IF; RETURN NEW; END $$ LANGUAGE plpgsql"
+            .to_string(),
+    );
+    for table in ["Star", "Planet", "Moon", "Asteroid"] {
+        statements.push(format!(
+            "CREATE TABLE {} (id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY, body_id BIGINT {})",
+            table, REFERENCE_BODY
+        ));
+        statements.push(format!(
+            "CREATE TRIGGER default_id BEFORE INSERT ON {} FOR EACH ROW EXECUTE FUNCTION default_id()",
+            table
+        ));
+    }
+    statements.extend([
+        format!(
+            "CREATE TABLE Npc (id BIGINT PRIMARY KEY, body_id BIGINT {}, brain TEXT NOT NULL, \
+             hull DOUBLE PRECISION NOT NULL, shield DOUBLE PRECISION NOT NULL)",
+            REFERENCE_BODY
+        ),
+        format!(
+            "CREATE TABLE Structure (id BIGINT PRIMARY KEY, body_id BIGINT {}, kind TEXT NOT NULL)",
+            REFERENCE_BODY
+        ),
+        format!("CREATE TABLE AsteroidOre ({})", ore_columns),
+        format!(
+            "CREATE TABLE Inventory (id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY, \
+             player_id BIGINT NOT NULL {}, item TEXT NOT NULL, amount BIGINT NOT NULL)",
+            REFERENCE_PLAYER
+        ),
+        format!(
+            "CREATE TABLE Ship (id BIGINT PRIMARY KEY {}, hull DOUBLE PRECISION NOT NULL, \
+             shield DOUBLE PRECISION NOT NULL)",
+            REFERENCE_PLAYER
+        ),
+        format!(
+            "CREATE TABLE Discovery (id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY, \
+             player_id BIGINT NOT NULL {}, body_id BIGINT NOT NULL, info TEXT NOT NULL)",
+            REFERENCE_PLAYER
+        ),
+        format!(
+            "CREATE TABLE DiscoveredSystem (id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY, \
+             player_id BIGINT NOT NULL {}, star_id BIGINT NOT NULL, discovered_at BIGINT NOT NULL)",
+            REFERENCE_PLAYER
+        ),
+        "CREATE TABLE Chat (id BIGINT PRIMARY KEY, channel TEXT NOT NULL, system_id BIGINT, \
+         sender TEXT NOT NULL, message TEXT NOT NULL, timestamp BIGINT NOT NULL)"
+            .to_string(),
+        "CREATE TABLE Journal (id BIGINT PRIMARY KEY, sequence BIGINT NOT NULL)".to_string(),
+    ]);
+    for (table, column) in [
+        ("Body", "owner"),
+        ("Body", "gravity_center"),
+        ("Player", "body_id"),
+        ("Player", "nickname"),
+        ("Star", "body_id"),
+        ("Planet", "body_id"),
+        ("Moon", "body_id"),
+        ("Asteroid", "body_id"),
+        ("Npc", "body_id"),
+        ("Structure", "body_id"),
+        ("Inventory", "player_id"),
+        ("Discovery", "player_id"),
+        ("Discovery", "body_id"),
+        ("DiscoveredSystem", "player_id"),
+    ] {
+        statements.push(format!(
+            "CREATE INDEX {}_index_{} ON {} ({})",
+            column, table, table, column
+        ));
+    }
+    statements
+}
+
+/// Ids given explicitly, as on import, move the sequence past them.
+fn explicit_ids() -> Vec<String> {
+    vec![
+        "CREATE OR REPLACE FUNCTION default_id() RETURNS trigger AS $$ \
+         DECLARE sequence TEXT := pg_get_serial_sequence(TG_TABLE_NAME, 'id'); BEGIN \
+         IF NEW.id IS NULL THEN NEW.id := nextval(sequence); \
+         ELSE PERFORM setval(sequence, GREATEST(NEW.id, COALESCE(pg_sequence_last_value(sequence), 1))); \
+         END IF; RETURN NEW; END $$ LANGUAGE plpgsql"
+            .to_string(),
+    ]
+}
+
+/// When the bodies of each system were last saved.
+fn star_systems() -> Vec<String> {
+    vec![format!(
+        "CREATE TABLE StarSystem (id BIGINT PRIMARY KEY {}, saved_at BIGINT NOT NULL)",
+        REFERENCE_BODY
+    )]
+}
+
+/// The seed the systems of the galaxy are generated from.
+fn galaxy() -> Vec<String> {
+    vec!["CREATE TABLE Galaxy (id BIGINT PRIMARY KEY, generation_seed BIGINT NOT NULL)".to_string()]
+}
+
+impl PostgresStore {
+    /// Connects to the database at `url`, creating or migrating the galaxy
+    /// tables in it when needed.
+    pub async fn connect(url: &str) -> Result<PostgresStore> {
+        let pool = PgPool::connect(url)
+            .await
+            .map_err(|err| Error::DbOpenError(url.to_string(), err))?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS schema_version (id BIGINT PRIMARY KEY, version BIGINT NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .map_err(|err| Error::DbCreateTableError("schema_version".to_string(), err))?;
+        let version: Option<i64> =
+            sqlx::query_scalar("SELECT version FROM schema_version WHERE id = 1")
+                .fetch_optional(&pool)
+                .await
+                .map_err(Error::DbLoadError)?;
+        let version = version.unwrap_or_default() as u32;
+        if version > POSTGRES_SCHEMA_VERSION {
+            return Err(Error::DbSchemaTooNew(version, POSTGRES_SCHEMA_VERSION));
+        }
+
+        if version < POSTGRES_SCHEMA_VERSION {
+            log::info!(
+                "Migrating the galaxy tables in {} to version {}",
+                url,
+                POSTGRES_SCHEMA_VERSION
+            );
+            let mut statements = Vec::new();
+            if version < 1 {
+                statements.extend(schema());
+            }
+            if version < 2 {
+                statements.extend(explicit_ids());
+            }
+            if version < 3 {
+                statements.extend(star_systems());
+            }
+            if version < 4 {
+                statements.extend(galaxy());
+            }
+
+            let mut transaction = pool.begin().await.map_err(Error::DbTransactionError)?;
+            for statement in statements {
+                sqlx::query(&statement)
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|err| Error::DbCreateTableError(statement.clone(), err))?;
+            }
+            sqlx::query(
+                "INSERT INTO schema_version VALUES (1, $1) \
+                 ON CONFLICT(id) DO UPDATE SET version = excluded.version",
+            )
+            .bind(POSTGRES_SCHEMA_VERSION as i64)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|err| Error::SqlDbInsertError("schema_version".to_string(), err))?;
+            transaction
+                .commit()
+                .await
+                .map_err(Error::DbTransactionError)?;
+        }
+
+        Ok(SqlStore::new(PgDatabase {
+            pool,
+            snapshot: None,
+        }))
+    }
+}
+
+impl Fields for PgRow {
+    fn real(&self, column: &str) -> Result<f64> {
+        self.try_get(column).map_err(Error::DbLoadError)
+    }
+
+    fn integer(&self, column: &str) -> Result<Option<i64>> {
+        self.try_get(column).map_err(Error::DbLoadError)
+    }
+
+    fn text(&self, column: &str) -> Result<String> {
+        self.try_get(column).map_err(Error::DbLoadError)
+    }
+}
+
+impl PgDatabase {
+    async fn fetch_all(
+        &mut self,
+        query: Query<'_, Postgres, PgArguments>,
+    ) -> sqlx::Result<Vec<PgRow>> {
+        match &mut self.snapshot {
+            Some(connection) => query.fetch_all(connection).await,
+            None => query.fetch_all(&self.pool).await,
+        }
+    }
+}
+
+impl SqlBackend for PgDatabase {
+    type Row = PgRow;
+
+    async fn select(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        value: SqlValue,
+    ) -> Result<Vec<PgRow>> {
+        sql_database::table(table_name)?;
+        sql_database::column(column_name)?;
+
+        let sql = format!("SELECT * FROM {} WHERE {}=$1", table_name, column_name);
+        self.fetch_all(bind(sqlx::query(&sql), &value))
+            .await
+            .map_err(|err| {
+                Error::DbSelectFromWhereError(
+                    table_name.to_string(),
+                    format!("{}={}", column_name, value),
+                    err,
+                )
+            })
+    }
+
+    async fn select_all(&mut self, table_name: &str) -> Result<Vec<PgRow>> {
+        sql_database::table(table_name)?;
+
+        self.fetch_all(sqlx::query(&format!("SELECT * FROM {}", table_name)))
+            .await
+            .map_err(|err| {
+                Error::DbSelectFromWhereError(table_name.to_string(), "all".to_string(), err)
+            })
+    }
+
+    async fn select_last(
+        &mut self,
+        table_name: &str,
+        order_column_name: &str,
+        limit: usize,
+    ) -> Result<Vec<PgRow>> {
+        sql_database::table(table_name)?;
+        sql_database::column(order_column_name)?;
+
+        let sql = format!(
+            "SELECT * FROM {} ORDER BY {} DESC LIMIT $1",
+            table_name, order_column_name
+        );
+        self.fetch_all(sqlx::query(&sql).bind(limit.min(i64::MAX as usize) as i64))
+            .await
+            .map_err(|err| {
+                Error::DbSelectFromWhereError(
+                    table_name.to_string(),
+                    format!("last {} by {}", limit, order_column_name),
+                    err,
+                )
+            })
+    }
+
+    async fn max(&mut self, table_name: &str, column_name: &str) -> Result<Option<Id>> {
+        sql_database::table(table_name)?;
+        sql_database::column(column_name)?;
+
+        let sql = format!("SELECT MAX({}) FROM {}", column_name, table_name);
+        let rows = self
+            .fetch_all(sqlx::query(&sql))
+            .await
+            .map_err(Error::DbLastIdError)?;
+        let max: Option<i64> = match rows.first() {
+            Some(row) => row.try_get(0).map_err(Error::DbLastIdError)?,
+            None => None,
+        };
+        Ok(max.map(|max| max as Id))
+    }
+
+    async fn execute(&mut self, statements: Vec<Statement>) -> Result<()> {
+        for statement in &statements {
+            statement.check()?;
+        }
+
+        let mut transaction = self.pool.begin().await.map_err(Error::DbTransactionError)?;
+        for statement in &statements {
+            for (sql, rows) in statement.executions(true) {
+                let mut query = sqlx::query(&sql);
+                for value in rows.iter().flatten() {
+                    query = bind(query, value);
+                }
+                query
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|err| Error::SqlDbInsertError(sql.clone(), err))?;
+            }
+        }
+        transaction
+            .commit()
+            .await
+            .map_err(Error::DbTransactionError)
+    }
+
+    async fn foreign_key_check(&mut self) -> Result<Vec<Orphan>> {
+        // PostgreSQL folds the unquoted names of the tables to lower case.
+        let references: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT c.conrelid::regclass::text, a.attname::text, c.confrelid::regclass::text \
+             FROM pg_constraint AS c \
+             JOIN pg_attribute AS a ON a.attrelid = c.conrelid AND a.attnum = c.conkey[1] \
+             WHERE c.contype = 'f' AND c.connamespace = current_schema()::regnamespace",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::DbLoadError)?;
+
+        let mut orphans = Vec::new();
+        for (table, column, parent) in references {
+            // The database may hold other tables than the galaxy ones.
+            let (Ok(table), Ok(parent), Ok(column)) = (
+                sql_database::table_named(&table),
+                sql_database::table_named(&parent),
+                sql_database::column(&column),
+            ) else {
+                continue;
+            };
+
+            let sql = format!(
+                "SELECT c.id FROM {} AS c WHERE c.{} IS NOT NULL \
+                 AND NOT EXISTS (SELECT 1 FROM {} AS p WHERE p.id = c.{})",
+                table, column, parent, column
+            );
+            let rowids: Vec<i64> = sqlx::query_scalar(&sql)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(Error::DbLoadError)?;
+            orphans.extend(rowids.into_iter().map(|rowid| Orphan {
+                table: table.to_string(),
+                rowid,
+                column: column.to_string(),
+                parent: parent.to_string(),
+            }));
+        }
+        Ok(orphans)
+    }
+
+    /// A repeatable read transaction, which writes made meanwhile don't
+    /// wait for.
+    fn snapshot(&self) -> impl Future<Output = Result<PgDatabase>> + Send + 'static {
+        let pool = self.pool.clone();
+        async move {
+            let mut connection = PgConnection::connect_with(&pool.connect_options())
+                .await
+                .map_err(Error::DbTransactionError)?;
+            sqlx::query("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+                .execute(&mut connection)
+                .await
+                .map_err(Error::DbTransactionError)?;
+            Ok(PgDatabase {
+                pool,
+                snapshot: Some(connection),
+            })
+        }
+    }
+
+    async fn discard_snapshot(self) -> Result<()> {
+        match self.snapshot {
+            Some(connection) => connection.close().await.map_err(Error::DbTransactionError),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,833 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use itertools::Itertools;
+
+use crate::error::Error;
+use crate::game::brain::BrainKind;
+use crate::game::celestial_body::CelestialBody;
+use crate::game::combat::Ship;
+use crate::game::entity::asteroid::Asteroid;
+use crate::game::entity::moon::Moon;
+use crate::game::entity::npc::Npc;
+use crate::game::entity::planet::Planet;
+use crate::game::entity::player::Player;
+use crate::game::entity::star::Star;
+use crate::game::entity::structure::{Structure, StructureKind};
+use crate::game::entity::Entity;
+use crate::game::inventory::{Inventory, Item};
+use crate::game::mining::{Ore, Ores};
+use crate::game::repr::Vector3;
+use crate::game::sensors::Sensors;
+use crate::protocol::{BodyInfo, ChatChannel, ChatInfo, GameInfo};
+use crate::sql_database::{Orphan, SqlValue, Statement};
+use crate::store::{ChatEntry, Dump, GalaxyStore, NextIds, SaveBatch};
+use crate::{Id, Result};
+
+/// Values of a row, by column.
+pub trait Fields {
+    fn real(&self, column: &str) -> Result<f64>;
+    fn integer(&self, column: &str) -> Result<Option<i64>>;
+    fn text(&self, column: &str) -> Result<String>;
+}
+
+/// A database holding the galaxy tables.
+pub trait SqlBackend: Send + Sized + 'static {
+    type Row: Fields + Send + Sync;
+
+    fn select(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        value: SqlValue,
+    ) -> impl Future<Output = Result<Vec<Self::Row>>> + Send;
+
+    fn select_all(
+        &mut self,
+        table_name: &str,
+    ) -> impl Future<Output = Result<Vec<Self::Row>>> + Send;
+
+    /// The last `limit` rows by `order_column_name`, last first.
+    fn select_last(
+        &mut self,
+        table_name: &str,
+        order_column_name: &str,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<Self::Row>>> + Send;
+
+    fn max(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+    ) -> impl Future<Output = Result<Option<Id>>> + Send;
+
+    /// Runs `statements` in order inside one transaction.
+    fn execute(&mut self, statements: Vec<Statement>) -> impl Future<Output = Result<()>> + Send;
+
+    fn foreign_key_check(&mut self) -> impl Future<Output = Result<Vec<Orphan>>> + Send;
+
+    /// The database as it is now, read through a connection of its own
+    /// whatever is written meanwhile, until discarded.
+    fn snapshot(&self) -> impl Future<Output = Result<Self>> + Send + 'static;
+
+    /// Lets go of a backend `snapshot` returned.
+    fn discard_snapshot(self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// The galaxy in the tables of a SQL database, one per entity kind.
+pub struct SqlStore<B: SqlBackend> {
+    pub(crate) backend: B,
+}
+
+impl<B: SqlBackend> SqlStore<B> {
+    pub fn new(backend: B) -> SqlStore<B> {
+        SqlStore { backend }
+    }
+
+    fn id_from_row(row: &B::Row, column_name: &str) -> Result<Id> {
+        Ok(row
+            .integer(column_name)?
+            .map_or(Id::MAX, |value| value as Id))
+    }
+
+    fn coordinates_from_row(row: &B::Row, column_name_prefix: &str) -> Result<Vector3> {
+        Ok(Vector3 {
+            x: row.real(format!("{}_x", column_name_prefix).as_str())?,
+            y: row.real(format!("{}_y", column_name_prefix).as_str())?,
+            z: row.real(format!("{}_z", column_name_prefix).as_str())?,
+        })
+    }
+
+    fn body_from_row(row: &B::Row, entity: Entity) -> Result<CelestialBody> {
+        Ok(CelestialBody {
+            coords: Self::coordinates_from_row(row, "coordinate")?,
+            id: Self::id_from_row(row, "id")?,
+            local_direction: Self::coordinates_from_row(row, "local_direction")?,
+            local_speed: row.real("local_speed")?,
+            rotating_speed: row.real("rotating_speed")?,
+            gravity_center: Self::id_from_row(row, "gravity_center")?,
+            entity,
+            angular_speed: row.real("angular_speed")?,
+            owner: Self::id_from_row(row, "owner")?,
+        })
+    }
+
+    fn player_from_row(
+        row: &B::Row,
+        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+    ) -> Result<Entity> {
+        Ok(Entity::Player(Player::new(
+            Self::id_from_row(row, "id")?,
+            row.text("nickname")?,
+            infos_sender,
+        )))
+    }
+
+    fn ores_from_row(row: &B::Row) -> Result<Ores> {
+        let mut ores = Ores::new();
+        for ore in Ore::ALL {
+            let amount = row.integer(ore.column())?.unwrap_or_default() as u32;
+            if amount > 0 {
+                ores.insert(ore, amount);
+            }
+        }
+        Ok(ores)
+    }
+
+    fn structure_from_row(row: &B::Row) -> Result<Entity> {
+        let kind = row.text("kind")?;
+        let kind = StructureKind::from_str(&kind).ok_or(Error::DbInvalidStructureKind(kind))?;
+        Ok(Entity::Structure(Structure::new(
+            Self::id_from_row(row, "id")?,
+            kind,
+        )))
+    }
+
+    fn npc_from_row(row: &B::Row) -> Result<Entity> {
+        let kind = row.text("brain")?;
+        let kind = BrainKind::from_str(&kind).ok_or(Error::DbInvalidBrainKind(kind))?;
+        let mut npc = Npc::new(Self::id_from_row(row, "id")?, kind.brain());
+        npc.ship.hull = row.real("hull")?;
+        npc.ship.shield = row.real("shield")?;
+        Ok(Entity::Npc(npc))
+    }
+
+    fn chat_from_row(row: &B::Row) -> Result<(Id, ChatInfo)> {
+        let channel = match row.text("channel")?.as_str() {
+            "System" => ChatChannel::System,
+            _ => ChatChannel::Global,
+        };
+        Ok((
+            Self::id_from_row(row, "system_id")?,
+            ChatInfo {
+                channel,
+                sender: row.text("sender")?,
+                message: row.text("message")?,
+                timestamp: row.integer("timestamp")?.unwrap_or_default() as u64,
+            },
+        ))
+    }
+
+    fn value_from_id(id: Id) -> SqlValue {
+        if id == Id::MAX {
+            SqlValue::Null
+        } else {
+            SqlValue::Integer(id as i64)
+        }
+    }
+
+    fn value_from_string(value: &str) -> SqlValue {
+        SqlValue::Text(value.to_string())
+    }
+
+    fn row_from_body(body: &CelestialBody) -> Vec<SqlValue> {
+        vec![
+            Self::value_from_id(body.id),
+            Self::value_from_id(body.owner),
+            SqlValue::Real(body.coords.x),
+            SqlValue::Real(body.coords.y),
+            SqlValue::Real(body.coords.z),
+            SqlValue::Real(body.local_direction.x),
+            SqlValue::Real(body.local_direction.y),
+            SqlValue::Real(body.local_direction.z),
+            SqlValue::Real(body.local_speed),
+            SqlValue::Real(body.angular_speed),
+            SqlValue::Real(body.rotating_speed),
+            Self::value_from_id(body.gravity_center),
+        ]
+    }
+
+    /// The row of the table of `body`'s entity, along with the name of that
+    /// table.
+    fn row_from_entity(body: &CelestialBody) -> (&'static str, Vec<SqlValue>) {
+        let body_id = Self::value_from_id(body.id);
+        match &body.entity {
+            Entity::Player(player) => (
+                "Player",
+                vec![
+                    Self::value_from_id(player.id),
+                    Self::value_from_string(&player.nickname),
+                    body_id,
+                ],
+            ),
+            Entity::Star(star) => ("Star", vec![Self::value_from_id(star.id), body_id]),
+            Entity::Planet(planet) => ("Planet", vec![Self::value_from_id(planet.id), body_id]),
+            Entity::Moon(moon) => ("Moon", vec![Self::value_from_id(moon.id), body_id]),
+            Entity::Asteroid(asteroid) => {
+                ("Asteroid", vec![Self::value_from_id(asteroid.id), body_id])
+            }
+            Entity::Structure(structure) => (
+                "Structure",
+                vec![
+                    Self::value_from_id(structure.id),
+                    body_id,
+                    Self::value_from_string(structure.kind.as_str()),
+                ],
+            ),
+            Entity::Npc(npc) => (
+                "Npc",
+                vec![
+                    Self::value_from_id(npc.id),
+                    body_id,
+                    Self::value_from_string(npc.brain.kind().as_str()),
+                    SqlValue::Real(npc.ship.hull),
+                    SqlValue::Real(npc.ship.shield),
+                ],
+            ),
+        }
+    }
+
+    fn row_from_ores(id: Id, ores: &Ores) -> Vec<SqlValue> {
+        let mut row = vec![Self::value_from_id(id)];
+        for ore in Ore::ALL {
+            row.push(SqlValue::Integer(
+                ores.get(&ore).copied().unwrap_or_default() as i64,
+            ));
+        }
+        row
+    }
+
+    fn rows_from_inventory(player_id: Id, inventory: &Inventory) -> Vec<Vec<SqlValue>> {
+        inventory
+            .stacks()
+            .iter()
+            .map(|stack| {
+                vec![
+                    Self::value_from_id(player_id),
+                    Self::value_from_string(&serde_json::to_string(&stack.item).unwrap()),
+                    SqlValue::Integer(stack.amount as i64),
+                ]
+            })
+            .collect()
+    }
+
+    fn row_from_ship(player_id: Id, ship: &Ship) -> Vec<SqlValue> {
+        vec![
+            Self::value_from_id(player_id),
+            SqlValue::Real(ship.hull),
+            SqlValue::Real(ship.shield),
+        ]
+    }
+
+    fn rows_from_sensors(player_id: Id, sensors: &Sensors) -> Vec<Vec<SqlValue>> {
+        sensors
+            .discovered
+            .values()
+            .map(|info| {
+                vec![
+                    Self::value_from_id(player_id),
+                    Self::value_from_id(info.id),
+                    Self::value_from_string(&serde_json::to_string(info).unwrap()),
+                ]
+            })
+            .collect()
+    }
+
+    fn rows_from_systems(player_id: Id, sensors: &Sensors) -> Vec<Vec<SqlValue>> {
+        sensors
+            .systems
+            .iter()
+            .map(|(star, discovered_at)| {
+                vec![
+                    Self::value_from_id(player_id),
+                    Self::value_from_id(*star),
+                    SqlValue::Integer(*discovered_at as i64),
+                ]
+            })
+            .collect()
+    }
+
+    fn row_from_chat(entry: &ChatEntry) -> Vec<SqlValue> {
+        let channel = match entry.info.channel {
+            ChatChannel::System => "System",
+            _ => "Global",
+        };
+        vec![
+            Self::value_from_id(entry.id),
+            Self::value_from_string(channel),
+            Self::value_from_id(entry.system),
+            Self::value_from_string(&entry.info.sender),
+            Self::value_from_string(&entry.info.message),
+            SqlValue::Integer(entry.info.timestamp as i64),
+        ]
+    }
+
+    fn ores_upserts() -> Vec<(&'static str, &'static str)> {
+        Ore::ALL
+            .iter()
+            .map(|ore| (ore.column(), ore.column()))
+            .collect()
+    }
+
+    async fn select_by_id(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        id: Id,
+    ) -> Result<Vec<B::Row>> {
+        self.backend
+            .select(table_name, column_name, Self::value_from_id(id))
+            .await
+    }
+
+    async fn get_ores(&mut self, id: Id) -> Result<Ores> {
+        let results = self.select_by_id("AsteroidOre", "id", id).await?;
+        match results.first() {
+            Some(row) => Self::ores_from_row(row),
+            None => Ok(Ores::new()),
+        }
+    }
+
+    async fn get_sensors(&mut self, player_id: Id) -> Result<Sensors> {
+        let mut discovered = BTreeMap::new();
+        for row in self
+            .select_by_id("Discovery", "player_id", player_id)
+            .await?
+        {
+            let info = row.text("info")?;
+            let info: BodyInfo = serde_json::from_str(&info)
+                .map_err(|err| Error::DeserializeError(info.clone(), err))?;
+            discovered.insert(info.id, info);
+        }
+
+        let mut systems = BTreeMap::new();
+        for row in self
+            .select_by_id("DiscoveredSystem", "player_id", player_id)
+            .await?
+        {
+            let discovered_at = row.integer("discovered_at")?.unwrap_or_default();
+            systems.insert(Self::id_from_row(&row, "star_id")?, discovered_at as u64);
+        }
+
+        Ok(Sensors::from_discovered(discovered, systems))
+    }
+
+    async fn get_ship(&mut self, player_id: Id) -> Result<Ship> {
+        let results = self.select_by_id("Ship", "id", player_id).await?;
+        let mut ship = Ship::default();
+        if let Some(row) = results.first() {
+            ship.hull = row.real("hull")?;
+            ship.shield = row.real("shield")?;
+        }
+        Ok(ship)
+    }
+
+    async fn get_inventory(&mut self, player_id: Id) -> Result<Inventory> {
+        let mut inventory = Inventory::default();
+        for row in self
+            .select_by_id("Inventory", "player_id", player_id)
+            .await?
+        {
+            let item = row.text("item")?;
+            let item: Item = serde_json::from_str(&item)
+                .map_err(|err| Error::DeserializeError(item.clone(), err))?;
+            let amount = row.integer("amount")?.unwrap_or_default();
+            inventory.add(item, amount as u32);
+        }
+        Ok(inventory)
+    }
+
+    async fn ids_where(&mut self, column_name: &str, id: Id) -> Result<Vec<Id>> {
+        self.select_by_id("Body", column_name, id)
+            .await?
+            .iter()
+            .map(|row| Self::id_from_row(row, "id"))
+            .collect()
+    }
+
+    async fn get_stars(&mut self) -> Result<Vec<(Id, Vector3)>> {
+        let mut stars = Vec::new();
+        for row in self.backend.select_all("Star").await? {
+            let body_id = Self::id_from_row(&row, "body_id")?;
+            match self.select_by_id("Body", "id", body_id).await?.as_slice() {
+                [body] => stars.push((body_id, Self::coordinates_from_row(body, "coordinate")?)),
+                _ => return Err(Error::InconsistentRow(body_id)),
+            }
+        }
+        Ok(stars)
+    }
+
+    async fn get_body(&mut self, id: Id) -> Result<CelestialBody> {
+        let subtables = [
+            "Player",
+            "Asteroid",
+            "Star",
+            "Planet",
+            "Moon",
+            "Structure",
+            "Npc",
+        ];
+        let mut found = None;
+        for subtable in subtables {
+            let results = self.select_by_id(subtable, "body_id", id).await?;
+            if let Some(row) = results.into_iter().next() {
+                found = Some((subtable, row));
+                break;
+            }
+        }
+        let Some((subtable, row)) = found else {
+            return Err(Error::DbUuidNotFound(id));
+        };
+
+        let entity_id = Self::id_from_row(&row, "id")?;
+        let entity = match subtable {
+            "Player" => return Err(Error::BodyIsPlayer(id)),
+            "Asteroid" => {
+                let mut asteroid = Asteroid::new(entity_id);
+                asteroid.ores = self.get_ores(id).await?;
+                Entity::Asteroid(asteroid)
+            }
+            "Star" => Entity::Star(Star { id: entity_id }),
+            "Planet" => Entity::Planet(Planet { id: entity_id }),
+            "Moon" => Entity::Moon(Moon { id: entity_id }),
+            "Structure" => Self::structure_from_row(&row)?,
+            _ => Self::npc_from_row(&row)?,
+        };
+
+        match self.select_by_id("Body", "id", id).await?.as_slice() {
+            [row] => Self::body_from_row(row, entity),
+            _ => Err(Error::InconsistentRow(id)),
+        }
+    }
+
+    async fn get_player(
+        &mut self,
+        nickname: &str,
+        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+    ) -> Result<CelestialBody> {
+        let results = self
+            .backend
+            .select("Player", "nickname", Self::value_from_string(nickname))
+            .await?;
+        let player_row = match results.as_slice() {
+            [] => return Err(Error::DbLoadPlayerByNicknameNotFound),
+            [row] => row,
+            [row, ..] => return Err(Error::InconsistentRow(Self::id_from_row(row, "body_id")?)),
+        };
+
+        let body_id = Self::id_from_row(player_row, "body_id")?;
+        let results = self.select_by_id("Body", "id", body_id).await?;
+        let [body_row] = results.as_slice() else {
+            return Err(Error::InconsistentRow(body_id));
+        };
+
+        let mut player =
+            Self::body_from_row(body_row, Self::player_from_row(player_row, infos_sender)?)?;
+        if let Entity::Player(entity) = &mut player.entity {
+            entity.inventory = self.get_inventory(entity.id).await?;
+            entity.ship = self.get_ship(entity.id).await?;
+            *entity.sensors = self.get_sensors(entity.id).await?;
+        }
+        Ok(player)
+    }
+
+    async fn get_saved_at(&mut self, star: Id) -> Result<Option<u64>> {
+        let results = self.select_by_id("StarSystem", "id", star).await?;
+        match results.first() {
+            Some(row) => Ok(row.integer("saved_at")?.map(|saved_at| saved_at as u64)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_chat(&mut self, limit: usize) -> Result<Vec<(Id, ChatInfo)>> {
+        let rows = self.backend.select_last("Chat", "id", limit).await?;
+        rows.iter().rev().map(Self::chat_from_row).collect()
+    }
+
+    async fn get_all(&mut self) -> Result<Vec<CelestialBody>> {
+        let mut bodies = Vec::new();
+        for row in self.backend.select_all("Player").await? {
+            let (infos_sender, _) = tokio::sync::mpsc::channel(1);
+            bodies.push(
+                self.get_player(&row.text("nickname")?, infos_sender)
+                    .await?,
+            );
+        }
+
+        let mut ores = BTreeMap::new();
+        for row in self.backend.select_all("AsteroidOre").await? {
+            ores.insert(Self::id_from_row(&row, "id")?, Self::ores_from_row(&row)?);
+        }
+        let mut entities = BTreeMap::new();
+        for subtable in ["Asteroid", "Star", "Planet", "Moon", "Structure", "Npc"] {
+            for row in self.backend.select_all(subtable).await? {
+                let body_id = Self::id_from_row(&row, "body_id")?;
+                let entity_id = Self::id_from_row(&row, "id")?;
+                let entity = match subtable {
+                    "Asteroid" => {
+                        let mut asteroid = Asteroid::new(entity_id);
+                        asteroid.ores = ores.remove(&body_id).unwrap_or_default();
+                        Entity::Asteroid(asteroid)
+                    }
+                    "Star" => Entity::Star(Star { id: entity_id }),
+                    "Planet" => Entity::Planet(Planet { id: entity_id }),
+                    "Moon" => Entity::Moon(Moon { id: entity_id }),
+                    "Structure" => Self::structure_from_row(&row)?,
+                    _ => Self::npc_from_row(&row)?,
+                };
+                entities.insert(body_id, entity);
+            }
+        }
+
+        for row in self.backend.select_all("Body").await? {
+            if let Some(entity) = entities.remove(&Self::id_from_row(&row, "id")?) {
+                bodies.push(Self::body_from_row(&row, entity)?);
+            }
+        }
+        bodies.sort_by_key(|body| body.id);
+        Ok(bodies)
+    }
+
+    async fn dump_snapshot(snapshot: B) -> Result<Dump> {
+        let mut store = SqlStore::new(snapshot);
+        let dumped =
+            async { Ok((store.get_all().await?, store.get_chat(usize::MAX).await?)) }.await;
+        store.backend.discard_snapshot().await?;
+        dumped
+    }
+
+    /// Deletes the bodies, along with the rows referencing them, and the
+    /// chat, then writes `batch`, in one transaction.
+    async fn replace_all(&mut self, batch: SaveBatch<'_>) -> Result<()> {
+        let mut statements = Vec::new();
+        for table in ["Body", "Chat"] {
+            let mut delete = Statement::delete(table, "id");
+            for row in self.backend.select_all(table).await? {
+                delete.push(vec![row
+                    .integer("id")?
+                    .map_or(SqlValue::Null, SqlValue::Integer)]);
+            }
+            if !delete.is_empty() {
+                statements.push(delete);
+            }
+        }
+        statements.extend(Self::statements(&batch));
+        self.backend.execute(statements).await
+    }
+
+    async fn get_next_ids(&mut self) -> Result<NextIds> {
+        Ok(NextIds {
+            body: self.backend.max("Body", "id").await?.map_or(1, |id| id + 1),
+            player: self
+                .backend
+                .max("Player", "id")
+                .await?
+                .map_or(1, |id| id + 1),
+            chat: self.backend.max("Chat", "id").await?.map_or(1, |id| id + 1),
+            journal_checkpoint: self
+                .backend
+                .max("Journal", "sequence")
+                .await?
+                .unwrap_or_default(),
+            generation_seed: match self
+                .backend
+                .select("Galaxy", "id", SqlValue::Integer(1))
+                .await?
+                .first()
+            {
+                Some(row) => row.integer("generation_seed")?.map(|seed| seed as u64),
+                None => None,
+            },
+        })
+    }
+
+    /// The statements writing `batch`, parents before the rows referencing
+    /// them.
+    fn statements(batch: &SaveBatch) -> Vec<Statement> {
+        let mut body_insert = Statement::upsert(
+            "Body",
+            12,
+            vec![
+                ("owner", "owner"),
+                ("coordinate_x", "coordinate_x"),
+                ("coordinate_y", "coordinate_y"),
+                ("coordinate_z", "coordinate_z"),
+                ("local_direction_x", "local_direction_x"),
+                ("local_direction_y", "local_direction_y"),
+                ("local_direction_z", "local_direction_z"),
+                ("local_speed", "local_speed"),
+                ("angular_speed", "angular_speed"),
+                ("rotating_speed", "rotating_speed"),
+                ("gravity_center", "gravity_center"),
+            ],
+        );
+        let mut entity_inserts = BTreeMap::from([
+            (
+                "Player",
+                Statement::upsert(
+                    "Player",
+                    3,
+                    vec![("nickname", "nickname"), ("body_id", "body_id")],
+                ),
+            ),
+            (
+                "Star",
+                Statement::upsert("Star", 2, vec![("body_id", "body_id")]),
+            ),
+            (
+                "Planet",
+                Statement::upsert("Planet", 2, vec![("body_id", "body_id")]),
+            ),
+            (
+                "Moon",
+                Statement::upsert("Moon", 2, vec![("body_id", "body_id")]),
+            ),
+            (
+                "Asteroid",
+                Statement::upsert("Asteroid", 2, vec![("body_id", "body_id")]),
+            ),
+            (
+                "Structure",
+                Statement::upsert(
+                    "Structure",
+                    3,
+                    vec![("body_id", "body_id"), ("kind", "kind")],
+                ),
+            ),
+            (
+                "Npc",
+                Statement::upsert(
+                    "Npc",
+                    5,
+                    vec![
+                        ("body_id", "body_id"),
+                        ("brain", "brain"),
+                        ("hull", "hull"),
+                        ("shield", "shield"),
+                    ],
+                ),
+            ),
+        ]);
+        let mut asteroid_ore_insert =
+            Statement::upsert("AsteroidOre", 1 + Ore::ALL.len(), Self::ores_upserts());
+        let mut inventory_insert =
+            Statement::insert("Inventory", vec!["player_id", "item", "amount"]);
+        let mut ship_insert =
+            Statement::upsert("Ship", 3, vec![("hull", "hull"), ("shield", "shield")]);
+        let mut discovery_insert =
+            Statement::insert("Discovery", vec!["player_id", "body_id", "info"]);
+        let mut system_insert = Statement::insert(
+            "DiscoveredSystem",
+            vec!["player_id", "star_id", "discovered_at"],
+        );
+        let mut inventory_delete = Statement::delete("Inventory", "player_id");
+        let mut discovery_delete = Statement::delete("Discovery", "player_id");
+        let mut system_delete = Statement::delete("DiscoveredSystem", "player_id");
+
+        for body in batch
+            .bodies
+            .iter()
+            .sorted_by(|body1, body2| Ord::cmp(&body2.id, &body1.id))
+        {
+            body_insert.push(Self::row_from_body(body));
+            let (table, row) = Self::row_from_entity(body);
+            entity_inserts.get_mut(table).unwrap().push(row);
+            match &body.entity {
+                Entity::Asteroid(asteroid) => {
+                    asteroid_ore_insert.push(Self::row_from_ores(body.id, &asteroid.ores));
+                }
+                Entity::Player(player) => {
+                    inventory_delete.push(vec![Self::value_from_id(player.id)]);
+                    for row in Self::rows_from_inventory(player.id, &player.inventory) {
+                        inventory_insert.push(row);
+                    }
+                    ship_insert.push(Self::row_from_ship(player.id, &player.ship));
+                    discovery_delete.push(vec![Self::value_from_id(player.id)]);
+                    for row in Self::rows_from_sensors(player.id, &player.sensors) {
+                        discovery_insert.push(row);
+                    }
+                    system_delete.push(vec![Self::value_from_id(player.id)]);
+                    for row in Self::rows_from_systems(player.id, &player.sensors) {
+                        system_insert.push(row);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut asteroid_delete = Statement::delete("Asteroid", "body_id");
+        let mut asteroid_ore_delete = Statement::delete("AsteroidOre", "id");
+        let mut discovered_delete = Statement::delete("Discovery", "body_id");
+        let mut body_delete = Statement::delete("Body", "id");
+        for id in batch.removals {
+            let id = Self::value_from_id(*id);
+            asteroid_delete.push(vec![id.clone()]);
+            asteroid_ore_delete.push(vec![id.clone()]);
+            discovered_delete.push(vec![id.clone()]);
+            body_delete.push(vec![id]);
+        }
+
+        let star_system_insert = Statement::upsert("StarSystem", 2, vec![("saved_at", "saved_at")])
+            .with(
+                batch
+                    .systems
+                    .iter()
+                    .map(|star| {
+                        vec![
+                            Self::value_from_id(*star),
+                            SqlValue::Integer(batch.saved_at as i64),
+                        ]
+                    })
+                    .collect(),
+            );
+        let chat_insert = Statement::upsert("Chat", 6, vec![("message", "message")])
+            .with(batch.chat.iter().map(Self::row_from_chat).collect());
+        let journal_insert =
+            Statement::upsert("Journal", 2, vec![("sequence", "sequence")]).with(vec![vec![
+                SqlValue::Integer(1),
+                SqlValue::Integer(batch.journal_checkpoint as i64),
+            ]]);
+        let galaxy_insert =
+            Statement::upsert("Galaxy", 2, vec![("generation_seed", "generation_seed")]).with(
+                vec![vec![
+                    SqlValue::Integer(1),
+                    SqlValue::Integer(batch.generation_seed as i64),
+                ]],
+            );
+
+        let mut statements = vec![
+            inventory_delete,
+            discovery_delete,
+            system_delete,
+            body_insert,
+        ];
+        statements.extend(entity_inserts.into_values());
+        statements.extend([
+            asteroid_ore_insert,
+            inventory_insert,
+            ship_insert,
+            discovery_insert,
+            system_insert,
+            star_system_insert,
+            asteroid_delete,
+            asteroid_ore_delete,
+            discovered_delete,
+            body_delete,
+            chat_insert,
+            journal_insert,
+            galaxy_insert,
+        ]);
+        statements
+            .into_iter()
+            .filter(|statement| !statement.is_empty())
+            .collect()
+    }
+}
+
+impl<B: SqlBackend> GalaxyStore for SqlStore<B> {
+    fn next_ids(&mut self) -> BoxFuture<'_, Result<NextIds>> {
+        self.get_next_ids().boxed()
+    }
+
+    fn load_body(&mut self, id: Id) -> BoxFuture<'_, Result<CelestialBody>> {
+        self.get_body(id).boxed()
+    }
+
+    fn load_player<'a>(
+        &'a mut self,
+        nickname: &'a str,
+        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
+    ) -> BoxFuture<'a, Result<CelestialBody>> {
+        self.get_player(nickname, infos_sender).boxed()
+    }
+
+    fn load_rotatings(&mut self, id: Id) -> BoxFuture<'_, Result<Vec<Id>>> {
+        self.ids_where("gravity_center", id).boxed()
+    }
+
+    fn load_saved_at(&mut self, star: Id) -> BoxFuture<'_, Result<Option<u64>>> {
+        self.get_saved_at(star).boxed()
+    }
+
+    fn load_stars(&mut self) -> BoxFuture<'_, Result<Vec<(Id, Vector3)>>> {
+        self.get_stars().boxed()
+    }
+
+    fn load_ownings(&mut self, player_id: Id) -> BoxFuture<'_, Result<Vec<Id>>> {
+        self.ids_where("owner", player_id).boxed()
+    }
+
+    fn load_chat(&mut self, limit: usize) -> BoxFuture<'_, Result<Vec<(Id, ChatInfo)>>> {
+        self.get_chat(limit).boxed()
+    }
+
+    fn dump(&self) -> BoxFuture<'static, Result<Dump>> {
+        let snapshot = self.backend.snapshot();
+        async move { Self::dump_snapshot(snapshot.await?).await }.boxed()
+    }
+
+    fn save<'a>(&'a mut self, batch: SaveBatch<'a>) -> BoxFuture<'a, Result<()>> {
+        let statements = Self::statements(&batch);
+        self.backend.execute(statements).boxed()
+    }
+
+    fn replace<'a>(&'a mut self, batch: SaveBatch<'a>) -> BoxFuture<'a, Result<()>> {
+        self.replace_all(batch).boxed()
+    }
+
+    fn check(&mut self) -> BoxFuture<'_, Result<Vec<Orphan>>> {
+        self.backend.foreign_key_check().boxed()
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use sqlx::sqlite::{SqliteConnectOptions, SqliteRow};
+use sqlx::{Row, SqlitePool};
+
+use crate::error::Error;
+use crate::sql_database::{Orphan, SqlDatabase, SqlValue, Statement};
+use crate::store::sql::{Fields, SqlBackend, SqlStore};
+use crate::{Id, Result};
+
+/// The galaxy in a sqlite file, the `.sbdb` of an instance.
+pub type SqliteStore = SqlStore<SqlDatabase>;
+
+/// Snapshots taken so far, numbering the copies they are read from.
+static SNAPSHOTS: AtomicU32 = AtomicU32::new(0);
+
+impl Fields for SqliteRow {
+    fn real(&self, column: &str) -> Result<f64> {
+        self.try_get(column).map_err(Error::DbLoadError)
+    }
+
+    fn integer(&self, column: &str) -> Result<Option<i64>> {
+        self.try_get(column).map_err(Error::DbLoadError)
+    }
+
+    fn text(&self, column: &str) -> Result<String> {
+        self.try_get(column).map_err(Error::DbLoadError)
+    }
+}
+
+impl SqlBackend for SqlDatabase {
+    type Row = SqliteRow;
+
+    async fn select(
+        &mut self,
+        table_name: &str,
+        column_name: &str,
+        value: SqlValue,
+    ) -> Result<Vec<SqliteRow>> {
+        self.select_from_where_equals(table_name, column_name, &value)
+            .await
+    }
+
+    async fn select_all(&mut self, table_name: &str) -> Result<Vec<SqliteRow>> {
+        self.select_all_rows(table_name).await
+    }
+
+    async fn select_last(
+        &mut self,
+        table_name: &str,
+        order_column_name: &str,
+        limit: usize,
+    ) -> Result<Vec<SqliteRow>> {
+        self.select_last_rows(table_name, order_column_name, limit)
+            .await
+    }
+
+    async fn max(&mut self, table_name: &str, column_name: &str) -> Result<Option<Id>> {
+        self.max_in(table_name, column_name).await
+    }
+
+    async fn execute(&mut self, statements: Vec<Statement>) -> Result<()> {
+        self.execute_in_transaction(statements).await
+    }
+
+    async fn foreign_key_check(&mut self) -> Result<Vec<Orphan>> {
+        SqlDatabase::foreign_key_check(self).await
+    }
+
+    /// A copy of the file next to it: a read transaction held while the
+    /// whole galaxy is read would keep saves waiting.
+    fn snapshot(&self) -> impl Future<Output = Result<SqlDatabase>> + Send + 'static {
+        let pool = self.pool.clone();
+        async move {
+            let copy = format!(
+                "{}.{}.snapshot",
+                pool.connect_options().get_filename().display(),
+                SNAPSHOTS.fetch_add(1, Ordering::Relaxed)
+            );
+            // Left over by a copy that was never discarded.
+            let _ = fs::remove_file(&copy);
+            sqlx::query("VACUUM INTO ?")
+                .bind(&copy)
+                .execute(&pool)
+                .await
+                .map_err(|err| Error::DbCopyError(copy.clone(), err))?;
+
+            let options = SqliteConnectOptions::new().filename(&copy).read_only(true);
+            let pool = SqlitePool::connect_with(options)
+                .await
+                .map_err(|err| Error::DbOpenError(copy, err))?;
+            Ok(SqlDatabase { pool })
+        }
+    }
+
+    async fn discard_snapshot(self) -> Result<()> {
+        let copy = self.pool.connect_options().get_filename().to_path_buf();
+        self.pool.close().await;
+        fs::remove_file(copy).map_err(Error::DbFileCreationError)
+    }
+}
@@ -1,56 +1,86 @@
-use crate::error::Error;
+use crate::game::brain::BrainKind;
+use crate::game::celestial_body::CelestialBody;
 use crate::game::entity::asteroid::Asteroid;
 use crate::game::entity::moon::Moon;
+use crate::game::entity::npc::Npc;
 use crate::game::entity::planet::Planet;
 use crate::game::entity::player::Player;
 use crate::game::entity::star::Star;
+use crate::game::entity::structure::{Structure, StructureKind};
 use crate::game::entity::Entity;
 use crate::game::repr::Vector3;
-use crate::protocol::GameInfo;
-use crate::{game::celestial_body::CelestialBody, sql_database::SqlDatabase};
+use crate::game::sensors::Sensors;
+use crate::instance::DEFAULT_GENERATION_SEED;
+use crate::journal::Record;
+use crate::protocol::{ChatChannel, ChatInfo, GameInfo};
+use crate::store::{ChatEntry, GalaxyStore, SaveBatch};
 use crate::{Id, Result};
-use itertools::Itertools;
-use sqlx::sqlite::SqliteRow;
-use sqlx::Row;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{u32, vec};
 
+/// Milliseconds since the epoch.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 pub(crate) struct SyncedBody {
     pub(crate) body: CelestialBody,
+    // Changed since it was last saved.
+    pub(crate) dirty: bool,
 }
 
 impl SyncedBody {
     pub fn new(celestial: CelestialBody) -> SyncedBody {
-        SyncedBody { body: celestial }
+        SyncedBody {
+            body: celestial,
+            dirty: true,
+        }
+    }
+
+    /// A body just as the database has it.
+    pub fn loaded(celestial: CelestialBody) -> SyncedBody {
+        SyncedBody {
+            body: celestial,
+            dirty: false,
+        }
     }
 }
 
 pub struct SyncPool {
     pub(crate) synced_bodies: HashMap<Id, SyncedBody>,
-    pub(crate) database: SqlDatabase,
+    pub(crate) store: Box<dyn GalaxyStore>,
     pub(crate) body_next_id: Id,
     pub(crate) player_next_id: Id,
+    pub(crate) chat_next_id: Id,
+    pub(crate) pending_chat: Vec<ChatEntry>,
+    pub(crate) pending_removals: Vec<Id>,
+    /// Last journal batch covered by the saved state.
+    pub(crate) journal_checkpoint: u32,
+    /// Seed the systems of the galaxy are generated from.
+    pub(crate) generation_seed: u64,
+    /// Stars of the systems in memory, whose bodies each save brings up to
+    /// date.
+    pub(crate) systems: Vec<Id>,
 }
 
 impl SyncPool {
-    pub async fn new(mut database: SqlDatabase) -> Result<SyncPool> {
-        let maybe_next_id_in_body = database.max_in("Body", "id").await?;
-        let maybe_next_id_in_player = database.max_in("Player", "id").await?;
-        let next_id_in_body = if maybe_next_id_in_body.is_none() {
-            1
-        } else {
-            maybe_next_id_in_body.unwrap() + 1
-        };
-        let next_id_in_player = if maybe_next_id_in_player.is_none() {
-            1
-        } else {
-            maybe_next_id_in_player.unwrap() + 1
-        };
+    pub async fn new(mut store: Box<dyn GalaxyStore>) -> Result<SyncPool> {
+        let next_ids = store.next_ids().await?;
         Ok(SyncPool {
-            database,
+            store,
             synced_bodies: HashMap::new(),
-            body_next_id: next_id_in_body,
-            player_next_id: next_id_in_player,
+            body_next_id: next_ids.body,
+            player_next_id: next_ids.player,
+            chat_next_id: next_ids.chat,
+            pending_chat: Vec::new(),
+            pending_removals: Vec::new(),
+            journal_checkpoint: next_ids.journal_checkpoint,
+            generation_seed: next_ids.generation_seed.unwrap_or(DEFAULT_GENERATION_SEED),
+            systems: Vec::new(),
         })
     }
 
@@ -66,6 +96,12 @@ impl SyncPool {
         ret
     }
 
+    pub(crate) fn next_id_in_chat(&mut self) -> Id {
+        let ret = self.chat_next_id;
+        self.chat_next_id += 1;
+        ret
+    }
+
     pub fn new_asteroids(&mut self, n: usize) -> Vec<CelestialBody> {
         let mut asteroids = Vec::new();
 
@@ -80,7 +116,7 @@ impl SyncPool {
                 0f64,
                 0f64,
                 Id::MAX,
-                Entity::Asteroid(Asteroid { id: Id::MAX }),
+                Entity::Asteroid(Asteroid::new(Id::MAX)),
             );
             self.synced_bodies.insert(id, SyncedBody::new(body.clone()));
 
@@ -147,6 +183,46 @@ impl SyncPool {
         celestial
     }
 
+    pub fn new_structure(&mut self, kind: StructureKind) -> CelestialBody {
+        let id = self.next_id_in_body();
+        let celestial = CelestialBody::new(
+            id,
+            Id::MAX,
+            Vector3::default(),
+            Vector3::default(),
+            0f64,
+            0f64,
+            0f64,
+            Id::MAX,
+            Entity::Structure(Structure::new(id, kind)),
+        );
+
+        self.synced_bodies
+            .insert(celestial.id, SyncedBody::new(celestial.clone()));
+
+        celestial
+    }
+
+    pub fn new_npc(&mut self, kind: BrainKind) -> CelestialBody {
+        let id = self.next_id_in_body();
+        let celestial = CelestialBody::new(
+            id,
+            Id::MAX,
+            Vector3::default(),
+            Vector3::default(),
+            0f64,
+            0f64,
+            0f64,
+            Id::MAX,
+            Entity::Npc(Npc::new(id, kind.brain())),
+        );
+
+        self.synced_bodies
+            .insert(celestial.id, SyncedBody::new(celestial.clone()));
+
+        celestial
+    }
+
     pub fn new_player(
         &mut self,
         nickname: &str,
@@ -174,225 +250,31 @@ impl SyncPool {
         celestial
     }
 
-    fn float_from_row(row: &SqliteRow, column_name: &str) -> Result<f64> {
-        Ok(row
-            .try_get(column_name)
-            .map_err(|err| Error::DbLoadError(err))?)
-    }
-
-    fn int_from_row(row: &SqliteRow, column_name: &str) -> Result<u32> {
-        Ok(row
-            .try_get(column_name)
-            .map_err(|err| Error::DbLoadError(err))?)
-    }
-
-    fn string_from_row(row: &SqliteRow, column_name: &str) -> Result<String> {
-        Ok(row
-            .try_get(column_name)
-            .map_err(|err| Error::DbLoadError(err))?)
-    }
-
-    fn id_from_row(row: &SqliteRow, column_name: &str) -> Result<Id> {
-        Ok(Self::int_from_row(row, column_name)?)
-    }
-
-    fn coordinates_from_row(row: &SqliteRow, column_name_prefix: &str) -> Result<Vector3> {
-        Ok(Vector3 {
-            x: Self::float_from_row(row, format!("{}_x", column_name_prefix).as_str())?,
-            y: Self::float_from_row(row, format!("{}_y", column_name_prefix).as_str())?,
-            z: Self::float_from_row(row, format!("{}_z", column_name_prefix).as_str())?,
-        })
-    }
-
-    fn body_from_row(row: &SqliteRow, entity: Entity, from_join: bool) -> Result<CelestialBody> {
-        let id_column_name = if from_join { "body_id" } else { "id" };
-        Ok(CelestialBody {
-            coords: Self::coordinates_from_row(row, "coordinate")?,
-            id: Self::id_from_row(row, id_column_name)?,
-            local_direction: Self::coordinates_from_row(row, "local_direction")?,
-            local_speed: Self::float_from_row(row, "local_speed")?,
-            rotating_speed: Self::float_from_row(row, "rotating_speed")?,
-            gravity_center: Self::id_from_row(row, "gravity_center")?,
-            entity,
-            angular_speed: Self::float_from_row(row, "angular_speed")?,
-            owner: Self::id_from_row(row, "owner")?,
-        })
-    }
-
-    fn player_from_row(
-        row: &SqliteRow,
-        from_join: bool,
-        infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
-    ) -> Result<Entity> {
-        let id_column_name = if from_join { "player_id" } else { "id" };
-        Ok(Entity::Player(Player::new(
-            Self::id_from_row(row, id_column_name)?,
-            Self::string_from_row(row, "nickname")?,
-            infos_sender,
-        )))
-    }
-
-    fn asteroid_from_row(row: &SqliteRow) -> Result<Entity> {
-        Ok(Entity::Asteroid(Asteroid {
-            id: Self::id_from_row(row, "id")?,
-        }))
-    }
-
-    fn planet_from_row(row: &SqliteRow) -> Result<Entity> {
-        Ok(Entity::Planet(Planet {
-            id: Self::id_from_row(row, "id")?,
-        }))
-    }
-
-    fn moon_from_row(row: &SqliteRow) -> Result<Entity> {
-        Ok(Entity::Moon(Moon {
-            id: Self::id_from_row(row, "id")?,
-        }))
-    }
-
-    fn star_from_row(row: &SqliteRow) -> Result<Entity> {
-        Ok(Entity::Star(Star {
-            id: Self::id_from_row(row, "id")?,
-        }))
-    }
-
-    fn value_from_id(id: Id) -> String {
-        if id == Id::MAX {
-            "NULL".to_string()
-        } else {
-            format!("{}", id.to_string())
-        }
-    }
-
-    fn row_from_body(body: &CelestialBody) -> Vec<String> {
-        vec![
-            Self::value_from_id(body.id),
-            Self::value_from_id(body.owner),
-            body.coords.x.to_string(),
-            body.coords.y.to_string(),
-            body.coords.z.to_string(),
-            body.local_direction.x.to_string(),
-            body.local_direction.y.to_string(),
-            body.local_direction.z.to_string(),
-            body.local_speed.to_string(),
-            body.angular_speed.to_string(),
-            body.rotating_speed.to_string(),
-            Self::value_from_id(body.gravity_center),
-        ]
-    }
-
-    fn row_from_player(player_body: &CelestialBody) -> Vec<String> {
-        if let Entity::Player(player) = &player_body.entity {
-            vec![
-                Self::value_from_id(player.id),
-                format!("'{}'", player.nickname),
-                Self::value_from_id(player_body.id),
-            ]
-        } else {
-            unreachable!()
-        }
-    }
-
-    fn row_from_star(star_body: &CelestialBody) -> Vec<String> {
-        if let Entity::Star(star) = &star_body.entity {
-            vec![
-                Self::value_from_id(star.id),
-                Self::value_from_id(star_body.id),
-            ]
-        } else {
-            unreachable!()
-        }
-    }
-
-    fn row_from_planet(planet_body: &CelestialBody) -> Vec<String> {
-        if let Entity::Planet(planet) = &planet_body.entity {
-            vec![
-                Self::value_from_id(planet.id),
-                Self::value_from_id(planet_body.id),
-            ]
-        } else {
-            unreachable!()
-        }
-    }
-
-    fn row_from_moon(moon_body: &CelestialBody) -> Vec<String> {
-        if let Entity::Moon(moon) = &moon_body.entity {
-            vec![
-                Self::value_from_id(moon.id),
-                Self::value_from_id(moon_body.id),
-            ]
-        } else {
-            unreachable!()
+    pub fn push_chat(&mut self, system: Id, info: &ChatInfo) {
+        if let ChatChannel::Direct(_) = info.channel {
+            return;
         }
+        let id = self.next_id_in_chat();
+        self.pending_chat.push(ChatEntry {
+            id,
+            system,
+            info: info.clone(),
+        });
     }
 
-    fn row_from_asteroid(asteroid_body: &CelestialBody) -> Vec<String> {
-        if let Entity::Asteroid(asteroid) = &asteroid_body.entity {
-            vec![
-                Self::value_from_id(asteroid.id),
-                Self::value_from_id(asteroid_body.id),
-            ]
-        } else {
-            unreachable!()
-        }
+    pub async fn get_chat_history(&mut self, limit: usize) -> Result<Vec<(Id, ChatInfo)>> {
+        self.store.load_chat(limit).await
     }
 
     pub async fn get_body(&mut self, id: Id) -> Result<CelestialBody> {
-        let maybe_player_body = self.synced_bodies.get(&id);
-        if maybe_player_body.is_some() {
-            let player_body = maybe_player_body.unwrap();
-            return Ok(player_body.body.clone());
-        }
-
-        let subtables = vec!["Player", "Asteroid", "Star", "Planet", "Moon"];
-        let mut good_results = Vec::new();
-        let mut good_table = "";
-        for subtable in subtables {
-            let results = self
-                .database
-                .select_from_where_equals(&subtable, "body_id", id.to_string().as_str())
-                .await?;
-            if results.len() > 0 {
-                good_table = subtable;
-                good_results = results;
-                break;
-            }
-        }
-
-        if good_results.is_empty() {
-            return Err(Error::DbUuidNotFound(id));
+        if let Some(synced_body) = self.synced_bodies.get(&id) {
+            return Ok(synced_body.body.clone());
         }
 
-        let row = good_results.first().unwrap();
-
-        let entity = if good_table == "Player" {
-            // Self::player_from_row(row, false, infos_sender)?
-            return Err(Error::Error);
-        } else if good_table == "Asteroid" {
-            Self::asteroid_from_row(row)?
-        } else if good_table == "Star" {
-            Self::star_from_row(row)?
-        } else if good_table == "Planet" {
-            Self::planet_from_row(row)?
-        } else if good_table == "Moon" {
-            Self::moon_from_row(row)?
-        } else {
-            todo!()
-        };
-
-        let results = self
-            .database
-            .select_from_where_equals("Body", "id", id.to_string().as_str())
-            .await?;
-
-        assert!(results.len() == 1);
-
-        let row = results.first().unwrap();
-
-        let synced_body = SyncedBody::new(Self::body_from_row(row, entity, false)?);
-        let id = synced_body.body.id;
-        self.synced_bodies.insert(id, synced_body);
-        Ok(self.synced_bodies.get(&id).unwrap().body.clone())
+        let body = self.store.load_body(id).await?;
+        self.synced_bodies
+            .insert(id, SyncedBody::loaded(body.clone()));
+        Ok(body)
     }
 
     pub async fn get_rotatings(&mut self, id: Id) -> Result<Vec<CelestialBody>> {
@@ -404,13 +286,13 @@ impl SyncPool {
                 break;
             }
             let next_id = ids.pop().unwrap();
-            let maybe_synced_body = self.synced_bodies.iter().find(|sb| sb.1.body.id == next_id);
+            let maybe_synced_body = self.synced_bodies.get(&next_id);
 
             let body = if let Some(synced_body) = maybe_synced_body {
-                if let Entity::Player(_) = synced_body.1.body.entity {
+                if let Entity::Player(_) = synced_body.body.entity {
                     continue;
                 }
-                synced_body.1.body.clone()
+                synced_body.body.clone()
             } else {
                 let body = self.get_body(next_id).await;
                 if body.is_err() {
@@ -420,15 +302,7 @@ impl SyncPool {
             };
 
             rotatings.push(body);
-
-            let results = self
-                .database
-                .select_from_where_equals("Body", "gravity_center", next_id.to_string().as_str())
-                .await?;
-
-            for row in results {
-                ids.push(row.try_get("id").unwrap());
-            }
+            ids.extend(self.store.load_rotatings(next_id).await?);
         }
 
         Ok(rotatings)
@@ -439,82 +313,156 @@ impl SyncPool {
         nickname: &str,
         infos_sender: tokio::sync::mpsc::Sender<GameInfo>,
     ) -> Result<CelestialBody> {
-        let maybe_player = self.synced_bodies.iter().find(|sb| {
-            if let Entity::Player(player) = &sb.1.body.entity {
-                player.nickname == nickname
-            } else {
-                false
+        let maybe_player = self.synced_bodies.values().find_map(|sb| {
+            if let Entity::Player(player) = &sb.body.entity {
+                if player.nickname == nickname {
+                    return Some((sb.body.id, player.id));
+                }
             }
+            None
         });
 
         let player = if maybe_player.is_none() {
-            let results = self
-                .database
-                .select_from_where_equals("Player", "nickname", nickname)
-                .await?;
-
-            if results.len() == 0 {
-                return Err(Error::DbLoadPlayerByNicknameNotFound);
+            let mut player = self.store.load_player(nickname, infos_sender).await?;
+            if let Entity::Player(entity) = &mut player.entity {
+                entity.ownings = self.get_ownings(entity.id).await?;
             }
 
-            assert!(results.len() == 1);
-
-            let player_row = results.first().unwrap();
-
-            let results = self
-                .database
-                .select_from_where_equals(
-                    "Body",
-                    "id",
-                    player_row.get::<u32, &str>("body_id").to_string().as_str(),
-                )
-                .await?;
-
-            assert!(results.len() == 1);
-
-            let body_row = results.first().unwrap();
-
-            let player = Self::body_from_row(
-                body_row,
-                Self::player_from_row(player_row, false, infos_sender)?,
-                false,
-            )?;
-
             self.synced_bodies
-                .insert(player.id, SyncedBody::new(player.clone()));
+                .insert(player.id, SyncedBody::loaded(player.clone()));
             player
         } else {
-            let synced_player = maybe_player.unwrap();
+            let (body_id, player_id) = maybe_player.unwrap();
+            let mut player = Player::new(player_id, nickname.to_string(), infos_sender);
+            player.ownings = self.get_ownings(player_id).await?;
+            let synced_player = &self.synced_bodies.get(&body_id).unwrap().body;
+            if let Entity::Player(synced_entity) = &synced_player.entity {
+                player.inventory = synced_entity.inventory.clone();
+                player.ship = synced_entity.ship.clone();
+                *player.sensors = Sensors::from_discovered(
+                    synced_entity.sensors.discovered.clone(),
+                    synced_entity.sensors.systems.clone(),
+                );
+            }
             CelestialBody {
-                angular_speed: synced_player.1.body.angular_speed,
-                coords: synced_player.1.body.coords.clone(),
-                gravity_center: synced_player.1.body.gravity_center,
-                id: synced_player.1.body.id,
-                local_direction: synced_player.1.body.local_direction.clone(),
-                local_speed: synced_player.1.body.local_speed,
-                owner: synced_player.1.body.owner,
-                rotating_speed: synced_player.1.body.rotating_speed,
-                entity: Entity::Player(Player::new(
-                    synced_player.1.body.id,
-                    nickname.to_string(),
-                    infos_sender,
-                )),
+                angular_speed: synced_player.angular_speed,
+                coords: synced_player.coords.clone(),
+                gravity_center: synced_player.gravity_center,
+                id: synced_player.id,
+                local_direction: synced_player.local_direction.clone(),
+                local_speed: synced_player.local_speed,
+                owner: synced_player.owner,
+                rotating_speed: synced_player.rotating_speed,
+                entity: Entity::Player(player),
             }
         };
 
         Ok(player)
     }
 
+    pub async fn get_ownings(&mut self, player_id: Id) -> Result<Vec<Id>> {
+        let mut ownings: Vec<Id> = self
+            .synced_bodies
+            .values()
+            .filter(|sb| sb.body.owner == player_id)
+            .map(|sb| sb.body.id)
+            .collect();
+
+        for id in self.store.load_ownings(player_id).await? {
+            let still_owned = self
+                .synced_bodies
+                .get(&id)
+                .is_none_or(|sb| sb.body.owner == player_id);
+            if still_owned && !ownings.contains(&id) {
+                ownings.push(id);
+            }
+        }
+
+        Ok(ownings)
+    }
+
+    /// Whether `body` differs from `synced` in anything the database stores.
+    fn changed(synced: &CelestialBody, body: &CelestialBody) -> bool {
+        let moved = synced.coords != body.coords
+            || synced.local_direction != body.local_direction
+            || synced.local_speed != body.local_speed
+            || synced.angular_speed != body.angular_speed
+            || synced.rotating_speed != body.rotating_speed
+            || synced.gravity_center != body.gravity_center
+            || synced.owner != body.owner;
+
+        moved
+            || match (&synced.entity, &body.entity) {
+                (Entity::Player(synced), Entity::Player(player)) => {
+                    synced.id != player.id
+                        || synced.nickname != player.nickname
+                        || synced.inventory != player.inventory
+                        || synced.ship.hull != player.ship.hull
+                        || synced.ship.shield != player.ship.shield
+                        || synced.sensors.discovered != player.sensors.discovered
+                        || synced.sensors.systems != player.sensors.systems
+                }
+                (Entity::Asteroid(synced), Entity::Asteroid(asteroid)) => synced != asteroid,
+                (Entity::Star(synced), Entity::Star(star)) => synced != star,
+                (Entity::Planet(synced), Entity::Planet(planet)) => synced != planet,
+                (Entity::Moon(synced), Entity::Moon(moon)) => synced != moon,
+                (Entity::Structure(synced), Entity::Structure(structure)) => synced != structure,
+                (Entity::Npc(synced), Entity::Npc(npc)) => {
+                    synced.id != npc.id
+                        || synced.brain.kind() != npc.brain.kind()
+                        || synced.ship.hull != npc.ship.hull
+                        || synced.ship.shield != npc.ship.shield
+                }
+                _ => true,
+            }
+    }
+
+    /// Keeps `body` to be saved, unless nothing it would save changed.
     pub fn sync_body(&mut self, body: &CelestialBody) {
         let maybe_synced_body = self.synced_bodies.get_mut(&body.id);
         if let Some(synced_body) = maybe_synced_body {
-            synced_body.body = body.clone();
+            if Self::changed(&synced_body.body, body) {
+                synced_body.body = body.clone();
+                synced_body.dirty = true;
+            }
         } else {
             self.synced_bodies
                 .insert(body.id, SyncedBody::new(body.clone()));
         }
     }
 
+    /// Forgets `id` and deletes it from the database on next save.
+    pub fn remove_body(&mut self, id: Id) {
+        self.synced_bodies.remove(&id);
+        self.pending_removals.push(id);
+    }
+
+    /// Forgets the bodies `ids` once saved, to load them again when needed.
+    pub fn forget(&mut self, ids: impl IntoIterator<Item = Id>) {
+        for id in ids {
+            if self.synced_bodies.get(&id).is_some_and(|sb| !sb.dirty) {
+                self.synced_bodies.remove(&id);
+            }
+        }
+    }
+
+    /// Applies the records journaled since the last save.
+    pub(crate) fn replay(&mut self, records: Vec<Record>) {
+        for record in records {
+            match record {
+                Record::Body(record) => {
+                    let body = record.into_body();
+                    self.body_next_id = self.body_next_id.max(body.id + 1);
+                    if let Entity::Player(player) = &body.entity {
+                        self.player_next_id = self.player_next_id.max(player.id + 1);
+                    }
+                    self.sync_body(&body);
+                }
+                Record::Removed(id) => self.remove_body(id),
+            }
+        }
+    }
+
     pub fn sync(&mut self, bodies: Vec<&CelestialBody>) {
         for body in bodies {
             self.sync_body(body);
@@ -522,94 +470,77 @@ impl SyncPool {
     }
 
     pub async fn get_gravity_centers(&mut self, id: Id) -> Result<Vec<Id>> {
-        let results = self
-            .database
-            .select_from_where_equals("Body", "gravity_center", id.to_string().as_str())
-            .await?;
-
-        let mut gravity_centers = Vec::new();
-        for row in results {
-            gravity_centers.push(row.try_get("id").unwrap());
-        }
-
-        Ok(gravity_centers)
+        self.store.load_rotatings(id).await
     }
 
+    /// Writes the bodies changed since the last save with their inventory, ship and
+    /// discoveries, along with removals, pending chat messages and the journal checkpoint,
+    /// in a single transaction, so a crash mid-save never leaves half of a trade or a mining
+    /// step on disk.
     pub(crate) async fn save(&mut self) -> Result<()> {
-        let mut body_insert = Vec::default();
-        let mut player_insert = Vec::default();
-        let mut star_insert = Vec::default();
-        let mut planet_insert = Vec::default();
-        let mut moon_insert = Vec::default();
-        let mut asteroid_insert = Vec::default();
-
-        for synced_body in self
-            .synced_bodies
-            .values_mut()
-            .sorted_by(|sb1, sb2| Ord::cmp(&sb2.body.id, &sb1.body.id))
-        {
-            body_insert.push(Self::row_from_body(&synced_body.body));
-            match &synced_body.body.entity {
-                Entity::Asteroid(_) => {
-                    asteroid_insert.push(Self::row_from_asteroid(&synced_body.body))
-                }
-                Entity::Star(_) => star_insert.push(Self::row_from_star(&synced_body.body)),
-                Entity::Player(_) => player_insert.push(Self::row_from_player(&synced_body.body)),
-                Entity::Planet(_) => planet_insert.push(Self::row_from_planet(&synced_body.body)),
-                Entity::Moon(_) => moon_insert.push(Self::row_from_moon(&synced_body.body)),
+        self.write(false).await
+    }
+
+    /// Same as `save`, in place of everything the store held.
+    pub(crate) async fn save_replacing(&mut self) -> Result<()> {
+        self.write(true).await
+    }
+
+    /// Writes the `bodies` of the systems of `stars` changed since the last
+    /// save, and when these systems were saved. Removals, chat and the
+    /// journal checkpoint wait for the next full save.
+    pub(crate) async fn save_systems(&mut self, stars: &[Id], bodies: &HashSet<Id>) -> Result<()> {
+        let batch = SaveBatch {
+            bodies: self
+                .synced_bodies
+                .values()
+                .filter(|sb| sb.dirty && bodies.contains(&sb.body.id))
+                .map(|sb| &sb.body)
+                .collect(),
+            removals: &[],
+            chat: &[],
+            journal_checkpoint: self.journal_checkpoint,
+            generation_seed: self.generation_seed,
+            systems: stars,
+            saved_at: now_millis(),
+        };
+        self.store.save(batch).await?;
+
+        for id in bodies {
+            if let Some(synced_body) = self.synced_bodies.get_mut(id) {
+                synced_body.dirty = false;
             }
         }
 
-        if !body_insert.is_empty() {
-            self.database
-                .insert_rows_into(
-                    "Body",
-                    body_insert,
-                    vec![
-                        // ("owner", "owner"),
-                        ("coordinate_x", "coordinate_x"),
-                        ("coordinate_y", "coordinate_y"),
-                        ("coordinate_z", "coordinate_z"),
-                        // ("local_direction_x", "local_direction_x"),
-                        // ("local_direction_y", "local_direction_y"),
-                        // ("local_direction_z", "local_direction_z"),
-                        // ("local_speed", "local_speed"),
-                        // ("angular_speed", "angular_speed"),
-                        // ("rotating_speed", "rotating_speed"),
-                        // ("gravity_center", "gravity_center"),
-                    ],
-                )
-                .await?;
-        }
-        if !player_insert.is_empty() {
-            self.database
-                .insert_rows_into(
-                    "Player",
-                    player_insert,
-                    vec![("nickname", "nickname"), ("body_id", "body_id")],
-                )
-                .await?;
-        }
-        if !star_insert.is_empty() {
-            self.database
-                .insert_rows_into("Star", star_insert, vec![("body_id", "body_id")])
-                .await?;
-        }
-        if !planet_insert.is_empty() {
-            self.database
-                .insert_rows_into("Planet", planet_insert, vec![("body_id", "body_id")])
-                .await?;
-        }
-        if !moon_insert.is_empty() {
-            self.database
-                .insert_rows_into("Moon", moon_insert, vec![("body_id", "body_id")])
-                .await?;
+        Ok(())
+    }
+
+    async fn write(&mut self, replace: bool) -> Result<()> {
+        let batch = SaveBatch {
+            bodies: self
+                .synced_bodies
+                .values()
+                .filter(|sb| sb.dirty)
+                .map(|sb| &sb.body)
+                .collect(),
+            removals: &self.pending_removals,
+            chat: &self.pending_chat,
+            journal_checkpoint: self.journal_checkpoint,
+            generation_seed: self.generation_seed,
+            systems: &self.systems,
+            saved_at: now_millis(),
+        };
+        if replace {
+            self.store.replace(batch).await?;
+        } else {
+            self.store.save(batch).await?;
         }
-        if !asteroid_insert.is_empty() {
-            self.database
-                .insert_rows_into("Asteroid", asteroid_insert, vec![("body_id", "body_id")])
-                .await?;
+
+        for synced_body in self.synced_bodies.values_mut() {
+            synced_body.dirty = false;
         }
+        self.pending_removals.clear();
+        self.pending_chat.clear();
 
         Ok(())
     }
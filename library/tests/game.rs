@@ -8,17 +8,38 @@ mod spacebuild_tests_game {
 
     use anyhow::anyhow;
     use common::trace;
+    use futures::{SinkExt, StreamExt};
     use futures_time::{future::FutureExt, time::Duration};
     use log::info;
     use spacebuild::{
         client::Client,
-        game::repr::Vector3,
+        error::Error,
+        game::{
+            combat::Weapon,
+            entity::Entity,
+            inventory::{Item, ItemStack, STARTING_CREDITS},
+            ownership::CLAIM_RANGE,
+            repr::Vector3,
+        },
         instance::Instance,
+        journal::EntityRecord,
         network::tls::{ClientPki, ServerPki},
-        protocol::GameInfo,
+        protocol::{
+            ChatChannel, ErrorKind, FireTarget, GameInfo, LoginResult, SpectateTarget, Trade,
+            TradeStatus, PROTOCOL_VERSION,
+        },
+        registry::{InstanceSummary, Registry, DEFAULT_INSTANCE, JOURNAL_PERIOD},
         server,
+        shard::{Peer, Region, ShardConfig},
+        snapshot::SnapshotInfo,
     };
-    use tokio::{net::TcpListener, sync::Mutex, time::sleep};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::Mutex,
+        time::sleep,
+    };
+    use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
     use uuid::Uuid;
 
     const SERVER_CERT: &[u8] = b"-----BEGIN CERTIFICATE-----
@@ -158,6 +179,44 @@ lBjhUjWT859gkyO6pYSTfndSpnWAdtQK9zsTYociBQ==
         Ok((instance, send_stop, game_thread, port))
     }
 
+    type RawSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn raw_login(port: u16, nickname: &str) -> anyhow::Result<RawSocket> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://localhost:{}", port))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        socket
+            .send(Message::text(format!(
+                "{{\"Login\":{{\"nickname\":\"{}\",\"version\":{}}}}}",
+                nickname, PROTOCOL_VERSION
+            )))
+            .await?;
+
+        match socket
+            .next()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await?
+        {
+            Some(Ok(Message::Text(_))) => Ok(socket),
+            other => Err(anyhow!("Unexpected login response: {:?}", other)),
+        }
+    }
+
+    async fn until_closed(socket: &mut RawSocket) -> anyhow::Result<()> {
+        loop {
+            match socket
+                .next()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await
+                .map_err(|_| anyhow!("Server did not close the connection"))?
+            {
+                None | Some(Err(_)) | Some(Ok(Message::Close(_))) => return Ok(()),
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+
     #[tokio::test]
     async fn case_01_connection() -> anyhow::Result<()> {
         let db_path = get_random_db_path();
@@ -711,4 +770,1294 @@ lBjhUjWT859gkyO6pYSTfndSpnWAdtQK9zsTYociBQ==
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn case_12_chat_global_and_direct() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut alice = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        alice
+            .login("alice")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut bob = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        bob.login("bob")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        alice
+            .chat(ChatChannel::Global, "hello everyone")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let chat = bob
+            .until_chat_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(ChatChannel::Global, chat.channel);
+        assert_eq!("alice", chat.sender);
+        assert_eq!("hello everyone", chat.message);
+
+        alice
+            .chat(ChatChannel::Direct("bob".to_string()), "hi bob")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let chat = bob
+            .until_chat_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(ChatChannel::Direct("bob".to_string()), chat.channel);
+        assert_eq!("hi bob", chat.message);
+
+        alice.terminate().await?;
+        bob.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_13_chat_rate_limit_and_validation() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut alice = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        alice
+            .login("alice")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut bob = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        bob.login("bob")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        alice
+            .chat(ChatChannel::Global, "x".repeat(1000).as_str())
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        alice
+            .chat(ChatChannel::Global, "bell\u{7}")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        for i in 0..10 {
+            alice
+                .chat(ChatChannel::Global, format!("spam {}", i).as_str())
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..5 {
+            let chat = bob
+                .until_chat_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            received.push(chat.message);
+        }
+
+        bob.chat(ChatChannel::Global, "after")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        loop {
+            let chat = bob
+                .until_chat_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            received.push(chat.message);
+            if received.last().unwrap() == "after" {
+                break;
+            }
+        }
+
+        assert_eq!(
+            vec!["spam 0", "spam 1", "spam 2", "spam 3", "spam 4", "after"],
+            received
+        );
+
+        alice.terminate().await?;
+        bob.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_14_invalid_actions_strike_out() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut socket = raw_login(port, "mallory").await?;
+
+        for payload in [
+            "not json at all",
+            "{\"ShipState\":{\"throttle_up\":true,\"direction\":[1e999,0,0]}}",
+            "{\"ShipState\":{\"throttle_up\":true,\"direction\":[null,0,0]}}",
+            "{\"Chat\":{\"channel\":\"Global\",\"message\":\"\"}}",
+            "{\"Chat\":{\"channel\":{\"Direct\":\"\\u0007\"},\"message\":\"hi\"}}",
+        ] {
+            socket.send(Message::text(payload)).await?;
+        }
+
+        until_closed(&mut socket).await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_15_zero_direction_keeps_coords_finite() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        for _ in 0..3 {
+            player
+                .move_in_space(Vector3::default())
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            let player_info = player
+                .until_player_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            assert!(player_info.coords.iter().all(|c| c.is_finite()));
+        }
+
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_16_oversized_message_disconnects() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut socket = raw_login(port, "mallory").await?;
+
+        let huge = format!(
+            "{{\"Chat\":{{\"channel\":\"Global\",\"message\":\"{}\"}}}}",
+            "x".repeat(1024 * 1024)
+        );
+        let _ = socket.send(Message::text(huge)).await;
+
+        until_closed(&mut socket).await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_17_flood_disconnects() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut socket = raw_login(port, "mallory").await?;
+
+        for _ in 0..200 {
+            let result = socket
+                .send(Message::text(
+                    "{\"ShipState\":{\"throttle_up\":true,\"direction\":[0,0,1]}}",
+                ))
+                .await;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        until_closed(&mut socket).await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_18_login_results() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert!(matches!(
+            player.login("bad\u{7}nick").await,
+            Err(spacebuild::error::Error::InvalidNickname)
+        ));
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://localhost:{}", port))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        socket
+            .send(Message::text("{\"Login\":{\"nickname\":\"old\"}}"))
+            .await?;
+        let response = socket
+            .next()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await?
+            .ok_or(anyhow!("No login response"))??;
+        let login_result: LoginResult = serde_json::from_str(response.to_text()?)?;
+        assert_eq!(
+            LoginResult::VersionMismatch {
+                server_version: PROTOCOL_VERSION
+            },
+            login_result
+        );
+
+        let mut socket = raw_login(port, "first").await?;
+
+        instance.lock().await.set_max_players(1);
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert!(matches!(
+            player.login("second").await,
+            Err(spacebuild::error::Error::ServerFull)
+        ));
+
+        socket.close(None).await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_19_error_info_after_login() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        player
+            .chat(ChatChannel::Direct("nobody".to_string()), "anyone?")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        loop {
+            let game_info = player
+                .next_game_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            if let GameInfo::Error(error_info) = game_info {
+                assert_eq!(ErrorKind::NotFound, error_info.kind);
+                break;
+            }
+        }
+
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_20_claim_refused() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let body_id = player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let (star_id, far_asteroid_id) = {
+            let instance = instance.lock().await;
+            let galaxy = instance.borrow_galaxy();
+            let player_coords = galaxy.borrow_body(body_id).unwrap().get_coords();
+            let bodies = galaxy.borrow_bodies();
+            let star_id = bodies
+                .iter()
+                .find(|body| matches!(body.borrow_entity(), Entity::Star(_)))
+                .unwrap()
+                .get_uuid();
+            let far_asteroid_id = bodies
+                .iter()
+                .find(|body| {
+                    matches!(body.borrow_entity(), Entity::Asteroid(_))
+                        && (body.get_coords() - player_coords).norm() > 2f64 * CLAIM_RANGE
+                })
+                .unwrap()
+                .get_uuid();
+            (star_id, far_asteroid_id)
+        };
+
+        for target in [star_id, far_asteroid_id, u32::MAX - 1] {
+            player
+                .claim(target)
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+
+            loop {
+                let game_info = player
+                    .next_game_info()
+                    .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                    .await??;
+                if let GameInfo::Error(error_info) = game_info {
+                    let expected = if target == u32::MAX - 1 {
+                        ErrorKind::NotFound
+                    } else {
+                        ErrorKind::Refused
+                    };
+                    assert_eq!(expected, error_info.kind);
+                    break;
+                }
+            }
+        }
+
+        {
+            let instance = instance.lock().await;
+            let galaxy = instance.borrow_galaxy();
+            assert_eq!(None, galaxy.borrow_body(star_id).unwrap().get_owner());
+            assert_eq!(
+                None,
+                galaxy.borrow_body(far_asteroid_id).unwrap().get_owner()
+            );
+        }
+
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_21_mine_refused() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let star_id = instance
+            .lock()
+            .await
+            .borrow_galaxy()
+            .borrow_bodies()
+            .iter()
+            .find(|body| matches!(body.borrow_entity(), Entity::Star(_)))
+            .unwrap()
+            .get_uuid();
+
+        player
+            .mine(star_id)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        loop {
+            let game_info = player
+                .next_game_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            match game_info {
+                GameInfo::Error(error_info) => {
+                    assert_eq!(ErrorKind::Refused, error_info.kind);
+                    break;
+                }
+                GameInfo::Mining(_) => panic!("Mining a star must be refused"),
+                _ => {}
+            }
+        }
+
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_22_trade_between_players() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut alice = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        alice
+            .login("alice")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut bob = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let bob_id = bob
+            .login("bob")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let gift = vec![ItemStack {
+            item: Item::Credits,
+            amount: 100,
+        }];
+
+        alice
+            .trade(Trade::Offer {
+                to: bob_id,
+                give: gift.clone(),
+                want: vec![],
+            })
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let offered = bob
+            .until_trade_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(TradeStatus::Offered, offered.status);
+        assert_eq!(gift, offered.give);
+
+        bob.trade(Trade::Accept { offer: offered.id })
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let inventory = bob
+            .until_inventory_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let credits = inventory
+            .items
+            .iter()
+            .find(|stack| stack.item == Item::Credits)
+            .unwrap();
+        assert_eq!(STARTING_CREDITS + 100, credits.amount);
+
+        loop {
+            let trade_info = alice
+                .until_trade_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            if trade_info.status == TradeStatus::Accepted {
+                break;
+            }
+        }
+
+        alice.terminate().await?;
+        bob.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_23_fire_refused() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let star_id = instance
+            .lock()
+            .await
+            .borrow_galaxy()
+            .borrow_bodies()
+            .iter()
+            .find(|body| matches!(body.borrow_entity(), Entity::Star(_)))
+            .unwrap()
+            .get_uuid();
+
+        player
+            .fire(Weapon::Laser, FireTarget::Body(star_id))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        player
+            .fire(Weapon::Laser, FireTarget::Direction([0f64, 0f64, 0f64]))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut kinds = Vec::new();
+        while kinds.len() < 2 {
+            let game_info = player
+                .next_game_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            match game_info {
+                GameInfo::Error(error_info) => kinds.push(error_info.kind),
+                GameInfo::Hit(_) => panic!("Firing at a star must be refused"),
+                _ => {}
+            }
+        }
+        assert_eq!(vec![ErrorKind::Refused, ErrorKind::InvalidAction], kinds);
+
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_24_request_map() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (_instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        loop {
+            let game_info = player
+                .next_game_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            if let GameInfo::BodiesInSystem(_) = game_info {
+                break;
+            }
+        }
+
+        player
+            .request_map()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let map = player
+            .until_map_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(1, map.systems.len());
+
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_25_spectate() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+
+        let (instance, send_stop, game_thread, port) = bootstrap(&db_path, false)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        let mut player = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let player_id = player
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        // Only players online can be followed.
+        let mut spectator = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert!(matches!(
+            spectator
+                .spectate(SpectateTarget::Player("nobody".to_string()), 3000f64)
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await?,
+            Err(Error::LoginRefused(_))
+        ));
+
+        let mut spectator = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        spectator
+            .spectate(SpectateTarget::Player("test".to_string()), 3000f64)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        {
+            let instance = instance.lock().await;
+            assert_eq!(1, instance.players_count());
+            assert_eq!(1, instance.spectators_count());
+        }
+
+        loop {
+            let game_info = spectator
+                .next_game_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            if let GameInfo::BodiesInSystem(bodies) = game_info {
+                if bodies.iter().any(|body| body.id == player_id) {
+                    break;
+                }
+            }
+        }
+
+        spectator
+            .chat(ChatChannel::Global, "hello")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        loop {
+            let game_info = spectator
+                .next_game_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+            if let GameInfo::Error(error_info) = game_info {
+                assert_eq!(ErrorKind::Refused, error_info.kind);
+                break;
+            }
+        }
+
+        spectator.terminate().await?;
+        player.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    const ADMIN_TOKEN: &str = "secret";
+
+    async fn admin_request(
+        port: u16,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+    ) -> anyhow::Result<(u16, String)> {
+        let mut stream = TcpStream::connect(format!("localhost:{}", port))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        stream
+            .write_all(
+                format!(
+                    "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    method, path, authorization
+                )
+                .as_bytes(),
+            )
+            .await?;
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .ok_or(anyhow!("Malformed response: {}", response))?
+            .parse()?;
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        Ok((status, body))
+    }
+
+    async fn bootstrap_registry(
+        mut registry: Registry,
+    ) -> anyhow::Result<(
+        Arc<Registry>,
+        crossbeam::channel::Sender<()>,
+        tokio::task::JoinHandle<spacebuild::Result<()>>,
+        u16,
+    )> {
+        let listener = TcpListener::bind("localhost:0")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let port = listener.local_addr()?.port();
+
+        registry.set_admin_token(ADMIN_TOKEN.to_string());
+        registry
+            .load_path(DEFAULT_INSTANCE, get_random_db_path().as_str())
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let registry = Arc::new(registry);
+
+        let (send_stop, recv_stop) = crossbeam::channel::bounded(1);
+        let registry_cln = Arc::clone(&registry);
+        let game_thread: tokio::task::JoinHandle<spacebuild::Result<()>> =
+            tokio::spawn(async move {
+                server::run(
+                    server::InstanceConfig::Registry(registry_cln),
+                    server::ServerConfig {
+                        tcp: server::TcpConfig::TcpListener(listener),
+                        pki: None,
+                    },
+                    recv_stop,
+                )
+                .await?;
+                Ok(())
+            });
+
+        Ok((registry, send_stop, game_thread, port))
+    }
+
+    #[tokio::test]
+    async fn case_26_named_instances() -> anyhow::Result<()> {
+        let data_dir = env::temp_dir().join(format!("space_build_tests_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir)?;
+
+        let (registry, send_stop, game_thread, port) =
+            bootstrap_registry(Registry::new(&data_dir)).await?;
+
+        let (status, _) = admin_request(port, "GET", "/admin/instances", None).await?;
+        assert_eq!(401, status);
+        let (status, _) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/../create",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(400, status);
+
+        let (status, body) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/beta/create",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(200, status);
+        let summaries: Vec<InstanceSummary> = serde_json::from_str(&body)?;
+        assert_eq!(
+            vec!["beta", DEFAULT_INSTANCE],
+            summaries
+                .iter()
+                .map(|summary| summary.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        let (status, _) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/beta/create",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(409, status);
+
+        let mut client = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login_to_instance("beta", "test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        {
+            let (beta, _) = registry.get("beta").await.unwrap();
+            assert_eq!(1, beta.lock().await.players_count());
+            let (default, _) = registry.get(DEFAULT_INSTANCE).await.unwrap();
+            assert_eq!(0, default.lock().await.players_count());
+        }
+
+        let mut unknown = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert!(unknown
+            .login_to_instance("gamma", "test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await?
+            .is_err());
+
+        client.terminate().await?;
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://localhost:{}/ws/beta", port))
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??;
+        socket
+            .send(Message::text(format!(
+                "{{\"Login\":{{\"nickname\":\"other\",\"version\":{}}}}}",
+                PROTOCOL_VERSION
+            )))
+            .await?;
+        match socket
+            .next()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await?
+        {
+            Some(Ok(Message::Text(text))) => assert!(text.contains("\"Ok\""), "{}", text),
+            other => return Err(anyhow!("Unexpected login response: {:?}", other)),
+        }
+
+        let (status, _) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/beta/unload",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(200, status);
+        assert!(data_dir.join("beta.sbdb").exists());
+        until_closed(&mut socket).await?;
+
+        let (status, body) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/beta/load",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(200, status);
+        assert!(body.contains("\"beta\""));
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+        assert!(registry.list().await.is_empty());
+
+        Ok(())
+    }
+
+    async fn set_shard(registry: &Registry, shard: ShardConfig) {
+        let (instance, _) = registry.get(DEFAULT_INSTANCE).await.unwrap();
+        instance.lock().await.set_shard(Some(shard));
+    }
+
+    async fn until_redirect(client: &mut Client, direction: Vector3) -> anyhow::Result<String> {
+        loop {
+            match client.next_game_info().await? {
+                GameInfo::Redirect(redirect) => return Ok(redirect.address),
+                // The server may already have closed the connection after
+                // redirecting, which is read next.
+                GameInfo::Player(_) => {
+                    let _ = client.move_in_space(direction).await;
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn player_body(instance: &Instance, nickname: &str) -> Option<([f64; 3], u32)> {
+        instance
+            .borrow_galaxy()
+            .borrow_bodies()
+            .into_iter()
+            .find_map(|body| match body.borrow_entity() {
+                Entity::Player(player) if player.borrow_nickname() == nickname => {
+                    let coords = body.get_coords();
+                    Some((
+                        [coords.x, coords.y, coords.z],
+                        player.borrow_inventory().amount(Item::Credits),
+                    ))
+                }
+                _ => None,
+            })
+    }
+
+    /// Both shards are `Registry`s of this test process rather than two
+    /// server processes, so that their instances can be inspected. Handoffs
+    /// still go through the HTTP endpoint and clients follow redirects over
+    /// their own connections, but nothing crosses an OS process boundary.
+    #[tokio::test]
+    async fn case_27_shard_handoff() -> anyhow::Result<()> {
+        let data_dir = env::temp_dir();
+        let (registry_a, send_stop_a, game_thread_a, port_a) =
+            bootstrap_registry(Registry::new(&data_dir)).await?;
+        let (registry_b, send_stop_b, game_thread_b, port_b) =
+            bootstrap_registry(Registry::new(&data_dir)).await?;
+        let address_a = format!("localhost:{}", port_a);
+        let address_b = format!("localhost:{}", port_b);
+
+        let mut client = Client::connect(address_a.as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let spawn = client
+            .until_player_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??
+            .coords;
+
+        // A owns everything below the boundary on x, B everything above.
+        let boundary = spawn[0] + 20f64;
+        let region_a = Region {
+            min: [f64::MIN; 3],
+            max: [boundary, f64::MAX, f64::MAX],
+        };
+        let region_b = Region {
+            min: [boundary, f64::MIN, f64::MIN],
+            max: [f64::MAX; 3],
+        };
+        set_shard(
+            &registry_a,
+            ShardConfig {
+                region: region_a.clone(),
+                peers: vec![Peer {
+                    address: address_b.clone(),
+                    region: region_b.clone(),
+                }],
+                secret: "shared".to_string(),
+            },
+        )
+        .await;
+        set_shard(
+            &registry_b,
+            ShardConfig {
+                region: region_b,
+                peers: vec![Peer {
+                    address: address_a.clone(),
+                    region: region_a,
+                }],
+                secret: "shared".to_string(),
+            },
+        )
+        .await;
+
+        let address = until_redirect(&mut client, Vector3::from(1, 0, 0))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(address_b, address);
+        drop(client);
+        let (instance_a, _) = registry_a.get(DEFAULT_INSTANCE).await.unwrap();
+        assert_eq!(0, instance_a.lock().await.players_count());
+
+        let mut stale = Client::connect(address_a.as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        match stale
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await?
+        {
+            Err(spacebuild::error::Error::PlayerHandedOff(address)) => {
+                assert_eq!(address_b, address)
+            }
+            other => return Err(anyhow!("Unexpected login on A: {:?}", other)),
+        }
+
+        let mut client = Client::connect(address_b.as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        {
+            let (instance_b, _) = registry_b.get(DEFAULT_INSTANCE).await.unwrap();
+            let (coords, credits) = player_body(&*instance_b.lock().await, "test").unwrap();
+            assert!(coords[0] >= boundary);
+            assert_eq!(STARTING_CREDITS, credits);
+        }
+
+        let address = until_redirect(&mut client, Vector3::from(-1, 0, 0))
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(address_a, address);
+        drop(client);
+
+        let mut client = Client::connect(address_a.as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        {
+            let (coords, _) = player_body(&*instance_a.lock().await, "test").unwrap();
+            assert!(coords[0] < boundary);
+        }
+        client.terminate().await?;
+
+        send_stop_a.send(())?;
+        send_stop_b.send(())?;
+        game_thread_a
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+        game_thread_b
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn case_28_crash_recovery() -> anyhow::Result<()> {
+        let db_path = get_random_db_path();
+        let listener = std::net::TcpListener::bind("localhost:0")?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        // The server gets a runtime of its own, shut down below without
+        // letting it save, as a crash would.
+        let server_runtime = tokio::runtime::Runtime::new()?;
+        let (_send_stop, recv_stop) = crossbeam::channel::bounded(1);
+        let path = db_path.clone();
+        server_runtime.spawn(async move {
+            server::run(
+                server::InstanceConfig::UserSqliteDb { path },
+                server::ServerConfig {
+                    tcp: server::TcpConfig::TcpListener(TcpListener::from_std(listener).unwrap()),
+                    pki: None,
+                },
+                recv_stop,
+            )
+            .await
+        });
+
+        let mut client = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        let spawn = client
+            .until_player_info()
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??
+            .coords;
+        let mut coords = spawn;
+        while coords[0] < spawn[0] + 100f64 {
+            client.move_in_space(Vector3::from(1, 0, 0)).await?;
+            coords = client
+                .until_player_info()
+                .timeout(Duration::from_secs(TIMEOUT_DURATION))
+                .await??
+                .coords;
+        }
+        sleep(JOURNAL_PERIOD * 2).await;
+
+        server_runtime.shutdown_background();
+        drop(client);
+
+        let mut instance = Instance::from_path(&db_path).await?;
+        instance.authenticate(&"test".to_string()).await?;
+        let (recovered, credits) = player_body(&instance, "test").unwrap();
+        assert!(recovered[0] >= coords[0]);
+        assert_eq!(STARTING_CREDITS, credits);
+        // The system generated for the player was recovered as well.
+        assert!(instance.borrow_galaxy().borrow_bodies().len() > 1);
+
+        Ok(())
+    }
+
+    async fn exported_nicknames(registry: &Registry) -> anyhow::Result<Vec<String>> {
+        let (instance, _) = registry.get(DEFAULT_INSTANCE).await.unwrap();
+        let export = instance.lock().await.export().await?;
+        Ok(export
+            .bodies
+            .into_iter()
+            .filter_map(|record| match record.entity {
+                EntityRecord::Player { nickname, .. } => Some(nickname),
+                _ => None,
+            })
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn case_29_snapshot_rollback() -> anyhow::Result<()> {
+        let data_dir = env::temp_dir().join(format!("space_build_tests_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir)?;
+
+        let (registry, send_stop, game_thread, port) =
+            bootstrap_registry(Registry::new(&data_dir)).await?;
+
+        let mut client = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+
+        // Taken while the player is connected.
+        let (status, body) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/default/snapshots",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(200, status);
+        let taken: Vec<SnapshotInfo> = serde_json::from_str(&body)?;
+        assert_eq!(1, taken.len());
+
+        let mut other = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        other
+            .login("other")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        assert_eq!(vec!["other", "test"], {
+            let mut nicknames = exported_nicknames(&registry).await?;
+            nicknames.sort();
+            nicknames
+        });
+
+        let (status, _) = admin_request(
+            port,
+            "POST",
+            "/admin/instances/default/rollback/42",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(404, status);
+        let (status, _) = admin_request(
+            port,
+            "POST",
+            &format!("/admin/instances/default/rollback/{}", taken[0].id),
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(200, status);
+        assert_eq!(vec!["test"], exported_nicknames(&registry).await?);
+
+        // The galaxy rolled back from was kept as well.
+        let (status, body) = admin_request(
+            port,
+            "GET",
+            "/admin/instances/default/snapshots",
+            Some(ADMIN_TOKEN),
+        )
+        .await?;
+        assert_eq!(200, status);
+        let snapshots: Vec<SnapshotInfo> = serde_json::from_str(&body)?;
+        assert_eq!(2, snapshots.len());
+        assert_eq!(taken[0], snapshots[0]);
+
+        let mut client = Client::connect(format!("localhost:{}", port).as_str(), None)
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client
+            .login("test")
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await??;
+        client.terminate().await?;
+
+        send_stop.send(())?;
+        game_thread
+            .timeout(Duration::from_secs(TIMEOUT_DURATION))
+            .await???;
+
+        Ok(())
+    }
 }
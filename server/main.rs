@@ -1,10 +1,16 @@
-use std::{env, io};
+use std::{env, io, sync::Arc, time::Duration};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use spacebuild::{
+    export::GalaxyExport,
+    game::chat::ChatConfig,
+    instance::Instance,
     network::tls::ServerPki,
+    registry::{InstanceSettings, Registry, DEFAULT_INSTANCE},
     server::{self, InstanceConfig, ServerConfig},
+    shard::ShardConfig,
+    snapshot::{RetentionPolicy, SnapshotConfig},
 };
 use tokio::task::JoinHandle;
 
@@ -25,6 +31,67 @@ struct Args {
     #[arg(short, long, default_value = "galaxy.sbdb")]
     instance: String,
 
+    #[arg(long, default_value_t = spacebuild::instance::DEFAULT_MAX_PLAYERS, value_name = "COUNT")]
+    max_players: usize,
+
+    #[arg(long, default_value_t = 50, value_name = "SIZE")]
+    chat_history: usize,
+
+    #[arg(long)]
+    persist_chat: bool,
+
+    #[arg(long, value_name = "SCRIPT_PATH")]
+    scripts: Option<String>,
+
+    /// Where named instances are created and loaded from
+    #[arg(long, default_value = ".", value_name = "DIR")]
+    data_dir: String,
+
+    /// Enables the /admin/ API for this bearer token
+    #[arg(long, value_name = "TOKEN")]
+    admin_token: Option<String>,
+
+    /// Also serves DIR/NAME.sbdb at /ws/NAME
+    #[arg(long, value_name = "NAME")]
+    load: Vec<String>,
+
+    /// JSON region and peers to hand players off to
+    #[arg(long, value_name = "CONFIG_PATH")]
+    shard: Option<String>,
+
+    /// Serves the default instance from this PostgreSQL database instead of
+    /// the instance file
+    #[arg(long, value_name = "URL")]
+    postgres: Option<String>,
+
+    /// Snapshots every instance this often, in DIR/snapshots
+    #[arg(long, value_name = "SECONDS")]
+    snapshot_period: Option<u64>,
+
+    /// Snapshots kept whatever their age
+    #[arg(long, default_value_t = RetentionPolicy::default().keep_last, value_name = "COUNT")]
+    keep_last: usize,
+
+    /// Latest hours for which the last snapshot is kept
+    #[arg(long, default_value_t = RetentionPolicy::default().keep_hourly, value_name = "COUNT")]
+    keep_hourly: usize,
+
+    /// Latest days for which the last snapshot is kept
+    #[arg(long, default_value_t = RetentionPolicy::default().keep_daily, value_name = "COUNT")]
+    keep_daily: usize,
+
+    /// Unloads star systems nobody looked at for this long
+    #[arg(long, default_value_t = spacebuild::instance::DEFAULT_SYSTEM_IDLE.as_secs(), value_name = "SECONDS")]
+    system_idle: u64,
+
+    /// Generates the systems of new galaxies from this seed
+    #[arg(long, value_name = "SEED")]
+    generation_seed: Option<u64>,
+
+    /// Reports rows of the instance file referencing missing ones, then exits
+    #[arg(long)]
+    check: bool,
+
     #[arg(long, default_value = "spacebuild::(.*)", value_name = "REGEX")]
     trace_filter: String,
 
@@ -34,14 +101,44 @@ struct Args {
         value_name = "TRACE|DEBUG|INFO|WARN|ERROR"
     )]
     trace_level: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Writes the galaxy of the instance to a JSON file, then exits
+    Export {
+        #[arg(value_name = "FILE")]
+        path: String,
+    },
+    /// Reads a galaxy from a JSON file into the instance, which must be
+    /// empty, then exits
+    Import {
+        #[arg(value_name = "FILE")]
+        path: String,
+    },
+}
+
+/// The default instance, from the database or else the file given.
+async fn open_instance(args: &Args) -> Result<Instance> {
+    Ok(match &args.postgres {
+        Some(url) => {
+            Registry::new(args.data_dir.as_str())
+                .open_postgres(DEFAULT_INSTANCE, url)
+                .await?
+        }
+        None => Instance::from_path(args.instance.as_str()).await?,
+    })
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    env::set_var("RUST_LOG", args.trace_level);
-    let pki = if let Some(tls) = args.tls {
+    env::set_var("RUST_LOG", &args.trace_level);
+    let pki = if let Some(tls) = args.tls.clone() {
         Some(ServerPki::Paths {
             cert: tls.first().unwrap().clone(),
             key: tls.last().unwrap().clone(),
@@ -50,7 +147,39 @@ async fn main() -> anyhow::Result<()> {
         None
     };
 
-    common::trace::init(Some(args.trace_filter));
+    common::trace::init(Some(args.trace_filter.clone()));
+    if args.check {
+        let orphans = open_instance(&args).await?.check().await?;
+        for orphan in &orphans {
+            println!("{}", orphan);
+        }
+        let checked = match args.postgres {
+            Some(_) => "The PostgreSQL galaxy",
+            None => args.instance.as_str(),
+        };
+        if !orphans.is_empty() {
+            bail!("{} inconsistent rows in {}", orphans.len(), checked);
+        }
+        println!("{} is consistent", checked);
+        return Ok(());
+    }
+    match &args.command {
+        Some(Command::Export { path }) => {
+            let export = open_instance(&args).await?.export().await?;
+            export.write(path)?;
+            println!("Exported {} bodies to {}", export.bodies.len(), path);
+            return Ok(());
+        }
+        Some(Command::Import { path }) => {
+            let export = GalaxyExport::read(path)?;
+            let bodies = export.bodies.len();
+            open_instance(&args).await?.import(export).await?;
+            println!("Imported {} bodies from {}", bodies, path);
+            return Ok(());
+        }
+        None => {}
+    }
+
     let (stop_on_input_send, stop_on_input_recv) = crossbeam::channel::bounded(1);
     tokio::spawn(async move {
         loop {
@@ -65,11 +194,43 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    let mut registry = Registry::new(args.data_dir.as_str());
+    registry.set_instance_settings(InstanceSettings {
+        chat_config: ChatConfig {
+            history_size: args.chat_history,
+            persist_history: args.persist_chat,
+        },
+        max_players: args.max_players,
+        scripts: args.scripts.clone(),
+        shard: args.shard.as_deref().map(ShardConfig::load).transpose()?,
+        snapshots: args.snapshot_period.map(|period| SnapshotConfig {
+            period: Duration::from_secs(period),
+            retention: RetentionPolicy {
+                keep_last: args.keep_last,
+                keep_hourly: args.keep_hourly,
+                keep_daily: args.keep_daily,
+            },
+        }),
+        system_idle: Duration::from_secs(args.system_idle),
+        generation_seed: args.generation_seed,
+    });
+    if let Some(token) = args.admin_token {
+        registry.set_admin_token(token);
+    }
+    if let Some(url) = &args.postgres {
+        registry.load_postgres(DEFAULT_INSTANCE, url).await?;
+    } else {
+        registry
+            .load_path(DEFAULT_INSTANCE, args.instance.as_str())
+            .await?;
+    }
+    for name in &args.load {
+        registry.load(name).await?;
+    }
+
     let server_hdl: JoinHandle<Result<()>> = tokio::spawn(async move {
         if let spacebuild::Result::Err(err) = server::run(
-            InstanceConfig::UserSqliteDb {
-                path: args.instance,
-            },
+            InstanceConfig::Registry(Arc::new(registry)),
             ServerConfig {
                 tcp: server::TcpConfig::Port(args.port),
                 pki,
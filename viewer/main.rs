@@ -12,15 +12,15 @@ use ratatui::{
     text::Text,
     widgets::{
         canvas::{Canvas, Circle, Points},
-        Block, Borders, Cell, HighlightSpacing, Row, Scrollbar, ScrollbarOrientation,
-        ScrollbarState, Table, TableState,
+        Block, Borders, Cell, HighlightSpacing, List, ListItem, Paragraph, Row, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Table, TableState,
     },
     DefaultTerminal, Frame,
 };
 use spacebuild::{
     client::Client,
     network::tls::ClientPki,
-    protocol::{BodyInfo, GameInfo},
+    protocol::{BodyInfo, ChatChannel, ChatInfo, GameInfo, SpectateTarget, SystemInfo},
     Id,
 };
 use std::{collections::HashMap, time::Duration};
@@ -40,6 +40,20 @@ struct Args {
         num_args(0..=1)
     )]
     tls: Option<Option<String>>,
+
+    #[arg(short, long, value_name = "NICKNAME")]
+    follow: Option<String>,
+
+    #[arg(long,
+        num_args = 3,
+        value_names = ["X", "Y", "Z"],
+        allow_negative_numbers = true,
+        conflicts_with = "follow",
+    )]
+    point: Option<Vec<f64>>,
+
+    #[arg(long, default_value_t = 5000f64, value_name = "RANGE")]
+    range: f64,
 }
 
 #[tokio::main]
@@ -59,8 +73,13 @@ async fn main() -> Result<()> {
     println!("Connecting to {}:{}", args.host, args.port);
     let mut client = Client::connect(format!("{}:{}", args.host, args.port).as_str(), pki).await?;
 
-    println!("Logging in as observer");
-    client.login("observer").await?;
+    let follow = match (args.follow, args.point) {
+        (Some(nickname), _) => SpectateTarget::Player(nickname),
+        (None, Some(point)) => SpectateTarget::Point([point[0], point[1], point[2]]),
+        (None, None) => SpectateTarget::Point([0f64; 3]),
+    };
+    println!("Spectating {:?}", follow);
+    client.spectate(follow, args.range).await?;
 
     print!("Running app");
     let terminal = ratatui::init();
@@ -80,10 +99,14 @@ struct App {
     draw_zoom: f64,
     draw_area: Rect,
     offset: (f64, f64),
+    chat_log: Vec<ChatInfo>,
+    galaxy_map: Vec<SystemInfo>,
+    map_requested: bool,
 }
 
 impl App {
     const FRAMES_PER_SECOND: f32 = 60.0;
+    const CHAT_LOG_SIZE: usize = 100;
 
     pub async fn run(mut self, mut terminal: DefaultTerminal, mut client: Client) -> Result<()> {
         let period = Duration::from_secs_f32(1.0 / Self::FRAMES_PER_SECOND);
@@ -93,6 +116,7 @@ impl App {
         std::io::stdout()
             .execute(crossterm::event::EnableMouseCapture)
             .unwrap();
+        self.map_requested = true;
 
         while !self.should_quit {
             tokio::select! {
@@ -101,6 +125,10 @@ impl App {
                 },
                 Some(Ok(event)) = events.next() => {
                     self.handle_event(&event);
+                    if self.map_requested {
+                        client.request_map().await?;
+                        self.map_requested = false;
+                    }
                 },
                 Ok(game_info) = client.next_game_info() => {
                     match game_info {
@@ -115,6 +143,15 @@ impl App {
                                 self.celestials.insert(body.id, body);
                             }
                         },
+                        GameInfo::Map(map) => {
+                            self.galaxy_map = map.systems;
+                        },
+                        GameInfo::Chat(chat) => {
+                            self.chat_log.push(chat);
+                            if self.chat_log.len() > Self::CHAT_LOG_SIZE {
+                                self.chat_log.remove(0);
+                            }
+                        },
                         _ => {}
                     }
                 }
@@ -129,8 +166,14 @@ impl App {
             .constraints([Constraint::Ratio(2, 10), Constraint::Min(0)])
             .split(f.area());
 
-        let bandeau = Block::default().title("Server info").borders(Borders::ALL);
-        f.render_widget(bandeau, chunks[0]);
+        let top_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Ratio(4, 10), Constraint::Min(0)])
+            .split(chunks[0]);
+
+        self.draw_map(f, top_chunks[0]);
+
+        self.draw_chat(f, top_chunks[1]);
 
         let main_chunks = Layout::default()
             .direction(Direction::Horizontal)
@@ -140,7 +183,7 @@ impl App {
         self.list_area = main_chunks[1];
         self.draw_area = main_chunks[0];
 
-        let header = ["Id", "Type", "X", "Y", "Z"]
+        let header = ["Id", "Type", "Owner", "X", "Y", "Z"]
             .into_iter()
             .map(Cell::from)
             .collect::<Row>()
@@ -152,7 +195,18 @@ impl App {
             };
             let mut cells = vec![];
             cells.push(Cell::from(Text::from(format!("{}", data.0))));
-            cells.push(Cell::from(Text::from(data.1.element_type.clone())));
+            let element_type = if data.1.stale {
+                format!("{} (stale)", data.1.element_type)
+            } else {
+                data.1.element_type.clone()
+            };
+            cells.push(Cell::from(Text::from(element_type)));
+            cells.push(Cell::from(Text::from(
+                data.1
+                    .owner
+                    .map(|owner| format!("{}", owner))
+                    .unwrap_or_default(),
+            )));
             cells.push(Cell::from(Text::from(format!(
                 "{}",
                 data.1.coords[0] as i32
@@ -173,6 +227,7 @@ impl App {
             [
                 Constraint::Min(5),
                 Constraint::Min(12),
+                Constraint::Min(6),
                 Constraint::Min(8),
                 Constraint::Min(8),
                 Constraint::Min(8),
@@ -273,6 +328,15 @@ impl App {
                                     color: Color::Red,
                                 });
                             }
+                            "Structure" => {
+                                ctx.layer();
+                                ctx.draw(&Circle {
+                                    x: coords[0],
+                                    y: coords[2],
+                                    radius: 5.,
+                                    color: Color::Magenta,
+                                });
+                            }
                             "Player" => {
                                 ctx.layer();
                                 ctx.draw(&Circle {
@@ -282,6 +346,15 @@ impl App {
                                     color: Color::Green,
                                 });
                             }
+                            "Npc" => {
+                                ctx.layer();
+                                ctx.draw(&Circle {
+                                    x: coords[0],
+                                    y: coords[2],
+                                    radius: 2.,
+                                    color: Color::Cyan,
+                                });
+                            }
                             _ => {}
                         }
                     }
@@ -291,9 +364,100 @@ impl App {
         }
     }
 
+    fn draw_map(&self, f: &mut Frame, area: Rect) {
+        let block = Block::default()
+            .title("Galaxy map (m to refresh)")
+            .borders(Borders::ALL);
+
+        if self.galaxy_map.is_empty() {
+            f.render_widget(Paragraph::new("No system discovered").block(block), area);
+            return;
+        }
+
+        let (mut x_bounds, mut y_bounds) = ([f64::MAX, f64::MIN], [f64::MAX, f64::MIN]);
+        for system in &self.galaxy_map {
+            x_bounds = [
+                x_bounds[0].min(system.coords[0]),
+                x_bounds[1].max(system.coords[0]),
+            ];
+            y_bounds = [
+                y_bounds[0].min(system.coords[2]),
+                y_bounds[1].max(system.coords[2]),
+            ];
+        }
+        let margin = ((x_bounds[1] - x_bounds[0]).max(y_bounds[1] - y_bounds[0]) * 0.1).max(1000.);
+        x_bounds = [x_bounds[0] - margin, x_bounds[1] + margin];
+        y_bounds = [y_bounds[0] - margin, y_bounds[1] + margin];
+
+        let systems = self.galaxy_map.clone();
+        let map_canvas = Canvas::default()
+            .block(block)
+            .x_bounds(x_bounds)
+            .y_bounds(y_bounds)
+            .paint(move |ctx| {
+                for system in &systems {
+                    ctx.draw(&Points {
+                        coords: &[(system.coords[0], system.coords[2])],
+                        color: Color::White,
+                    });
+                    ctx.print(
+                        system.coords[0],
+                        system.coords[2],
+                        format!(
+                            " {} ({}p {}m {}a {}s)",
+                            system.star,
+                            system.planets,
+                            system.moons,
+                            system.asteroids,
+                            system.structures
+                        ),
+                    );
+                }
+            });
+
+        f.render_widget(map_canvas, area);
+    }
+
+    fn draw_chat(&self, f: &mut Frame, area: Rect) {
+        let chat_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(3)])
+            .split(area);
+
+        let visible = chat_chunks[0].height.saturating_sub(2) as usize;
+        let items = self
+            .chat_log
+            .iter()
+            .skip(self.chat_log.len().saturating_sub(visible))
+            .map(|chat| {
+                let (channel, color) = match &chat.channel {
+                    ChatChannel::Global => ("global".to_string(), Color::White),
+                    ChatChannel::System => ("system".to_string(), Color::Cyan),
+                    ChatChannel::Direct(to) => (format!("to {}", to), Color::Magenta),
+                };
+                ListItem::new(format!("[{}] {}: {}", channel, chat.sender, chat.message))
+                    .style(Style::new().fg(color))
+            })
+            .collect::<Vec<_>>();
+
+        f.render_widget(
+            List::new(items).block(Block::default().title("Chat").borders(Borders::ALL)),
+            chat_chunks[0],
+        );
+
+        f.render_widget(
+            Paragraph::new("Spectating, read-only")
+                .block(Block::default().title("Status").borders(Borders::ALL)),
+            chat_chunks[1],
+        );
+    }
+
     fn handle_event(&mut self, event: &Event) {
         match event {
             Event::Key(key_event) => match key_event.code {
+                KeyCode::Char('m') => {
+                    self.map_requested = true;
+                }
                 KeyCode::Char('q') => {
                     self.should_quit = true;
                 }